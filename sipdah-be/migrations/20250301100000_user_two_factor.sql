@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE user_two_factor
+(
+    user_id    BINARY(16) PRIMARY KEY,
+    secret     VARCHAR(64) NOT NULL,
+    enabled_at DATETIME    NULL,
+    created_at DATETIME    NOT NULL,
+    updated_at DATETIME    NOT NULL,
+
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
+
+CREATE TABLE user_recovery_code
+(
+    id         BINARY(16) PRIMARY KEY,
+    user_id    BINARY(16)  NOT NULL,
+    code_hash  VARCHAR(64) NOT NULL,
+    used_at    DATETIME    NULL,
+    created_at DATETIME    NOT NULL,
+
+    UNIQUE (user_id, code_hash),
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE user
+    ADD COLUMN email_verified_at DATETIME NULL AFTER phone_number;
+
+-- Accounts created before verification existed are trusted as they are
+UPDATE user
+SET email_verified_at = created_at
+WHERE email_verified_at IS NULL;
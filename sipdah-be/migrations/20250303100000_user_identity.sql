@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE user_identity
+(
+    id         BINARY(16) PRIMARY KEY,
+    user_id    BINARY(16)   NOT NULL,
+    provider   VARCHAR(64)  NOT NULL,
+    subject    VARCHAR(255) NOT NULL,
+    email      VARCHAR(255) NULL,
+    created_at DATETIME     NOT NULL,
+
+    UNIQUE (provider, subject),
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
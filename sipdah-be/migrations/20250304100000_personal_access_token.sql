@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE personal_access_token
+(
+    id           BINARY(16) PRIMARY KEY,
+    user_id      BINARY(16)   NOT NULL,
+    name         VARCHAR(64)  NOT NULL,
+    token_hash   VARCHAR(64)  NOT NULL UNIQUE,
+    scopes       VARCHAR(255) NOT NULL,
+    expires_at   DATETIME     NOT NULL,
+    last_used_at DATETIME     NULL,
+    created_at   DATETIME     NOT NULL,
+
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
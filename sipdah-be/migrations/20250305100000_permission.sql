@@ -0,0 +1,33 @@
+-- Add migration script here
+CREATE TABLE permission
+(
+    id          BINARY(16) PRIMARY KEY,
+    name        VARCHAR(64)  NOT NULL UNIQUE,
+    description VARCHAR(255) NOT NULL,
+    created_at  DATETIME     NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE role_permission
+(
+    role_id       BINARY(16) NOT NULL,
+    permission_id BINARY(16) NOT NULL,
+    granted_at    DATETIME   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (role_id, permission_id),
+    FOREIGN KEY (role_id) REFERENCES role (id) ON DELETE CASCADE,
+    FOREIGN KEY (permission_id) REFERENCES permission (id) ON DELETE CASCADE
+);
+
+INSERT INTO permission (id, name, description) VALUES
+                                                   (UUID_TO_BIN(UUID()), 'project:create', 'Create projects'),
+                                                   (UUID_TO_BIN(UUID()), 'project:manage', 'Manage any project'),
+                                                   (UUID_TO_BIN(UUID()), 'user:manage', 'Manage user accounts'),
+                                                   (UUID_TO_BIN(UUID()), 'role:manage', 'Create roles and change their permissions'),
+                                                   (UUID_TO_BIN(UUID()), 'role:assign', 'Assign roles to users');
+
+-- Administrators keep every capability they had before permissions existed
+INSERT INTO role_permission (role_id, permission_id)
+SELECT r.id, p.id
+FROM role r
+         CROSS JOIN permission p
+WHERE r.name IN ('ADMIN', 'SUPER_ADMIN');
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- No foreign keys, entries must outlive the accounts they mention
+CREATE TABLE audit_log
+(
+    id         BINARY(16) PRIMARY KEY,
+    actor_id   BINARY(16)   NOT NULL,
+    user_id    BINARY(16)   NOT NULL,
+    session_id VARCHAR(36)  NULL,
+    action     VARCHAR(255) NOT NULL,
+    created_at DATETIME     NOT NULL,
+
+    INDEX idx_audit_log_actor_id (actor_id, created_at),
+    INDEX idx_audit_log_user_id (user_id, created_at)
+);
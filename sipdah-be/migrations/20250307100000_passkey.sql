@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE passkey
+(
+    id            BINARY(16) PRIMARY KEY,
+    user_id       BINARY(16)   NOT NULL,
+    name          VARCHAR(64)  NOT NULL,
+    credential_id VARCHAR(255) NOT NULL UNIQUE,
+    credential    TEXT         NOT NULL,
+    sign_count    INT UNSIGNED NOT NULL,
+    transports    VARCHAR(255) NULL,
+    last_used_at  DATETIME     NULL,
+    created_at    DATETIME     NOT NULL,
+
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- `user_id` stays empty for failed attempts against unknown emails
+CREATE TABLE login_event
+(
+    id         BINARY(16) PRIMARY KEY,
+    user_id    BINARY(16)  NULL,
+    email      VARCHAR(64) NOT NULL,
+    kind       VARCHAR(16) NOT NULL,
+    outcome    VARCHAR(16) NOT NULL,
+    ip_address VARCHAR(45) NULL,
+    user_agent TEXT        NULL,
+    created_at DATETIME    NOT NULL,
+
+    INDEX idx_login_event_user_id (user_id, created_at),
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE file
+(
+    id         BINARY(16) PRIMARY KEY,
+    owner_id   BINARY(16)      NULL,
+    category   VARCHAR(36)     NOT NULL,
+    file_name  VARCHAR(255)    NOT NULL,
+    file_type  VARCHAR(255)    NOT NULL,
+    file_size  BIGINT UNSIGNED NOT NULL,
+    location   VARCHAR(255)    NOT NULL,
+    created_at DATETIME        NOT NULL,
+
+    FOREIGN KEY (owner_id) REFERENCES user (id) ON DELETE SET NULL
+);
+
+ALTER TABLE user
+    ADD FOREIGN KEY (photo_id) REFERENCES file (id) ON DELETE SET NULL;
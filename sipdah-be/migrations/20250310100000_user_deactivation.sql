@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE user
+    ADD COLUMN deactivated_at DATETIME NULL AFTER updated_at,
+    ADD COLUMN anonymized_at  DATETIME NULL AFTER deleted_at;
+
+CREATE INDEX user_deleted_at_idx ON user (deleted_at);
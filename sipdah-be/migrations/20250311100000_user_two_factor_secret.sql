@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Secrets are stored encrypted, which takes more room than the plain base32 value
+ALTER TABLE user_two_factor
+    MODIFY COLUMN secret VARCHAR(255) NOT NULL;
@@ -1,5 +1,107 @@
 use chrono::Duration;
+use serde::Deserialize;
 use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtKey {
+    pub kid: String,
+    // RS256 or EdDSA
+    pub algorithm: String,
+    pub public_key_path: String,
+    // Keys without a private key only verify tokens, e.g. a retired key during rotation
+    pub private_key_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcProvider {
+    // Used in the login routes, e.g. `/api/v1/auth/oidc/{id}/authorize`
+    pub id: String,
+    pub issuer: String,
+    pub client_id: String,
+    // Public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    // Enterprise IdPs often omit `email_verified` although they own every address they issue
+    #[serde(default)]
+    pub trust_email: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailVerificationPolicy {
+    // Unverified users can do everything
+    Optional,
+    // Unverified users only reach `email_verification_allowed_routes`
+    Restricted,
+    // Unverified users cannot sign in
+    Required,
+}
+
+impl FromStr for EmailVerificationPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "optional" => Ok(Self::Optional),
+            "restricted" => Ok(Self::Restricted),
+            "required" => Ok(Self::Required),
+            _ => Err(format!("Unknown email verification policy {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl FromStr for CookieSameSite {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lax" => Ok(Self::Lax),
+            "none" => Ok(Self::None),
+            _ => Err(format!("Unknown cookie same site policy {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenDelivery {
+    // Tokens only travel in HttpOnly cookies
+    Cookie,
+    // Tokens only travel in the response body, no cookies are set
+    Body,
+    Both,
+}
+
+impl FromStr for TokenDelivery {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "cookie" => Ok(Self::Cookie),
+            "body" => Ok(Self::Body),
+            "both" => Ok(Self::Both),
+            _ => Err(format!("Unknown token delivery {}", value)),
+        }
+    }
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -10,12 +112,62 @@ pub struct Config {
     pub acquire_timeout: Duration,
     pub max_connections: u32,
     pub min_connections: u32,
-    pub access_token_key_secret: String,
+    pub jwt_keys: Vec<JwtKey>,
+    pub jwt_signing_key_id: String,
     pub access_token_key_ttl: Duration,
-    pub refresh_token_key_secret: String,
     pub refresh_token_key_ttl: Duration,
     pub redis_default_ttl: Duration,
+    // How long a lookup that found nothing is remembered
+    pub cache_negative_ttl: Duration,
+    // Entities read straight from the database, e.g. ["user", "user_roles", "role_permissions"]
+    pub cache_disabled_entities: Vec<String>,
     pub cors_allowed_origins: Vec<String>,
+    // Reverse proxies whose X-Forwarded-For header is believed
+    pub trusted_proxies: Vec<IpAddr>,
+    pub totp_issuer: String,
+    pub two_factor_required_roles: Vec<String>,
+    pub two_factor_challenge_ttl: Duration,
+    pub app_url: String,
+    pub mailer: String,
+    pub mail_from: String,
+    pub smtp_url: String,
+    pub outbox_dir: String,
+    pub storage_dir: String,
+    pub avatar_max_size_kib: usize,
+    pub password_reset_ttl: Duration,
+    pub password_reset_max_requests: i64,
+    pub password_reset_request_window: Duration,
+    pub email_verification_policy: EmailVerificationPolicy,
+    pub email_verification_allowed_routes: Vec<String>,
+    pub email_verification_ttl: Duration,
+    pub email_verification_resend_interval: Duration,
+    pub sign_in_max_attempts: i64,
+    pub sign_in_ip_max_attempts: i64,
+    pub sign_in_delay_after: i64,
+    pub sign_in_lockout_ttl: Duration,
+    pub oidc_providers: Vec<OidcProvider>,
+    pub oidc_state_ttl: Duration,
+    pub password_hash_memory_kib: u32,
+    pub password_hash_iterations: u32,
+    pub password_hash_parallelism: u32,
+    // Base64 encoded 32 byte AES key for secrets kept in the database, e.g. TOTP secrets
+    pub encryption_key: String,
+    pub cookie_secure: bool,
+    pub cookie_domain: Option<String>,
+    pub cookie_same_site: CookieSameSite,
+    // Clients may override it per request
+    pub token_delivery: TokenDelivery,
+    // Defaults to the host of `webauthn_rp_origin`
+    pub webauthn_rp_id: Option<String>,
+    pub webauthn_rp_origin: String,
+    pub webauthn_rp_name: String,
+    pub webauthn_challenge_ttl: Duration,
+    pub magic_link_ttl: Duration,
+    pub magic_link_max_requests: i64,
+    pub magic_link_request_window: Duration,
+    // How long a deleted account can still be restored before it is anonymized
+    pub account_deletion_grace_period: Duration,
+    pub account_purge_interval: Duration,
 }
 
 impl Config {
@@ -28,11 +180,11 @@ impl Config {
             cache_url: env::var("CACHE_URL").expect("CACHE_URL must be set"),
             idle_timeout: env::var("IDLE_TIMEOUT")
                 .map(|v| v.parse::<i64>().unwrap())
-                .map(|v| Duration::seconds(v))
+                .map(Duration::seconds)
                 .expect("IDLE_TIMEOUT must be set and valid"),
             acquire_timeout: env::var("ACQUIRE_TIMEOUT")
                 .map(|v| v.parse::<i64>().unwrap())
-                .map(|v| Duration::seconds(v))
+                .map(Duration::seconds)
                 .expect("ACQUIRE_TIMEOUT must be set and valid"),
             max_connections: env::var("MAX_CONNECTIONS")
                 .map(|v| v.parse::<u32>().unwrap())
@@ -40,25 +192,228 @@ impl Config {
             min_connections: env::var("MIN_CONNECTIONS")
                 .map(|v| v.parse::<u32>().unwrap())
                 .expect("MIN_CONNECTIONS must be set"),
-            access_token_key_secret: env::var("ACCESS_TOKEN_KEY")
-                .expect("ACCESS_TOKEN_KEY must be set"),
+            jwt_keys: env::var("JWT_KEYS")
+                .map(|v| serde_json::from_str::<Vec<JwtKey>>(&v).unwrap())
+                .expect("JWT_KEYS must be set and valid"),
+            jwt_signing_key_id: env::var("JWT_SIGNING_KEY_ID")
+                .expect("JWT_SIGNING_KEY_ID must be set"),
             access_token_key_ttl: env::var("ACCESS_TOKEN_TTL")
                 .map(|v| v.parse::<i64>().unwrap())
-                .map(|v| Duration::minutes(v))
+                .map(Duration::minutes)
                 .expect("ACCESS_TOKEN_TTL must be set and valid"),
-            refresh_token_key_secret: env::var("REFRESH_TOKEN_KEY")
-                .expect("REFRESH_TOKEN_KEY must be set"),
             refresh_token_key_ttl: env::var("REFRESH_TOKEN_TTL")
                 .map(|v| v.parse::<i64>().unwrap())
-                .map(|v| Duration::days(v))
+                .map(Duration::days)
                 .expect("ACCESS_TOKEN_TTL must be set and valid"),
             redis_default_ttl: env::var("REDIS_DEFAULT_TTL")
                 .map(|v| v.parse::<i64>().unwrap())
-                .map(|v| Duration::seconds(v))
+                .map(Duration::seconds)
                 .expect("REDIS_DEFAULT_TTL must be set and valid"),
+            cache_negative_ttl: env::var("CACHE_NEGATIVE_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::seconds)
+                .unwrap_or_else(|_| Duration::seconds(30)),
+            cache_disabled_entities: env::var("CACHE_DISABLED_ENTITIES")
+                .map(|v| serde_json::from_str::<Vec<String>>(&v).unwrap())
+                .unwrap_or_default(),
             cors_allowed_origins: env::var("CORS_ALLOWED_ORIGINS")
                 .map(|v| serde_json::from_str::<Vec<String>>(&v).unwrap())
                 .expect("CORS_ALLOWED_ORIGINS must be set and valid"),
+            trusted_proxies: env::var("TRUSTED_PROXIES")
+                .map(|v| serde_json::from_str::<Vec<IpAddr>>(&v).unwrap())
+                .unwrap_or_default(),
+            totp_issuer: env::var("TOTP_ISSUER").unwrap_or_else(|_| "SIPDAH".to_string()),
+            two_factor_required_roles: env::var("TWO_FACTOR_REQUIRED_ROLES")
+                .map(|v| serde_json::from_str::<Vec<String>>(&v).unwrap())
+                .unwrap_or_else(|_| vec!["ADMIN".to_string(), "SUPER_ADMIN".to_string()]),
+            two_factor_challenge_ttl: env::var("TWO_FACTOR_CHALLENGE_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::seconds)
+                .unwrap_or_else(|_| Duration::minutes(5)),
+            app_url: env::var("APP_URL").expect("APP_URL must be set"),
+            mailer: env::var("MAILER").unwrap_or_else(|_| "outbox".to_string()),
+            mail_from: env::var("MAIL_FROM")
+                .unwrap_or_else(|_| "SIPDAH <no-reply@sipdah.local>".to_string()),
+            smtp_url: env::var("SMTP_URL").unwrap_or_default(),
+            outbox_dir: env::var("OUTBOX_DIR").unwrap_or_else(|_| "outbox".to_string()),
+            storage_dir: env::var("STORAGE_DIR").unwrap_or_else(|_| "uploads".to_string()),
+            avatar_max_size_kib: env::var("AVATAR_MAX_SIZE_KIB")
+                .map(|v| v.parse::<usize>().unwrap())
+                .unwrap_or(2048),
+            password_reset_ttl: env::var("PASSWORD_RESET_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::minutes)
+                .unwrap_or_else(|_| Duration::minutes(30)),
+            // Reset links an address may ask for within `password_reset_request_window`
+            password_reset_max_requests: env::var("PASSWORD_RESET_MAX_REQUESTS")
+                .map(|v| v.parse::<i64>().unwrap())
+                .unwrap_or(3),
+            password_reset_request_window: env::var("PASSWORD_RESET_REQUEST_WINDOW")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::minutes)
+                .unwrap_or_else(|_| Duration::minutes(15)),
+            email_verification_policy: env::var("EMAIL_VERIFICATION_POLICY")
+                .map(|v| v.parse::<EmailVerificationPolicy>().unwrap())
+                .unwrap_or(EmailVerificationPolicy::Restricted),
+            // A trailing `*` matches every path under the prefix
+            email_verification_allowed_routes: env::var("EMAIL_VERIFICATION_ALLOWED_ROUTES")
+                .map(|v| serde_json::from_str::<Vec<String>>(&v).unwrap())
+                .unwrap_or_else(|_| vec!["/api/v1/auth/*".to_string(), "/api/v1/user".to_string()]),
+            email_verification_ttl: env::var("EMAIL_VERIFICATION_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::hours)
+                .unwrap_or_else(|_| Duration::hours(24)),
+            email_verification_resend_interval: env::var("EMAIL_VERIFICATION_RESEND_INTERVAL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::seconds)
+                .unwrap_or_else(|_| Duration::seconds(60)),
+            sign_in_max_attempts: env::var("SIGN_IN_MAX_ATTEMPTS")
+                .map(|v| v.parse::<i64>().unwrap())
+                .unwrap_or(10),
+            sign_in_ip_max_attempts: env::var("SIGN_IN_IP_MAX_ATTEMPTS")
+                .map(|v| v.parse::<i64>().unwrap())
+                .unwrap_or(50),
+            sign_in_delay_after: env::var("SIGN_IN_DELAY_AFTER")
+                .map(|v| v.parse::<i64>().unwrap())
+                .unwrap_or(3),
+            sign_in_lockout_ttl: env::var("SIGN_IN_LOCKOUT_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::minutes)
+                .unwrap_or_else(|_| Duration::minutes(15)),
+            oidc_providers: env::var("OIDC_PROVIDERS")
+                .map(|v| serde_json::from_str::<Vec<OidcProvider>>(&v).unwrap())
+                .unwrap_or_default(),
+            oidc_state_ttl: env::var("OIDC_STATE_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::minutes)
+                .unwrap_or_else(|_| Duration::minutes(10)),
+            // Argon2id costs, the defaults follow the OWASP recommendation
+            password_hash_memory_kib: env::var("PASSWORD_HASH_MEMORY_KIB")
+                .map(|v| v.parse::<u32>().unwrap())
+                .unwrap_or(19 * 1024),
+            password_hash_iterations: env::var("PASSWORD_HASH_ITERATIONS")
+                .map(|v| v.parse::<u32>().unwrap())
+                .unwrap_or(2),
+            password_hash_parallelism: env::var("PASSWORD_HASH_PARALLELISM")
+                .map(|v| v.parse::<u32>().unwrap())
+                .unwrap_or(1),
+            encryption_key: env::var("ENCRYPTION_KEY").expect("ENCRYPTION_KEY must be set"),
+            cookie_secure: env::var("COOKIE_SECURE")
+                .map(|v| v.parse::<bool>().unwrap())
+                .unwrap_or(true),
+            cookie_domain: env::var("COOKIE_DOMAIN").ok(),
+            cookie_same_site: env::var("COOKIE_SAME_SITE")
+                .map(|v| v.parse::<CookieSameSite>().unwrap())
+                .unwrap_or(CookieSameSite::None),
+            token_delivery: env::var("TOKEN_DELIVERY")
+                .map(|v| v.parse::<TokenDelivery>().unwrap())
+                .unwrap_or(TokenDelivery::Both),
+            webauthn_rp_id: env::var("WEBAUTHN_RP_ID").ok(),
+            webauthn_rp_origin: env::var("WEBAUTHN_RP_ORIGIN")
+                .or_else(|_| env::var("APP_URL"))
+                .expect("WEBAUTHN_RP_ORIGIN or APP_URL must be set"),
+            webauthn_rp_name: env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "SIPDAH".to_string()),
+            webauthn_challenge_ttl: env::var("WEBAUTHN_CHALLENGE_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::seconds)
+                .unwrap_or_else(|_| Duration::minutes(5)),
+            magic_link_ttl: env::var("MAGIC_LINK_TTL")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::minutes)
+                .unwrap_or_else(|_| Duration::minutes(10)),
+            // Links an address may ask for within `magic_link_request_window`
+            magic_link_max_requests: env::var("MAGIC_LINK_MAX_REQUESTS")
+                .map(|v| v.parse::<i64>().unwrap())
+                .unwrap_or(3),
+            magic_link_request_window: env::var("MAGIC_LINK_REQUEST_WINDOW")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::minutes)
+                .unwrap_or_else(|_| Duration::minutes(15)),
+            account_deletion_grace_period: env::var("ACCOUNT_DELETION_GRACE_PERIOD")
+                .map(|v| v.parse::<i64>().unwrap())
+                .map(Duration::days)
+                .unwrap_or_else(|_| Duration::days(30)),
+            account_purge_interval: env::var("ACCOUNT_PURGE_INTERVAL")
+                .map(|v| {
+                    v.parse::<i64>()
+                        .ok()
+                        .filter(|minutes| *minutes > 0)
+                        .expect("ACCOUNT_PURGE_INTERVAL must be a positive number of minutes")
+                })
+                .map(Duration::minutes)
+                .unwrap_or_else(|_| Duration::hours(1)),
+        }
+    }
+
+    pub fn is_cache_enabled(&self, entity: &str) -> bool {
+        !self
+            .cache_disabled_entities
+            .iter()
+            .any(|disabled| disabled == entity)
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    // The defaults of `new`, with made-up values where it insists on an environment variable
+    pub fn for_tests() -> Self {
+        Config {
+            port: 8080,
+            database_url: String::new(),
+            cache_url: String::new(),
+            idle_timeout: Duration::seconds(60),
+            acquire_timeout: Duration::seconds(5),
+            max_connections: 1,
+            min_connections: 1,
+            jwt_keys: Vec::new(),
+            jwt_signing_key_id: String::new(),
+            access_token_key_ttl: Duration::minutes(15),
+            refresh_token_key_ttl: Duration::days(7),
+            redis_default_ttl: Duration::seconds(300),
+            cache_negative_ttl: Duration::seconds(30),
+            cache_disabled_entities: Vec::new(),
+            cors_allowed_origins: vec!["https://sipdah.example".to_string()],
+            trusted_proxies: Vec::new(),
+            totp_issuer: "SIPDAH".to_string(),
+            two_factor_required_roles: Vec::new(),
+            two_factor_challenge_ttl: Duration::minutes(5),
+            app_url: "https://sipdah.example".to_string(),
+            mailer: "outbox".to_string(),
+            mail_from: "SIPDAH <no-reply@sipdah.local>".to_string(),
+            smtp_url: String::new(),
+            outbox_dir: "outbox".to_string(),
+            storage_dir: "uploads".to_string(),
+            avatar_max_size_kib: 2048,
+            password_reset_ttl: Duration::minutes(30),
+            password_reset_max_requests: 3,
+            password_reset_request_window: Duration::minutes(15),
+            email_verification_policy: EmailVerificationPolicy::Restricted,
+            email_verification_allowed_routes: Vec::new(),
+            email_verification_ttl: Duration::hours(24),
+            email_verification_resend_interval: Duration::seconds(60),
+            sign_in_max_attempts: 10,
+            sign_in_ip_max_attempts: 50,
+            sign_in_delay_after: 3,
+            sign_in_lockout_ttl: Duration::minutes(15),
+            oidc_providers: Vec::new(),
+            oidc_state_ttl: Duration::minutes(10),
+            password_hash_memory_kib: 19 * 1024,
+            password_hash_iterations: 2,
+            password_hash_parallelism: 1,
+            encryption_key: String::new(),
+            cookie_secure: true,
+            cookie_domain: None,
+            cookie_same_site: CookieSameSite::None,
+            token_delivery: TokenDelivery::Both,
+            webauthn_rp_id: None,
+            webauthn_rp_origin: "https://sipdah.example".to_string(),
+            webauthn_rp_name: "SIPDAH".to_string(),
+            webauthn_challenge_ttl: Duration::minutes(5),
+            magic_link_ttl: Duration::minutes(10),
+            magic_link_max_requests: 3,
+            magic_link_request_window: Duration::minutes(15),
+            account_deletion_grace_period: Duration::days(30),
+            account_purge_interval: Duration::hours(1),
         }
     }
 }
@@ -0,0 +1,69 @@
+use crate::config::Config;
+use crate::internal::model::auth::Device;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+impl<S> FromRequestParts<S> for Device
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let trusted_proxies = parts
+            .extensions
+            .get::<Arc<Config>>()
+            .map(|config| config.trusted_proxies.as_slice())
+            .unwrap_or_default();
+        let ip_address = client_ip(peer, forwarded_for(parts), trusted_proxies);
+
+        Ok(Device {
+            user_agent,
+            ip_address: ip_address.map(|ip| ip.to_string()),
+        })
+    }
+}
+
+fn forwarded_for(parts: &Parts) -> Vec<IpAddr> {
+    parts
+        .headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|value| value.trim().parse::<IpAddr>().ok())
+        .collect()
+}
+
+// Anyone can send the header, so it only counts when the peer is one of our own proxies. Each
+// proxy appends the address it saw, the client is the last hop that is not a trusted proxy.
+fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Vec<IpAddr>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let peer = peer?;
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+
+    forwarded_for
+        .into_iter()
+        .rev()
+        .find(|ip| !trusted_proxies.contains(ip))
+        .or(Some(peer))
+}
@@ -1,5 +1,19 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 pub fn new() -> String {
     Uuid::now_v7().to_string()
-}
\ No newline at end of file
+}
+
+// Unguessable value for tokens that are handed out to clients, unlike the time ordered ids
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Random tokens have enough entropy that a fast hash is sufficient and keeps them searchable
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
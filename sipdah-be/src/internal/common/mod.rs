@@ -1,3 +1,4 @@
 pub mod id;
 pub mod uow;
-pub mod response;
\ No newline at end of file
+pub mod response;
+pub mod device;
\ No newline at end of file
@@ -26,12 +26,10 @@ where
         code: u16,
         message: String,
     ) -> (StatusCode, AxumJson<ApiResponse<Option<T>>>) {
-        let result = match self {
+        match self {
             Ok(data) => json_success(code, data, message),
             Err(error) => json_error(error),
-        };
-
-        result
+        }
     }
 
     fn json(self) -> (StatusCode, AxumJson<ApiResponse<Option<T>>>) {
@@ -84,6 +82,7 @@ where
         Error::Forbidden(message) => (StatusCode::FORBIDDEN, message),
         Error::NotFound(message) => (StatusCode::NOT_FOUND, message),
         Error::Conflict(message) => (StatusCode::CONFLICT, message),
+        Error::TooManyRequests(message) => (StatusCode::TOO_MANY_REQUESTS, message),
         Error::Internal(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
     };
 
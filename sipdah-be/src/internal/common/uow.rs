@@ -1,33 +1,63 @@
 use crate::internal::model::error::Error;
+use crate::internal::provider::cache::Cache as CacheProvider;
 use sqlx::mysql::{MySqlArguments, MySqlRow};
 use sqlx::query::{Query, QueryAs};
 use sqlx::{MySql, MySqlPool, Pool, Transaction};
 use std::cell::RefCell;
 use std::future::Future;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 use tokio::task_local;
+use tracing::warn;
 
 task_local! {
     pub static CURRENT_TRANSACTION: RefCell<Option<&'static mut Transaction<'static, MySql>>>;
+    // Cache keys to evict once the current transaction is over
+    static PENDING_EVICTIONS: Arc<Mutex<Vec<String>>>;
 }
 
-pub struct TransactionManager {
+pub struct TransactionManager<T>
+where
+    T: CacheProvider,
+{
     pool: Arc<MySqlPool>,
+    cache_provider: Arc<T>,
 }
 
 pub trait Uow {
     async fn run<F, R>(&self, operation: F) -> Result<R, Error>
     where
         F: Future<Output = Result<R, Error>>;
+
+    // Evicts after the transaction ends, so a concurrent read cannot cache the old row again
+    async fn evict(&self, key: String) -> Result<(), Error>;
 }
 
-impl TransactionManager {
-    pub fn new(pool: Arc<MySqlPool>) -> Self {
-        Self { pool }
+impl<T> TransactionManager<T>
+where
+    T: CacheProvider,
+{
+    pub fn new(pool: Arc<MySqlPool>, cache_provider: Arc<T>) -> Self {
+        Self {
+            pool,
+            cache_provider,
+        }
+    }
+
+    // Runs after a rollback too, reads inside the transaction may have cached what never landed
+    async fn evict_pending(&self, evictions: &Mutex<Vec<String>>) {
+        let keys = std::mem::take(&mut *evictions.lock().unwrap_or_else(PoisonError::into_inner));
+        for key in keys {
+            if let Err(err) = self.cache_provider.del(key.clone()).await {
+                warn!("Failed to evict cache key {}: {}", key, err);
+            }
+        }
     }
 }
 
-impl Uow for TransactionManager {
+impl<T> Uow for TransactionManager<T>
+where
+    T: CacheProvider,
+{
     async fn run<F, R>(&self, operation: F) -> Result<R, Error>
     where
         F: Future<Output = Result<R, Error>>,
@@ -38,25 +68,50 @@ impl Uow for TransactionManager {
             .await
             .map_err(|err| Error::Internal(err.to_string()))?;
         // Safety: we control the transaction lifetime within this scope
-        let tx_static = unsafe { std::mem::transmute(&mut tx) };
-
+        let tx_static = unsafe {
+            std::mem::transmute::<
+                &mut Transaction<'_, MySql>,
+                &'static mut Transaction<'static, MySql>,
+            >(&mut tx)
+        };
+
+        let evictions = Arc::new(Mutex::new(Vec::new()));
         let result = CURRENT_TRANSACTION
-            .scope(RefCell::new(Some(tx_static)), operation)
+            .scope(
+                RefCell::new(Some(tx_static)),
+                PENDING_EVICTIONS.scope(Arc::clone(&evictions), operation),
+            )
             .await;
 
-        match result {
-            Ok(value) => {
-                tx.commit()
-                    .await
-                    .map_err(|err| Error::Internal(err.to_string()))?;
-                Ok(value)
-            }
-            Err(e) => {
-                tx.rollback()
-                    .await
-                    .map_err(|err| Error::Internal(err.to_string()))?;
-                Err(e)
-            }
+        let result = match result {
+            Ok(value) => tx
+                .commit()
+                .await
+                .map(|_| value)
+                .map_err(|err| Error::Internal(err.to_string())),
+            Err(e) => match tx.rollback().await {
+                Ok(_) => Err(e),
+                Err(err) => Err(Error::Internal(err.to_string())),
+            },
+        };
+        self.evict_pending(&evictions).await;
+
+        result
+    }
+
+    async fn evict(&self, key: String) -> Result<(), Error> {
+        let is_deferred = PENDING_EVICTIONS
+            .try_with(|evictions| {
+                evictions
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(key.clone())
+            })
+            .is_ok();
+
+        match is_deferred {
+            true => Ok(()),
+            false => self.cache_provider.del(key).await,
         }
     }
 }
@@ -121,7 +176,7 @@ where
 {
     let tx = get_transaction();
 
-    let result = match tx {
+    match tx {
         Ok(tx) => with_transaction!(tx, {
             query
                 .fetch_optional(&mut **tx)
@@ -132,9 +187,7 @@ where
             .fetch_optional(pool)
             .await
             .map_err(|err| Error::Internal(err.to_string())),
-    };
-
-    result
+    }
 }
 
 pub async fn fetch_one<T>(
@@ -159,3 +212,26 @@ where
             .map_err(|err| Error::Internal(err.to_string())),
     }
 }
+
+pub async fn fetch_all<T>(
+    query: QueryAs<'_, MySql, T, MySqlArguments>,
+    pool: &Pool<MySql>,
+) -> Result<Vec<T>, Error>
+where
+    T: Send + Unpin + for<'r> sqlx::FromRow<'r, MySqlRow>,
+{
+    let tx = get_transaction();
+
+    match tx {
+        Ok(tx) => with_transaction!(tx, {
+            query
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(|err| Error::Internal(err.to_string()))
+        }),
+        Err(_) => query
+            .fetch_all(pool)
+            .await
+            .map_err(|err| Error::Internal(err.to_string())),
+    }
+}
@@ -1,9 +1,13 @@
+use crate::config::{Config, EmailVerificationPolicy};
 use crate::internal;
 use crate::internal::common::response;
+use crate::internal::middleware::csrf::verify_csrf;
 use crate::internal::model::error::Error;
 use crate::internal::model::identity::{Identity, IDENTITY};
+use crate::internal::model::personal_access_token::{SCOPED_ROUTES, TOKEN_PREFIX};
 use crate::internal::router::auth::AuthState;
 use axum::extract::State;
+use axum::http::Method;
 use axum::response::IntoResponse;
 use axum::{extract::Request, http::StatusCode, middleware::Next, response::Response};
 use axum_extra::extract::CookieJar;
@@ -21,6 +25,17 @@ pub async fn auth<T1: auth::Service>(
     req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
+    // Browsers attach the cookie on their own, so only cookie authentication can be forged
+    let csrf = match bearer {
+        None if jar.get("access_token").is_some() => {
+            verify_csrf(&state.config, &jar, req.method(), req.headers())
+        }
+        _ => Ok(()),
+    };
+    if let Err(error) = csrf {
+        return Ok(json_error::<String>(error).into_response());
+    }
+
     let token = bearer
         .as_ref()
         .map(|b| b.token().to_string())
@@ -34,14 +49,96 @@ pub async fn auth<T1: auth::Service>(
         }
     };
 
-    match state.auth_service.verify_access_token(&token) {
-        Ok(claim) => {
-            let identity = Identity {
-                user_id: claim.sub,
-                email: claim.email,
-            };
-            Ok(IDENTITY.scope(identity, next.run(req)).await)
+    let identity = if token.starts_with(TOKEN_PREFIX) {
+        match state
+            .auth_service
+            .verify_personal_access_token(&token)
+            .await
+        {
+            Ok(identity) => identity,
+            Err(error) => return Ok(json_error::<String>(error).into_response()),
+        }
+    } else {
+        match state.auth_service.verify_access_token(&token).await {
+            Ok(claim) if !claim.email_verified && !is_allowed_unverified(&state.config, &req) => {
+                let error = Error::Forbidden("Email address has not been verified".to_string());
+                return Ok(json_error::<String>(error).into_response());
+            }
+            Ok(claim) => match load_grants(&state, &claim.sub).await {
+                Ok((roles, permissions)) => Identity {
+                    user_id: claim.sub,
+                    email: claim.email,
+                    session_id: Some(claim.sid),
+                    actor_id: claim.act,
+                    scopes: None,
+                    roles,
+                    permissions,
+                },
+                Err(error) => return Ok(json_error::<String>(error).into_response()),
+            },
+            Err(error) => return Ok(json_error::<String>(error).into_response()),
+        }
+    };
+
+    if let Some(scopes) = &identity.scopes {
+        let is_granted = required_scope(&req).is_some_and(|scope| scopes.contains(&scope));
+        if !is_granted {
+            let error = Error::Forbidden("Token does not grant access to this route".to_string());
+            return Ok(json_error::<String>(error).into_response());
+        }
+    }
+
+    // Refuse to act for an impersonating administrator when the trail cannot be written
+    if identity.actor_id.is_some() {
+        let action = format!("{} {}", req.method(), req.uri().path());
+        if let Err(error) = state
+            .auth_service
+            .record_audit_log(&identity, &action)
+            .await
+        {
+            return Ok(json_error::<String>(error).into_response());
         }
-        Err(error) => Ok(json_error::<String>(error).into_response()),
     }
+
+    Ok(IDENTITY.scope(identity, next.run(req)).await)
+}
+
+async fn load_grants<T1: auth::Service>(
+    state: &AuthState<T1>,
+    user_id: &str,
+) -> Result<(Vec<String>, Vec<String>), Error> {
+    let roles = state.auth_service.get_roles(user_id).await?;
+    let permissions = state.auth_service.get_permissions(&roles).await?;
+
+    Ok((roles, permissions))
+}
+
+// `GET /api/v1/roles/{id}` needs `roles:read`, any other method needs `roles:write`
+fn required_scope(req: &Request) -> Option<String> {
+    let path = req.uri().path();
+    let (_, resource) = SCOPED_ROUTES.iter().find(|(prefix, _)| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })?;
+    let access = match *req.method() {
+        Method::GET | Method::HEAD => "read",
+        _ => "write",
+    };
+
+    Some(format!("{}:{}", resource, access))
+}
+
+fn is_allowed_unverified(config: &Config, req: &Request) -> bool {
+    if config.email_verification_policy == EmailVerificationPolicy::Optional {
+        return true;
+    }
+
+    let path = req.uri().path();
+    config
+        .email_verification_allowed_routes
+        .iter()
+        .any(|route| match route.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == route,
+        })
 }
@@ -0,0 +1,68 @@
+use crate::config::Config;
+use crate::internal::model::error::Error;
+use axum::http::{header, HeaderMap, Method};
+use axum_extra::extract::CookieJar;
+use reqwest::Url;
+
+// Double-submit token: the cookie is readable by the app, which echoes it in the header
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+// Cookie-authenticated unsafe requests need an allowed `Origin` and a matching `X-CSRF-Token`
+pub fn verify_csrf(
+    config: &Config,
+    jar: &CookieJar,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Result<(), Error> {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+
+    let origin = request_origin(headers);
+    if let Some(origin) = origin.filter(|origin| !config.cors_allowed_origins.contains(origin)) {
+        return Err(Error::Forbidden(format!(
+            "Origin {} is not allowed",
+            origin
+        )));
+    }
+
+    let expected = jar.get(CSRF_COOKIE).map(|cookie| cookie.value());
+    let actual = headers
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    match (expected, actual) {
+        (Some(expected), Some(actual)) if !expected.is_empty() && equals(expected, actual) => {
+            Ok(())
+        }
+        _ => Err(Error::Forbidden(
+            "CSRF token is missing or invalid".to_string(),
+        )),
+    }
+}
+
+// Browsers may leave out `Origin` on same-origin requests, `Referer` is the fallback
+fn request_origin(headers: &HeaderMap) -> Option<String> {
+    if let Some(origin) = headers
+        .get(header::ORIGIN)
+        .and_then(|value| value.to_str().ok())
+    {
+        return Some(origin.to_string());
+    }
+
+    headers
+        .get(header::REFERER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|referer| Url::parse(referer).ok())
+        .map(|url| url.origin().ascii_serialization())
+}
+
+// Constant time, so the token cannot be guessed byte by byte
+fn equals(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
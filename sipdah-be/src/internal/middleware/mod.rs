@@ -1,2 +1,5 @@
 pub mod auth;
+pub mod csrf;
+pub mod role;
 pub use auth::auth;
+pub use role::require_roles;
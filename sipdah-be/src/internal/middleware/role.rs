@@ -0,0 +1,30 @@
+use crate::internal::common::response::json_error;
+use crate::internal::model::error::Error;
+use crate::internal::model::identity::get_current_identity;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+// Must be layered inside `middleware::auth`, which provides the identity
+pub async fn require_roles(
+    State(roles): State<&'static [&'static str]>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let identity = match get_current_identity() {
+        Ok(identity) => identity,
+        Err(error) => return Ok(json_error::<String>(error).into_response()),
+    };
+
+    let is_allowed = identity
+        .roles
+        .iter()
+        .any(|role| roles.contains(&role.as_str()));
+    if !is_allowed {
+        let error = Error::Forbidden("You do not have permission to access this route".to_string());
+        return Ok(json_error::<String>(error).into_response());
+    }
+
+    Ok(next.run(req).await)
+}
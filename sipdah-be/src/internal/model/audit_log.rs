@@ -0,0 +1,21 @@
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+
+pub const ACTION_IMPERSONATION_START: &str = "impersonation.start";
+pub const ACTION_IMPERSONATION_END: &str = "impersonation.end";
+// Refreshes skip `middleware::auth`, which logs every other request of the session
+pub const ACTION_IMPERSONATION_REFRESH: &str = "impersonation.refresh";
+
+// Something `actor_id` did on behalf of `user_id`, who differ only while impersonating
+pub struct AuditLog {
+    pub id: String,
+    pub actor_id: String,
+    pub user_id: String,
+    pub session_id: Option<String>,
+    pub action: String,
+    pub created_at: DateTime<Local>,
+}
+
+pub trait Repository {
+    async fn create(&self, audit_log: &AuditLog) -> Result<(), Error>;
+}
@@ -1,19 +1,119 @@
 use crate::internal::model::error::Error;
+use crate::internal::model::identity::Identity;
+use crate::internal::model::oidc::{OidcAuthorizationResponse, OidcCallbackRequest};
+use crate::internal::model::passkey::{
+    FinishPasskeyAuthenticationRequest, FinishPasskeyRegistrationRequest,
+    PasskeyAuthenticationResponse, PasskeyResponse, StartPasskeyAuthenticationRequest,
+};
+use crate::internal::model::session::SessionResponse;
+use crate::internal::model::two_factor::{
+    RecoveryCodesResponse, SetupTwoFactorRequest, TwoFactorCodeRequest, TwoFactorEnrollmentResponse,
+};
+use chrono::{DateTime, Local};
+use jsonwebtoken::jwk::JwkSet;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
+use webauthn_rs::prelude::CreationChallengeResponse;
 
 pub trait Service {
-    async fn sign_in(&self, req: &SignInRequest) -> Result<AuthResponse, Error>;
+    async fn sign_in(&self, req: &SignInRequest) -> Result<SignInResponse, Error>;
 
-    async fn sign_up(&self, req: &SignUpRequest) -> Result<AuthResponse, Error>;
+    async fn sign_up(&self, req: &SignUpRequest) -> Result<SignInResponse, Error>;
+
+    async fn oidc_authorize(
+        &self,
+        provider_id: &str,
+        binding: Option<&str>,
+    ) -> Result<OidcAuthorizationResponse, Error>;
+
+    async fn oidc_callback(&self, req: &OidcCallbackRequest) -> Result<SignInResponse, Error>;
 
     async fn sign_out(&self) -> Result<(), Error>;
 
     async fn refresh(&self, req: &RefreshTokenRequest) -> Result<AuthResponse, Error>;
 
-    fn verify_access_token(&self, token: &str) -> Result<Claim, Error>;
+    async fn verify_two_factor(&self, req: &VerifyTwoFactorRequest) -> Result<AuthResponse, Error>;
+
+    async fn setup_two_factor(
+        &self,
+        req: &SetupTwoFactorRequest,
+    ) -> Result<TwoFactorEnrollmentResponse, Error>;
+
+    async fn enroll_two_factor(&self) -> Result<TwoFactorEnrollmentResponse, Error>;
+
+    async fn confirm_two_factor(
+        &self,
+        req: &TwoFactorCodeRequest,
+    ) -> Result<RecoveryCodesResponse, Error>;
+
+    async fn disable_two_factor(&self, req: &TwoFactorCodeRequest) -> Result<(), Error>;
+
+    async fn forgot_password(&self, req: &ForgotPasswordRequest) -> Result<(), Error>;
+
+    async fn reset_password(&self, req: &ResetPasswordRequest) -> Result<(), Error>;
+
+    async fn restore_account(&self, req: &RestoreAccountRequest) -> Result<(), Error>;
+
+    async fn verify_email(&self, req: &VerifyEmailRequest) -> Result<(), Error>;
+
+    async fn resend_email_verification(
+        &self,
+        req: &ResendEmailVerificationRequest,
+    ) -> Result<(), Error>;
+
+    async fn unlock_sign_in(&self, req: &UnlockSignInRequest) -> Result<(), Error>;
+
+    async fn send_magic_link(&self, req: &MagicLinkRequest) -> Result<(), Error>;
+
+    async fn verify_magic_link(
+        &self,
+        req: &VerifyMagicLinkRequest,
+    ) -> Result<SignInResponse, Error>;
+
+    async fn get_sessions(&self) -> Result<Vec<SessionResponse>, Error>;
+
+    async fn revoke_session(&self, session_id: &str) -> Result<(), Error>;
+
+    async fn revoke_other_sessions(&self) -> Result<(), Error>;
+
+    async fn start_impersonation(&self, req: &ImpersonationRequest) -> Result<AuthResponse, Error>;
+
+    async fn end_impersonation(&self) -> Result<(), Error>;
+
+    async fn record_audit_log(&self, identity: &Identity, action: &str) -> Result<(), Error>;
+
+    async fn start_passkey_registration(&self) -> Result<CreationChallengeResponse, Error>;
+
+    async fn finish_passkey_registration(
+        &self,
+        req: &FinishPasskeyRegistrationRequest,
+    ) -> Result<PasskeyResponse, Error>;
+
+    async fn start_passkey_authentication(
+        &self,
+        req: &StartPasskeyAuthenticationRequest,
+    ) -> Result<PasskeyAuthenticationResponse, Error>;
+
+    async fn finish_passkey_authentication(
+        &self,
+        req: &FinishPasskeyAuthenticationRequest,
+    ) -> Result<AuthResponse, Error>;
+
+    async fn get_passkeys(&self) -> Result<Vec<PasskeyResponse>, Error>;
+
+    async fn delete_passkey(&self, passkey_id: &str) -> Result<(), Error>;
+
+    async fn verify_access_token(&self, token: &str) -> Result<Claim, Error>;
+
+    async fn get_roles(&self, user_id: &str) -> Result<Vec<String>, Error>;
+
+    async fn get_permissions(&self, roles: &[String]) -> Result<Vec<String>, Error>;
+
+    async fn verify_personal_access_token(&self, token: &str) -> Result<Identity, Error>;
 
     fn verify_refresh_token(&self, token: &str) -> Result<Claim, Error>;
+
+    fn jwks(&self) -> JwkSet;
 }
 
 #[derive(Validate, Deserialize)]
@@ -30,10 +130,12 @@ pub struct SignInRequest {
     pub email: String,
     #[validate(length(
         min = 6,
-        max = 16,
-        message = "Password length must be between 6 and 16 characters."
+        max = 128,
+        message = "Password length must be between 6 and 128 characters."
     ))]
     pub password: String,
+    #[serde(skip)]
+    pub device: Device,
 }
 
 #[derive(Validate, Deserialize)]
@@ -56,10 +158,12 @@ pub struct SignUpRequest {
     pub email: String,
     #[validate(length(
         min = 6,
-        max = 16,
-        message = "Password length must be between 6 and 16 characters."
+        max = 128,
+        message = "Password length must be between 6 and 128 characters."
     ))]
     pub password: String,
+    #[serde(skip)]
+    pub device: Device,
 }
 
 #[derive(Validate, Deserialize)]
@@ -67,6 +171,123 @@ pub struct SignUpRequest {
 pub struct RefreshTokenRequest {
     #[validate(length(min = 1, message = "Token is required"))]
     pub refresh_token: String,
+    #[serde(skip)]
+    pub device: Device,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct VerifyTwoFactorRequest {
+    #[validate(length(min = 1, message = "Challenge token is required"))]
+    pub challenge_token: String,
+    #[validate(length(
+        min = 6,
+        max = 16,
+        message = "Code length must be between 6 and 16 characters."
+    ))]
+    pub code: String,
+    #[serde(skip)]
+    pub device: Device,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct ForgotPasswordRequest {
+    #[validate(
+        email(message = "Invalid email format. Please provide a valid email address."),
+        length(
+            min = 1,
+            max = 64,
+            message = "Email length must be between 1 and 64 characters."
+        )
+    )]
+    pub email: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+    #[validate(length(
+        min = 6,
+        max = 128,
+        message = "Password length must be between 6 and 128 characters."
+    ))]
+    pub password: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct RestoreAccountRequest {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct VerifyEmailRequest {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct ResendEmailVerificationRequest {
+    #[validate(
+        email(message = "Invalid email format. Please provide a valid email address."),
+        length(
+            min = 1,
+            max = 64,
+            message = "Email length must be between 1 and 64 characters."
+        )
+    )]
+    pub email: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct UnlockSignInRequest {
+    #[validate(
+        email(message = "Invalid email format. Please provide a valid email address."),
+        length(
+            min = 1,
+            max = 64,
+            message = "Email length must be between 1 and 64 characters."
+        )
+    )]
+    pub email: String,
+    // Network the user signs in from, as shown in their login history; it may be locked on its own
+    #[validate(ip(message = "Invalid IP address."))]
+    pub ip_address: Option<String>,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct MagicLinkRequest {
+    #[validate(
+        email(message = "Invalid email format. Please provide a valid email address."),
+        length(
+            min = 1,
+            max = 64,
+            message = "Email length must be between 1 and 64 characters."
+        )
+    )]
+    pub email: String,
+    // Random value kept in a cookie of the requesting browser, absent for non-browser clients
+    #[serde(skip)]
+    pub binding: Option<String>,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct VerifyMagicLinkRequest {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+    #[serde(skip)]
+    pub binding: Option<String>,
+    #[serde(skip)]
+    pub device: Device,
 }
 
 #[derive(Debug, Serialize)]
@@ -74,15 +295,106 @@ pub struct RefreshTokenRequest {
 pub struct AuthResponse {
     pub user_id: String,
     pub email: String,
+    // Left out when the client takes its tokens as cookies only
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub access_token: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub refresh_token: String,
+    // Only present right after two-factor enrollment is completed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+pub enum SignInResponse {
+    Authenticated(AuthResponse),
+    TwoFactorRequired(TwoFactorChallengeResponse),
+    EmailVerificationRequired(EmailVerificationResponse),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct EmailVerificationResponse {
+    pub user_id: String,
+    pub email: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct TwoFactorChallengeResponse {
+    pub challenge_token: String,
+    // The account must enroll through `/api/v1/auth/2fa/setup` before it can verify
+    pub setup_required: bool,
+    pub expires_at: DateTime<Local>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorChallenge {
+    pub user_id: String,
+    pub device: Device,
+    pub setup_required: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
 pub struct Claim {
+    pub(crate) jti: String,
+    pub(crate) sid: String,
     pub(crate) sub: String,
     pub(crate) exp: i64,
     pub(crate) iat: i64,
     pub(crate) email: String,
+    // Tokens issued before verification existed carry no flag
+    #[serde(default)]
+    pub(crate) email_verified: bool,
+    // For clients only, the server always checks the current roles
+    #[serde(default)]
+    pub(crate) roles: Vec<String>,
+    // Real user behind an impersonation session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) act: Option<String>,
+    pub(crate) typ: TokenType,
+}
+
+// Bound to the address it was sent to, so the link dies once the email changes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailVerificationClaim {
+    pub(crate) sub: String,
+    pub(crate) exp: i64,
+    pub(crate) iat: i64,
+    pub(crate) email: String,
+    pub(crate) typ: TokenType,
+}
+
+// Single use is enforced in the cache by `jti`, `bnd` holds the hashed browser binding if any
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MagicLinkClaim {
+    pub(crate) jti: String,
+    pub(crate) sub: String,
+    pub(crate) exp: i64,
+    pub(crate) iat: i64,
+    pub(crate) email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) bnd: Option<String>,
+    pub(crate) typ: TokenType,
+}
+
+// All tokens are signed by the same keys, so the type keeps them apart
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    Access,
+    Refresh,
+    EmailVerification,
+    MagicLink,
+}
+
+pub struct ImpersonationRequest {
+    pub user_id: String,
+    pub device: Device,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
 }
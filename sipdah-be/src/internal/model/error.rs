@@ -7,6 +7,7 @@ pub enum Error {
     Unauthorized(String),
     Forbidden(String),
     Conflict(String),
+    TooManyRequests(String),
     Internal(String),
 }
 
@@ -1,22 +1,69 @@
-// use crate::internal::model::error::Error;
-// use chrono::{DateTime, Local};
-// 
-// pub struct File {
-//     pub id: String,
-//     pub file_name: String,
-//     pub file_size: u64,
-//     pub file_path: u64,
-//     pub file_type: String,
-//     pub location: String,
-//     pub owner_id: Option<String>,
-//     pub category: String,
-//     pub created_at: DateTime<Local>,
-//     pub updated_at: DateTime<Local>,
-//     pub deleted_at: Option<DateTime<Local>>,
-// }
-// 
-// pub trait FileRepository {
-//     async fn save(file: File) -> Result<(), Error>;
-// 
-//     async fn find_by_id(file: File) -> Result<(), Error>;
-// }
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use sqlx::FromRow;
+
+pub const CATEGORY_AVATAR: &str = "avatar";
+
+#[derive(FromRow)]
+pub struct File {
+    pub id: String,
+    pub owner_id: Option<String>,
+    pub category: String,
+    pub file_name: String,
+    pub file_type: String,
+    pub file_size: u64,
+    // Key of the content in the storage provider
+    pub location: String,
+    pub created_at: DateTime<Local>,
+}
+
+// A file as it arrived in a multipart request, before it is checked and stored
+pub struct FileUpload {
+    pub file_name: Option<String>,
+    pub content: Vec<u8>,
+}
+
+pub trait Repository {
+    async fn create(&self, file: &File) -> Result<(), Error>;
+
+    async fn find_by_id(&self, file_id: &str) -> Result<Option<File>, Error>;
+
+    async fn find_all_by_owner_id(&self, owner_id: &str) -> Result<Vec<File>, Error>;
+
+    async fn delete(&self, file_id: &str) -> Result<(), Error>;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct FileResponse {
+    pub id: String,
+    pub category: String,
+    pub file_name: String,
+    pub file_type: String,
+    pub file_size: u64,
+    pub url: String,
+    pub created_at: DateTime<Local>,
+}
+
+impl From<File> for FileResponse {
+    fn from(file: File) -> Self {
+        Self {
+            url: file_url(&file.id),
+            id: file.id,
+            category: file.category,
+            file_name: file.file_name,
+            file_type: file.file_type,
+            file_size: file.file_size,
+            created_at: file.created_at,
+        }
+    }
+}
+
+pub trait Service {
+    async fn get_public(&self, file_id: &str) -> Result<(File, Vec<u8>), Error>;
+}
+
+pub fn file_url(file_id: &str) -> String {
+    format!("/api/v1/files/{}", file_id)
+}
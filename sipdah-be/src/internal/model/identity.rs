@@ -5,6 +5,15 @@ use tokio::task_local;
 pub struct Identity {
     pub user_id: String,
     pub email: String,
+    // Only interactive sign-ins have a session
+    pub session_id: Option<String>,
+    // The administrator behind an impersonation session, `user_id` is the impersonated user
+    pub actor_id: Option<String>,
+    // Personal access tokens are limited to their scopes, sessions are not limited at all
+    pub scopes: Option<Vec<String>>,
+    pub roles: Vec<String>,
+    // Union of the permissions granted to `roles`
+    pub permissions: Vec<String>,
 }
 
 task_local! {
@@ -17,3 +26,31 @@ pub fn get_current_identity() -> Result<Identity, Error> {
         // Authorization must be handle in router
         .map_err(|_| Error::Forbidden("Failed to retrieve current identity".to_string()))
 }
+
+// Credentials outlive an impersonation, so only the user themselves may mint or change them
+pub fn require_account_owner() -> Result<Identity, Error> {
+    let identity = get_current_identity()?;
+    if identity.actor_id.is_some() {
+        return Err(Error::Forbidden(
+            "Credentials cannot be changed from an impersonation session".to_string(),
+        ));
+    }
+
+    Ok(identity)
+}
+
+pub fn require_permission(permission: &str) -> Result<Identity, Error> {
+    let identity = get_current_identity()?;
+    if !identity
+        .permissions
+        .iter()
+        .any(|granted| granted == permission)
+    {
+        return Err(Error::Forbidden(format!(
+            "Permission {} is required",
+            permission
+        )));
+    }
+
+    Ok(identity)
+}
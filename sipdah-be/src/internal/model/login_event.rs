@@ -0,0 +1,72 @@
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use sqlx::FromRow;
+
+pub const KIND_SIGN_IN: &str = "sign_in";
+pub const KIND_REFRESH: &str = "refresh";
+// Recorded for the impersonated user, the administrator is in the audit log
+pub const KIND_IMPERSONATION: &str = "impersonation";
+
+pub const OUTCOME_SUCCESS: &str = "success";
+pub const OUTCOME_FAILURE: &str = "failure";
+// Turned away by the sign-in throttle before the password was checked
+pub const OUTCOME_LOCKED: &str = "locked";
+
+// Most recent events returned by the history endpoints
+pub const LOGIN_HISTORY_LIMIT: u32 = 100;
+
+#[derive(FromRow)]
+pub struct LoginEvent {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub email: String,
+    pub kind: String,
+    pub outcome: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+pub trait Repository {
+    async fn create(&self, login_event: &LoginEvent) -> Result<(), Error>;
+
+    async fn find_all_by_user_id(
+        &self,
+        user_id: &str,
+        limit: u32,
+    ) -> Result<Vec<LoginEvent>, Error>;
+
+    async fn find_successes_by_user_id(
+        &self,
+        user_id: &str,
+        kind: &str,
+        limit: u32,
+    ) -> Result<Vec<LoginEvent>, Error>;
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error>;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct LoginEventResponse {
+    pub id: String,
+    pub kind: String,
+    pub outcome: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+impl From<LoginEvent> for LoginEventResponse {
+    fn from(login_event: LoginEvent) -> Self {
+        LoginEventResponse {
+            id: login_event.id,
+            kind: login_event.kind,
+            outcome: login_event.outcome,
+            ip_address: login_event.ip_address,
+            user_agent: login_event.user_agent,
+            created_at: login_event.created_at,
+        }
+    }
+}
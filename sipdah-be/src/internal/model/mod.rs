@@ -1,8 +1,17 @@
+pub mod audit_log;
 pub mod auth;
 pub mod error;
 pub mod file;
 pub mod identity;
+pub mod login_event;
+pub mod oidc;
+pub mod passkey;
+pub mod permission;
+pub mod personal_access_token;
 pub mod project;
+pub mod revoked_token;
 pub mod role;
+pub mod session;
+pub mod two_factor;
 pub mod user;
 pub mod web;
@@ -0,0 +1,87 @@
+use crate::internal::model::auth::Device;
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::Validate;
+
+// An account at an external identity provider linked to a local user
+#[derive(FromRow)]
+pub struct UserIdentity {
+    pub id: String,
+    pub user_id: String,
+    pub provider: String,
+    // The `sub` claim, stable for the account at its provider
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+pub trait Repository {
+    async fn create(&self, identity: &UserIdentity) -> Result<(), Error>;
+
+    async fn find_by_provider_and_subject(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<UserIdentity>, Error>;
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<UserIdentity>, Error>;
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error>;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct UserIdentityResponse {
+    pub id: String,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+impl From<UserIdentity> for UserIdentityResponse {
+    fn from(identity: UserIdentity) -> Self {
+        Self {
+            id: identity.id,
+            provider: identity.provider,
+            subject: identity.subject,
+            email: identity.email,
+            created_at: identity.created_at,
+        }
+    }
+}
+
+// Kept server side between the redirect to the provider and the callback
+#[derive(Serialize, Deserialize)]
+pub struct OidcState {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    // Hash of the browser binding cookie, the callback must come from the same browser
+    #[serde(default)]
+    pub binding: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct OidcAuthorizationResponse {
+    pub authorization_url: String,
+    pub state: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct OidcCallbackRequest {
+    #[serde(skip)]
+    pub provider: String,
+    #[validate(length(min = 1, message = "Code is required"))]
+    pub code: String,
+    #[validate(length(min = 1, message = "State is required"))]
+    pub state: String,
+    #[serde(skip)]
+    pub binding: Option<String>,
+    #[serde(skip)]
+    pub device: Device,
+}
@@ -0,0 +1,126 @@
+use crate::internal::model::auth::Device;
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::Validate;
+use webauthn_rs::prelude::{
+    DiscoverableAuthentication, PasskeyAuthentication, PublicKeyCredential,
+    RegisterPublicKeyCredential, RequestChallengeResponse,
+};
+
+// Pending ceremonies, kept in the cache until they are finished or expire
+pub fn passkey_registration_key(user_id: &str) -> String {
+    format!("auth:passkey-registration:{}", user_id)
+}
+
+pub fn passkey_authentication_key(challenge_id: &str) -> String {
+    format!("auth:passkey-authentication:{}", challenge_id)
+}
+
+#[derive(FromRow)]
+pub struct Passkey {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    // Base64url encoded, as the authenticator reports it
+    pub credential_id: String,
+    // The serialized `webauthn_rs::prelude::Passkey` holding the public key
+    pub credential: String,
+    pub sign_count: u32,
+    // Space separated, e.g. "internal hybrid"
+    pub transports: Option<String>,
+    pub last_used_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+
+pub trait Repository {
+    async fn create(&self, passkey: &Passkey) -> Result<(), Error>;
+
+    async fn find_by_credential_id(&self, credential_id: &str) -> Result<Option<Passkey>, Error>;
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<Passkey>, Error>;
+
+    async fn update_credential(
+        &self,
+        passkey_id: &str,
+        credential: &str,
+        sign_count: u32,
+    ) -> Result<(), Error>;
+
+    async fn delete(&self, passkey_id: &str, user_id: &str) -> Result<(), Error>;
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error>;
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct FinishPasskeyRegistrationRequest {
+    #[validate(length(
+        min = 1,
+        max = 64,
+        message = "Name length must be between 1 and 64 characters."
+    ))]
+    pub name: String,
+    pub credential: RegisterPublicKeyCredential,
+}
+
+// A started sign-in, discoverable without an email or limited to that user's passkeys with one
+#[derive(Serialize, Deserialize)]
+pub enum PasskeyChallenge {
+    Discoverable(DiscoverableAuthentication),
+    User {
+        user_id: String,
+        authentication: PasskeyAuthentication,
+    },
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct StartPasskeyAuthenticationRequest {
+    #[validate(email(message = "Invalid email format. Please provide a valid email address."))]
+    pub email: Option<String>,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct FinishPasskeyAuthenticationRequest {
+    #[validate(length(min = 1, message = "Challenge id is required"))]
+    pub challenge_id: String,
+    pub credential: PublicKeyCredential,
+    #[serde(skip)]
+    pub device: Device,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct PasskeyAuthenticationResponse {
+    pub challenge_id: String,
+    // Passed as is to `navigator.credentials.get`
+    pub options: RequestChallengeResponse,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct PasskeyResponse {
+    pub id: String,
+    pub name: String,
+    pub transports: Vec<String>,
+    pub last_used_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+
+impl From<Passkey> for PasskeyResponse {
+    fn from(passkey: Passkey) -> Self {
+        PasskeyResponse {
+            id: passkey.id,
+            name: passkey.name,
+            transports: passkey
+                .transports
+                .map(|transports| transports.split(' ').map(String::from).collect())
+                .unwrap_or_default(),
+            last_used_at: passkey.last_used_at,
+            created_at: passkey.created_at,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use sqlx::FromRow;
+
+pub const PERMISSION_USER_MANAGE: &str = "user:manage";
+pub const PERMISSION_ROLE_MANAGE: &str = "role:manage";
+pub const PERMISSION_ROLE_ASSIGN: &str = "role:assign";
+
+pub const CACHE_ROLE_PERMISSIONS: &str = "role_permissions";
+
+// Permission names granted to a role as cached for `middleware::auth`
+pub fn role_permissions_key(role: &str) -> String {
+    format!("auth:role-permissions:{}", role)
+}
+
+#[derive(FromRow)]
+pub struct Permission {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub created_at: DateTime<Local>,
+}
+
+pub trait Repository {
+    async fn find_all(&self) -> Result<Vec<Permission>, Error>;
+
+    async fn find_by_name(&self, name: &str) -> Result<Option<Permission>, Error>;
+
+    async fn find_all_by_role_id(&self, role_id: &str) -> Result<Vec<Permission>, Error>;
+
+    async fn find_all_by_role_name(&self, role: &str) -> Result<Vec<Permission>, Error>;
+
+    async fn grant(&self, role_id: &str, permission_id: &str) -> Result<(), Error>;
+
+    async fn revoke(&self, role_id: &str, permission_id: &str) -> Result<(), Error>;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct PermissionResponse {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub created_at: DateTime<Local>,
+}
+
+impl From<Permission> for PermissionResponse {
+    fn from(permission: Permission) -> Self {
+        Self {
+            id: permission.id,
+            name: permission.name,
+            description: permission.description,
+            created_at: permission.created_at,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::{Validate, ValidationError};
+
+// Lets `middleware::auth` tell these tokens apart from JWTs without decoding them
+pub const TOKEN_PREFIX: &str = "sipdah_pat_";
+
+// A scope grants `read` (GET) or `write` (anything else) on one of the resources below
+pub const SCOPES: &[&str] = &["user:read", "user:write", "roles:read", "roles:write"];
+
+// Route prefixes and the resource they belong to, routes not listed here refuse every token
+pub const SCOPED_ROUTES: &[(&str, &str)] = &[
+    ("/api/v1/user", "user"),
+    ("/api/v1/users", "user"),
+    ("/api/v1/roles", "roles"),
+    ("/api/v1/permissions", "roles"),
+];
+
+#[derive(FromRow)]
+pub struct PersonalAccessToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub token_hash: String,
+    // Space separated, as in OAuth
+    pub scopes: String,
+    pub expires_at: DateTime<Local>,
+    pub last_used_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+
+impl PersonalAccessToken {
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes.split_whitespace().map(String::from).collect()
+    }
+}
+
+pub trait Repository {
+    async fn create(&self, token: &PersonalAccessToken) -> Result<(), Error>;
+
+    async fn find_by_token_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PersonalAccessToken>, Error>;
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<PersonalAccessToken>, Error>;
+
+    async fn update_last_used_at(&self, token_id: &str) -> Result<(), Error>;
+
+    async fn delete(&self, token_id: &str, user_id: &str) -> Result<(), Error>;
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error>;
+}
+
+pub trait Service {
+    async fn create(
+        &self,
+        req: &CreatePersonalAccessTokenRequest,
+    ) -> Result<CreatedPersonalAccessTokenResponse, Error>;
+
+    async fn get_all(&self) -> Result<Vec<PersonalAccessTokenResponse>, Error>;
+
+    async fn revoke(&self, token_id: &str) -> Result<(), Error>;
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    if scopes.iter().all(|scope| SCOPES.contains(&scope.as_str())) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("scopes")
+            .with_message(format!("Scopes must be any of {}", SCOPES.join(", ")).into()))
+    }
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct CreatePersonalAccessTokenRequest {
+    #[validate(length(
+        min = 1,
+        max = 64,
+        message = "Name length must be between 1 and 64 characters."
+    ))]
+    pub name: String,
+    #[validate(
+        length(min = 1, message = "At least one scope is required"),
+        custom(function = "validate_scopes")
+    )]
+    pub scopes: Vec<String>,
+    #[validate(range(min = 1, max = 365, message = "Expiry must be between 1 and 365 days."))]
+    pub expires_in_days: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct PersonalAccessTokenResponse {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: DateTime<Local>,
+    pub last_used_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+
+impl From<PersonalAccessToken> for PersonalAccessTokenResponse {
+    fn from(token: PersonalAccessToken) -> Self {
+        Self {
+            scopes: token.scope_list(),
+            id: token.id,
+            name: token.name,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+            created_at: token.created_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct CreatedPersonalAccessTokenResponse {
+    // Only returned here, the server keeps nothing but its hash
+    pub token: String,
+    #[serde(flatten)]
+    pub details: PersonalAccessTokenResponse,
+}
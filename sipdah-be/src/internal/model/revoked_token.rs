@@ -0,0 +1,8 @@
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+
+pub trait Repository {
+    async fn revoke(&self, token_id: &str, expires_at: DateTime<Local>) -> Result<(), Error>;
+
+    async fn is_revoked(&self, token_id: &str) -> Result<bool, Error>;
+}
@@ -1,10 +1,22 @@
 use crate::internal::model::error::Error;
+use crate::internal::model::permission::PermissionResponse;
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use validator::Validate;
 
 pub const ROLE_USER: &str = "USER";
+pub const ROLE_SUPER_ADMIN: &str = "SUPER_ADMIN";
+
+// Allowed through `middleware::require_roles` on routes no permission covers
+pub const SUPER_ADMIN_ROLES: &[&str] = &[ROLE_SUPER_ADMIN];
+
+pub const CACHE_USER_ROLES: &str = "user_roles";
+
+// Role names of a user as cached for `middleware::auth`
+pub fn user_roles_key(user_id: &str) -> String {
+    format!("auth:user-roles:{}", user_id)
+}
 
 #[derive(FromRow)]
 pub struct Role {
@@ -14,6 +26,13 @@ pub struct Role {
     pub updated_at: DateTime<Local>
 }
 
+// A role name next to the user holding it, for loading the roles of many users at once
+#[derive(FromRow)]
+pub struct UserRole {
+    pub user_id: String,
+    pub name: String
+}
+
 pub trait Repository {
     async fn create(&self, role: &Role) -> Result<(), Error>;
 
@@ -23,9 +42,15 @@ pub trait Repository {
 
     async fn find_by_name(&self, name: &str) -> Result<Option<Role>, Error>;
 
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<Role>, Error>;
+
+    async fn find_all_by_user_ids(&self, user_ids: &[String]) -> Result<Vec<UserRole>, Error>;
+
     async fn exists_by_name(&self, name: &str) -> Result<bool, Error>;
 
     async fn add(&self, user_id: &str, role_id: &str) -> Result<(), Error>;
+
+    async fn remove(&self, user_id: &str, role_id: &str) -> Result<(), Error>;
 }
 
 pub trait Service {
@@ -34,6 +59,14 @@ pub trait Service {
     async fn find_by_id(&self, role_id: &str) -> Result<RoleResponse, Error>;
 
     async fn find_all(&self) -> Result<Vec<RoleResponse>, Error>;
+
+    async fn find_all_permissions(&self) -> Result<Vec<PermissionResponse>, Error>;
+
+    async fn find_permissions(&self, role_id: &str) -> Result<Vec<PermissionResponse>, Error>;
+
+    async fn grant_permission(&self, role_id: &str, permission: &str) -> Result<(), Error>;
+
+    async fn revoke_permission(&self, role_id: &str, permission: &str) -> Result<(), Error>;
 }
 
 #[derive(Validate, Deserialize)]
@@ -0,0 +1,44 @@
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+// A signed-in device, tracking its newest refresh and access tokens so either can be cut off
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub refresh_token_id: String,
+    pub access_token_id: String,
+    pub access_token_expires_at: DateTime<Local>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Local>,
+    pub last_used_at: DateTime<Local>,
+    // Set when an administrator opened the session to act as `user_id`
+    #[serde(default)]
+    pub actor_id: Option<String>,
+}
+
+pub trait Repository {
+    async fn save(&self, session: &Session) -> Result<(), Error>;
+
+    async fn find_by_id(&self, session_id: &str) -> Result<Option<Session>, Error>;
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<Session>, Error>;
+
+    async fn delete(&self, session: &Session) -> Result<(), Error>;
+
+    // Marks a refresh token as used, false when it had already been used before
+    async fn consume_refresh_token(&self, refresh_token_id: &str) -> Result<bool, Error>;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct SessionResponse {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub current: bool,
+    pub created_at: DateTime<Local>,
+    pub last_used_at: DateTime<Local>,
+}
@@ -0,0 +1,76 @@
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use validator::Validate;
+
+#[derive(FromRow)]
+pub struct TwoFactor {
+    pub user_id: String,
+    // Base32 encoded TOTP secret, the repository encrypts it at rest
+    pub secret: String,
+    // Enrollment is pending until the first code is confirmed
+    pub enabled_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+}
+
+#[derive(FromRow)]
+pub struct RecoveryCode {
+    pub id: String,
+    pub user_id: String,
+    pub code_hash: String,
+    pub used_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+
+pub trait Repository {
+    async fn save(&self, two_factor: &TwoFactor) -> Result<(), Error>;
+
+    async fn find_by_user_id(&self, user_id: &str) -> Result<Option<TwoFactor>, Error>;
+
+    async fn delete_by_user_id(&self, user_id: &str) -> Result<(), Error>;
+
+    async fn create_recovery_code(&self, code: &RecoveryCode) -> Result<(), Error>;
+
+    async fn find_unused_recovery_code(
+        &self,
+        user_id: &str,
+        code_hash: &str,
+    ) -> Result<Option<RecoveryCode>, Error>;
+
+    async fn use_recovery_code(&self, code_id: &str) -> Result<(), Error>;
+
+    async fn delete_recovery_codes_by_user_id(&self, user_id: &str) -> Result<(), Error>;
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct SetupTwoFactorRequest {
+    #[validate(length(min = 1, message = "Challenge token is required"))]
+    pub challenge_token: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct TwoFactorCodeRequest {
+    #[validate(length(
+        min = 6,
+        max = 16,
+        message = "Code length must be between 6 and 16 characters."
+    ))]
+    pub code: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct TwoFactorEnrollmentResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
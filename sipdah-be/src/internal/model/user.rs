@@ -1,9 +1,43 @@
 use crate::internal::model::error::Error;
+use crate::internal::model::file::{file_url, FileResponse, FileUpload};
+use crate::internal::model::login_event::LoginEventResponse;
+use crate::internal::model::oidc::UserIdentityResponse;
+use crate::internal::model::passkey::PasskeyResponse;
+use crate::internal::model::personal_access_token::PersonalAccessTokenResponse;
+use crate::internal::model::session::SessionResponse;
+use crate::internal::model::web::{PageRequest, PageResponse};
 use chrono::DateTime;
 use chrono::Local;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
-use validator::Validate;
+use validator::{Validate, ValidationError};
+
+pub const CACHE_USER: &str = "user";
+
+pub const STATUS_ACTIVE: &str = "active";
+pub const STATUS_DEACTIVATED: &str = "deactivated";
+pub const STATUS_DELETED: &str = "deleted";
+
+pub const SORT_CREATED_AT: &str = "created_at";
+pub const SORT_NAME: &str = "name";
+pub const SORT_EMAIL: &str = "email";
+
+pub fn user_detail_key(user_id: &str) -> String {
+    format!("user:detail:{}", user_id)
+}
+
+pub fn account_restore_key(token: &str) -> String {
+    format!("auth:account-restore:{}", token)
+}
+
+// A requested address waits here until the link mailed to it is opened
+pub fn pending_email_key(user_id: &str) -> String {
+    format!("user:pending-email:{}", user_id)
+}
+
+pub fn reauthentication_failures_key(user_id: &str) -> String {
+    format!("user:reauthentication-failures:{}", user_id)
+}
 
 #[derive(FromRow, Serialize)]
 pub struct User {
@@ -12,22 +46,59 @@ pub struct User {
     pub password: String,
     pub name: String,
     pub phone_number: Option<String>,
-    pub photo_url: Option<String>,
+    pub photo_id: Option<String>,
+    pub email_verified_at: Option<DateTime<Local>>,
     pub created_at: DateTime<Local>,
     pub updated_at: DateTime<Local>,
+    pub deactivated_at: Option<DateTime<Local>>,
     pub deleted_at: Option<DateTime<Local>>,
 }
 
 pub trait Repository {
     async fn create(&self, user: &User) -> Result<(), Error>;
 
+    // Deactivated and deleted users are treated as absent, as they are by `find_by_email`
     async fn find_by_id(&self, user_id: &str) -> Result<Option<User>, Error>;
 
+    // Also finds deactivated and deleted users, for the code paths that bring them back
+    async fn find_any_by_id(&self, user_id: &str) -> Result<Option<User>, Error>;
+
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, Error>;
 
+    // A page of users after `cursor` in the order the filter asks for
+    async fn find_all(
+        &self,
+        filter: &UserFilter,
+        cursor: Option<&UserCursor>,
+        limit: u32,
+    ) -> Result<Vec<User>, Error>;
+
+    // Deleted users not anonymized yet
+    async fn find_all_deleted_before(
+        &self,
+        deleted_before: DateTime<Local>,
+    ) -> Result<Vec<User>, Error>;
+
     async fn exists_by_email(&self, email: &str) -> Result<bool, Error>;
 
-    async fn exists_by_id(&self, id: &str) -> Result<bool, Error>;
+    async fn update_password(&self, user_id: &str, password: &str) -> Result<(), Error>;
+
+    async fn verify_email(&self, user_id: &str) -> Result<(), Error>;
+
+    async fn update_email(&self, user_id: &str, email: &str) -> Result<(), Error>;
+
+    async fn update_profile(&self, user: &User) -> Result<(), Error>;
+
+    async fn deactivate(&self, user_id: &str) -> Result<(), Error>;
+
+    async fn reactivate(&self, user_id: &str) -> Result<(), Error>;
+
+    async fn delete(&self, user_id: &str) -> Result<(), Error>;
+
+    async fn restore(&self, user_id: &str) -> Result<(), Error>;
+
+    // Replaces every personal field, the row itself stays for the records pointing to it
+    async fn anonymize(&self, user_id: &str) -> Result<(), Error>;
 }
 
 pub trait Service {
@@ -35,24 +106,222 @@ pub trait Service {
 
     async fn get_current(&self) -> Result<UserResponse, Error>;
 
-    async fn add_roles(&self, req: AddRolesRequest) -> Result<UserResponse, Error>;
+    async fn get_all(
+        &self,
+        page: PageRequest,
+        filter: UserFilter,
+    ) -> Result<PageResponse<UserResponse>, Error>;
+
+    async fn update_roles(&self, req: UpdateRolesRequest) -> Result<UserResponse, Error>;
+
+    async fn replace_roles(&self, req: ReplaceRolesRequest) -> Result<UserResponse, Error>;
+
+    async fn remove_role(&self, user_id: &str, role_id: &str) -> Result<UserResponse, Error>;
+
+    async fn change_password(&self, req: ChangePasswordRequest) -> Result<(), Error>;
+
+    async fn change_email(&self, req: ChangeEmailRequest) -> Result<UserResponse, Error>;
+
+    async fn update_profile(&self, req: UpdateProfileRequest) -> Result<UserResponse, Error>;
+
+    async fn get_login_history(&self) -> Result<Vec<LoginEventResponse>, Error>;
+
+    async fn get_login_history_by_user_id(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<LoginEventResponse>, Error>;
+
+    async fn delete_current(&self, req: DeleteAccountRequest) -> Result<(), Error>;
+
+    async fn deactivate(&self, user_id: &str) -> Result<(), Error>;
+
+    async fn reactivate(&self, user_id: &str) -> Result<(), Error>;
+
+    async fn export(&self) -> Result<UserExport, Error>;
+
+    // Anonymizes the accounts whose grace period has ended, run in the background
+    async fn anonymize_deleted(&self) -> Result<usize, Error>;
 }
 
-#[derive(Serialize)]
-#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+// Also kept in the cache, so it has to read back what it writes
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct UserResponse {
     pub id: String,
     pub email: String,
     pub name: String,
     pub phone_number: Option<String>,
     pub photo_url: Option<String>,
+    pub email_verified_at: Option<DateTime<Local>>,
     pub created_at: DateTime<Local>,
     pub updated_at: DateTime<Local>,
+    // Role names, filled in by the service since they live in their own table
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Only ever set in the admin directory, the other lookups skip inactive users
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deactivated_at: Option<DateTime<Local>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Local>>,
 }
 
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            photo_url: user.photo_id.as_deref().map(file_url),
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            phone_number: user.phone_number,
+            email_verified_at: user.email_verified_at,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            roles: Vec::new(),
+            deactivated_at: user.deactivated_at,
+            deleted_at: user.deleted_at,
+        }
+    }
+}
+
+// Query of `GET /api/v1/users`, every field narrows the result further
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct UserFilter {
+    // Prefix of the name or the email
+    #[validate(length(max = 64, message = "Search must not be longer than 64 characters."))]
+    pub search: Option<String>,
+    pub role: Option<String>,
+    // active, deactivated or deleted, active when left out
+    pub status: Option<String>,
+    pub created_from: Option<DateTime<Local>>,
+    pub created_to: Option<DateTime<Local>>,
+    // created_at, name or email
+    pub sort: Option<String>,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+impl UserFilter {
+    pub fn status(&self) -> &str {
+        self.status.as_deref().unwrap_or(STATUS_ACTIVE)
+    }
+
+    pub fn sort(&self) -> &str {
+        self.sort.as_deref().unwrap_or(SORT_CREATED_AT)
+    }
+}
+
+// Position after the last user of a page, UUIDv7 ids keep creation order and break ties
+#[derive(Serialize, Deserialize)]
+pub struct UserCursor {
+    pub sort: String,
+    pub descending: bool,
+    // Sort value of the last user, left out when sorting by creation
+    pub key: Option<String>,
+    pub id: String,
+}
+
+// Role ids to assign and to take away, roles already in that state are skipped
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct UpdateRolesRequest {
+    #[serde(skip)]
+    pub user_id: String,
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+// The complete set of role ids the user ends up with
 #[derive(Validate, Deserialize)]
 #[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
-pub struct AddRolesRequest {
+pub struct ReplaceRolesRequest {
+    #[serde(skip)]
     pub user_id: String,
     pub roles: Vec<String>,
 }
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1, message = "Current password is required."))]
+    pub current_password: String,
+    #[validate(length(
+        min = 6,
+        max = 128,
+        message = "Password length must be between 6 and 128 characters."
+    ))]
+    pub new_password: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct ChangeEmailRequest {
+    #[validate(
+        email(message = "Invalid email format. Please provide a valid email address."),
+        length(
+            min = 1,
+            max = 64,
+            message = "Email length must be between 1 and 64 characters."
+        )
+    )]
+    pub email: String,
+    #[validate(length(min = 1, message = "Current password is required."))]
+    pub current_password: String,
+}
+
+#[derive(Validate, Deserialize)]
+#[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
+pub struct DeleteAccountRequest {
+    #[validate(length(min = 1, message = "Current password is required."))]
+    pub current_password: String,
+}
+
+// Everything kept about a user, as handed out by `GET /api/v1/user/export`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserExport {
+    pub exported_at: DateTime<Local>,
+    pub profile: UserResponse,
+    pub two_factor_enabled: bool,
+    pub identities: Vec<UserIdentityResponse>,
+    pub passkeys: Vec<PasskeyResponse>,
+    pub personal_access_tokens: Vec<PersonalAccessTokenResponse>,
+    pub sessions: Vec<SessionResponse>,
+    pub login_history: Vec<LoginEventResponse>,
+    pub files: Vec<FileResponse>,
+}
+
+// Empty clears the number, otherwise digits with the usual separators and an optional leading +
+fn validate_phone_number(phone_number: &str) -> Result<(), ValidationError> {
+    let digits = phone_number.chars().filter(|c| c.is_ascii_digit()).count();
+    let is_valid = phone_number.is_empty()
+        || (phone_number.len() <= 20
+            && (7..=15).contains(&digits)
+            && phone_number.char_indices().all(|(i, c)| {
+                c.is_ascii_digit() || matches!(c, ' ' | '-' | '(' | ')') || (c == '+' && i == 0)
+            }));
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("phone_number").with_message(
+            "Phone number must have 7 to 15 digits and at most 20 characters.".into(),
+        ))
+    }
+}
+
+// Fields left out stay as they are, sent as multipart so the avatar can come along
+#[derive(Validate, Default)]
+pub struct UpdateProfileRequest {
+    #[validate(length(
+        min = 1,
+        max = 64,
+        message = "Name length must be between 1 and 64 characters."
+    ))]
+    pub name: Option<String>,
+    #[validate(custom(function = "validate_phone_number"))]
+    pub phone_number: Option<String>,
+    pub avatar: Option<FileUpload>,
+}
@@ -1,31 +1,54 @@
+use crate::internal::model::error::Error;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+const DEFAULT_PAGE_SIZE: u32 = 10;
+const MAX_PAGE_SIZE: u32 = 100;
+
 #[derive(Serialize, Deserialize)]
 pub struct PageRequest {
     pub cursor: Option<String>,
     pub size: Option<u32>,
 }
 
-// impl PageRequest {
-//     pub fn size(&self) -> u32 {
-//         if let Some(size) = self.size { size } else { 10 }
-//     }
-//
-//     pub fn cursor(&self) -> String {
-//         if let Some(cursor) = &self.cursor {
-//             cursor.clone()
-//         } else {
-//             id::new()
-//         }
-//     }
-// }
+impl PageRequest {
+    pub fn size(&self) -> u32 {
+        self.size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    // Reads back a cursor made by `encode_cursor`, `None` asks for the first page
+    pub fn cursor<T: DeserializeOwned>(&self) -> Result<Option<T>, Error> {
+        let cursor = match &self.cursor {
+            Some(cursor) => cursor,
+            None => return Ok(None),
+        };
+
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .map(Some)
+            .ok_or_else(|| Error::BadRequest("Cursor is not valid".to_string()))
+    }
+}
+
+// Opaque to clients, they only hand it back to get the next page
+pub fn encode_cursor<T: Serialize>(cursor: &T) -> Result<String, Error> {
+    let json = serde_json::to_vec(cursor).map_err(|err| Error::Internal(err.to_string()))?;
+
+    Ok(URL_SAFE_NO_PAD.encode(json))
+}
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all(serialize = "camelCase", deserialize = "snake_case"))]
 pub struct PageResponse<T> {
     pub data: Vec<T>,
-    pub next_cursor: Option<i64>,
-    pub size: i32,
+    pub next_cursor: Option<String>,
+    pub size: u32,
 }
 
 #[derive(Serialize)]
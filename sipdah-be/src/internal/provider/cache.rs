@@ -7,6 +7,15 @@ use serde::de::DeserializeOwned;
 use serde_json;
 use std::sync::Arc;
 
+// Later increments leave the expiry alone, so a window does not slide with every attempt
+const INCR_SCRIPT: &str = r#"
+    local count = redis.call("INCR", KEYS[1])
+    if count == 1 then
+        redis.call("EXPIRE", KEYS[1], ARGV[1])
+    end
+    return count
+"#;
+
 pub trait Cache {
     async fn set<T: Serialize>(&self, key: String, value: T) -> Result<(), Error>;
 
@@ -14,7 +23,27 @@ pub trait Cache {
 
     async fn get<T: DeserializeOwned>(&self, key: String) -> Result<Option<T>, Error>;
 
+    // Sets the key only when it does not exist yet, returning whether it was set
+    async fn setnx<T: Serialize>(
+        &self,
+        key: String,
+        value: T,
+        ttl: Duration,
+    ) -> Result<bool, Error>;
+
     async fn del(&self, key: String) -> Result<(), Error>;
+
+    // Reads and deletes the key atomically, for values that may only be used once
+    async fn take<T: DeserializeOwned>(&self, key: String) -> Result<Option<T>, Error>;
+
+    // Increments a counter whose expiry only the first increment sets, returning the new value
+    async fn incr(&self, key: String, ttl: Duration) -> Result<i64, Error>;
+
+    async fn sadd(&self, key: String, member: String, ttl: Duration) -> Result<(), Error>;
+
+    async fn srem(&self, key: String, member: String) -> Result<(), Error>;
+
+    async fn smembers(&self, key: String) -> Result<Vec<String>, Error>;
 }
 
 pub struct Redis {
@@ -70,6 +99,33 @@ impl Cache for Redis {
         }
     }
 
+    async fn setnx<T: Serialize>(
+        &self,
+        key: String,
+        value: T,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+        let serialized =
+            serde_json::to_string(&value).map_err(|err| Error::Internal(err.to_string()))?;
+
+        let reply: Option<String> = cmd("SET")
+            .arg(key)
+            .arg(serialized)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl.num_seconds())
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        Ok(reply.is_some())
+    }
+
     async fn del(&self, key: String) -> Result<(), Error> {
         let mut conn = self
             .pool
@@ -85,4 +141,196 @@ impl Cache for Redis {
 
         Ok(())
     }
+
+    async fn take<T: DeserializeOwned>(&self, key: String) -> Result<Option<T>, Error> {
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+        let value: Option<String> = cmd("GETDEL")
+            .arg(key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        value
+            .map(|serialized| serde_json::from_str::<T>(serialized.as_str()))
+            .transpose()
+            .map_err(|err| Error::Internal(err.to_string()))
+    }
+
+    async fn incr(&self, key: String, ttl: Duration) -> Result<i64, Error> {
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        let count: i64 = cmd("EVAL")
+            .arg(INCR_SCRIPT)
+            .arg(1)
+            .arg(&key)
+            .arg(ttl.num_seconds())
+            .query_async(&mut conn)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        Ok(count)
+    }
+
+    async fn sadd(&self, key: String, member: String, ttl: Duration) -> Result<(), Error> {
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        deadpool_redis::redis::pipe()
+            .atomic()
+            .cmd("SADD")
+            .arg(&key)
+            .arg(member)
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(ttl.num_seconds())
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn srem(&self, key: String, member: String) -> Result<(), Error> {
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        cmd("SREM")
+            .arg(key)
+            .arg(member)
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn smembers(&self, key: String) -> Result<Vec<String>, Error> {
+        let mut conn = self
+            .pool
+            .get()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        cmd("SMEMBERS")
+            .arg(key)
+            .query_async::<Vec<String>>(&mut conn)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))
+    }
+}
+
+// Stands in for Redis in tests, keys never expire
+#[cfg(test)]
+#[derive(Default)]
+pub struct Memory {
+    values: std::sync::Mutex<std::collections::HashMap<String, String>>,
+    sets: std::sync::Mutex<std::collections::HashMap<String, Vec<String>>>,
+}
+
+#[cfg(test)]
+impl Cache for Memory {
+    async fn set<T: Serialize>(&self, key: String, value: T) -> Result<(), Error> {
+        self.setx(key, value, Duration::zero()).await
+    }
+
+    async fn setx<T: Serialize>(&self, key: String, value: T, _: Duration) -> Result<(), Error> {
+        let serialized =
+            serde_json::to_string(&value).map_err(|err| Error::Internal(err.to_string()))?;
+        self.values.lock().unwrap().insert(key, serialized);
+
+        Ok(())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, key: String) -> Result<Option<T>, Error> {
+        Ok(self
+            .values
+            .lock()
+            .unwrap()
+            .get(&key)
+            .and_then(|value| serde_json::from_str(value).ok()))
+    }
+
+    async fn setnx<T: Serialize>(&self, key: String, value: T, _: Duration) -> Result<bool, Error> {
+        let serialized =
+            serde_json::to_string(&value).map_err(|err| Error::Internal(err.to_string()))?;
+        let mut values = self.values.lock().unwrap();
+        if values.contains_key(&key) {
+            return Ok(false);
+        }
+        values.insert(key, serialized);
+
+        Ok(true)
+    }
+
+    async fn del(&self, key: String) -> Result<(), Error> {
+        self.values.lock().unwrap().remove(&key);
+        self.sets.lock().unwrap().remove(&key);
+
+        Ok(())
+    }
+
+    async fn take<T: DeserializeOwned>(&self, key: String) -> Result<Option<T>, Error> {
+        Ok(self
+            .values
+            .lock()
+            .unwrap()
+            .remove(&key)
+            .and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    async fn incr(&self, key: String, _: Duration) -> Result<i64, Error> {
+        let mut values = self.values.lock().unwrap();
+        let count = values
+            .get(&key)
+            .and_then(|value| value.parse::<i64>().ok())
+            .unwrap_or(0)
+            + 1;
+        values.insert(key, count.to_string());
+
+        Ok(count)
+    }
+
+    async fn sadd(&self, key: String, member: String, _: Duration) -> Result<(), Error> {
+        let mut sets = self.sets.lock().unwrap();
+        let members = sets.entry(key).or_default();
+        if !members.contains(&member) {
+            members.push(member);
+        }
+
+        Ok(())
+    }
+
+    async fn srem(&self, key: String, member: String) -> Result<(), Error> {
+        if let Some(members) = self.sets.lock().unwrap().get_mut(&key) {
+            members.retain(|existing| *existing != member);
+        }
+
+        Ok(())
+    }
+
+    async fn smembers(&self, key: String) -> Result<Vec<String>, Error> {
+        Ok(self
+            .sets
+            .lock()
+            .unwrap()
+            .get(&key)
+            .cloned()
+            .unwrap_or_default())
+    }
 }
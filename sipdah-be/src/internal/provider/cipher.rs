@@ -0,0 +1,71 @@
+use crate::config::Config;
+use crate::internal::model::error::Error;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+
+// Marks values written by `encrypt`, with room for another scheme later on
+const PREFIX: &str = "v1:";
+const NONCE_LEN: usize = 12;
+
+pub trait Cipher {
+    fn encrypt(&self, plaintext: &str) -> Result<String, Error>;
+
+    fn decrypt(&self, ciphertext: &str) -> Result<String, Error>;
+}
+
+pub struct Aes {
+    cipher: Aes256Gcm,
+}
+
+impl Aes {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let key = STANDARD
+            .decode(&config.encryption_key)
+            .ok()
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| {
+                Error::Internal("Encryption key must be 32 bytes encoded as base64".to_string())
+            })?;
+
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+impl Cipher for Aes {
+    fn encrypt(&self, plaintext: &str) -> Result<String, Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+
+        Ok(format!("{}{}", PREFIX, URL_SAFE_NO_PAD.encode(payload)))
+    }
+
+    fn decrypt(&self, ciphertext: &str) -> Result<String, Error> {
+        let payload = ciphertext
+            .strip_prefix(PREFIX)
+            .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+            .filter(|payload| payload.len() > NONCE_LEN)
+            .ok_or_else(|| Error::Internal("Encrypted value is malformed".to_string()))?;
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        String::from_utf8(plaintext).map_err(|err| Error::Internal(err.to_string()))
+    }
+}
@@ -0,0 +1,173 @@
+use crate::config::Config;
+use crate::internal::model::error::Error;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+
+// DER header of an Ed25519 SubjectPublicKeyInfo: a sequence holding the id-Ed25519 algorithm
+// (1.3.101.112) and a 33 byte bit string, which is followed by the raw 32 byte key
+const ED25519_SPKI_HEADER: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+const ED25519_KEY_LEN: usize = 32;
+
+struct Key {
+    algorithm: Algorithm,
+    encoding_key: Option<EncodingKey>,
+    decoding_key: DecodingKey,
+    jwk: Jwk,
+}
+
+pub struct Keyring {
+    signing_key_id: String,
+    keys: HashMap<String, Key>,
+}
+
+impl Keyring {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let mut keys = HashMap::new();
+
+        for key in &config.jwt_keys {
+            let public_pem = fs::read(&key.public_key_path)
+                .map_err(|err| Error::Internal(format!("{}: {}", key.public_key_path, err)))?;
+            let private_pem = match &key.private_key_path {
+                Some(path) => Some(
+                    fs::read(path).map_err(|err| Error::Internal(format!("{}: {}", path, err)))?,
+                ),
+                None => None,
+            };
+
+            let (algorithm, parameters, encoding_key) = match key.algorithm.as_str() {
+                "RS256" => {
+                    let public_key =
+                        RsaPublicKey::from_public_key_pem(&String::from_utf8_lossy(&public_pem))
+                            .map_err(|err| Error::Internal(err.to_string()))?;
+                    let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+                        e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+                    });
+                    let encoding_key = private_pem
+                        .map(|pem| EncodingKey::from_rsa_pem(&pem))
+                        .transpose()
+                        .map_err(|err| Error::Internal(err.to_string()))?;
+
+                    (Algorithm::RS256, parameters, encoding_key)
+                }
+                "EdDSA" => {
+                    let public_key =
+                        pem::parse(&public_pem).map_err(|err| Error::Internal(err.to_string()))?;
+                    let raw_key = Some(public_key.contents())
+                        .filter(|_| public_key.tag() == "PUBLIC KEY")
+                        .filter(|der| der.len() == ED25519_SPKI_HEADER.len() + ED25519_KEY_LEN)
+                        .and_then(|der| der.strip_prefix(ED25519_SPKI_HEADER.as_slice()))
+                        .ok_or_else(|| {
+                            Error::Internal(format!("Key {} is not an Ed25519 key", key.kid))
+                        })?;
+                    let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(raw_key),
+                    });
+                    let encoding_key = private_pem
+                        .map(|pem| EncodingKey::from_ed_pem(&pem))
+                        .transpose()
+                        .map_err(|err| Error::Internal(err.to_string()))?;
+
+                    (Algorithm::EdDSA, parameters, encoding_key)
+                }
+                algorithm => {
+                    return Err(Error::Internal(format!(
+                        "Key {} uses unsupported algorithm {}",
+                        key.kid, algorithm
+                    )));
+                }
+            };
+
+            let jwk = Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    key_algorithm: Some(match algorithm {
+                        Algorithm::RS256 => KeyAlgorithm::RS256,
+                        _ => KeyAlgorithm::EdDSA,
+                    }),
+                    key_id: Some(key.kid.clone()),
+                    ..Default::default()
+                },
+                algorithm: parameters,
+            };
+            let decoding_key =
+                DecodingKey::from_jwk(&jwk).map_err(|err| Error::Internal(err.to_string()))?;
+
+            keys.insert(
+                key.kid.clone(),
+                Key {
+                    algorithm,
+                    encoding_key,
+                    decoding_key,
+                    jwk,
+                },
+            );
+        }
+
+        let can_sign = keys
+            .get(&config.jwt_signing_key_id)
+            .is_some_and(|key| key.encoding_key.is_some());
+        if !can_sign {
+            return Err(Error::Internal(format!(
+                "Signing key {} is missing or has no private key",
+                config.jwt_signing_key_id
+            )));
+        }
+
+        Ok(Self {
+            signing_key_id: config.jwt_signing_key_id.clone(),
+            keys,
+        })
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, Error> {
+        let key = &self.keys[&self.signing_key_id];
+        let encoding_key = key
+            .encoding_key
+            .as_ref()
+            .ok_or_else(|| Error::Internal("Signing key has no private key".to_string()))?;
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.signing_key_id.clone());
+
+        jsonwebtoken::encode(&header, claims, encoding_key)
+            .map_err(|err| Error::Internal(err.to_string()))
+    }
+
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> jsonwebtoken::errors::Result<T> {
+        let header = jsonwebtoken::decode_header(token)?;
+        // The algorithm comes from our own key, never from the token header
+        let key = header
+            .kid
+            .and_then(|kid| self.keys.get(&kid))
+            .ok_or(ErrorKind::InvalidToken)?;
+
+        jsonwebtoken::decode::<T>(token, &key.decoding_key, &Validation::new(key.algorithm))
+            .map(|data| data.claims)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys: Vec<Jwk> = self.keys.values().map(|key| key.jwk.clone()).collect();
+        keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+
+        JwkSet { keys }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::config::Config;
+use crate::internal::common::id;
+use crate::internal::model::error::Error;
+use chrono::{DateTime, Local};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[derive(Serialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer {
+    // Sendable so delivery can be moved off the request with `spawn_send`
+    fn send(&self, mail: &Mail) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+// Delivers in the background, a slow or failing mail server must not hold up or fail a request
+pub fn spawn_send<M>(mailer: Arc<M>, mail: Mail)
+where
+    M: Mailer + Send + Sync + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(err) = mailer.send(&mail).await {
+            warn!(
+                "Failed to send \"{}\" to {}: {}",
+                mail.subject, mail.to, err
+            );
+        }
+    });
+}
+
+pub struct Smtp {
+    config: Arc<Config>,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl Smtp {
+    pub fn new(config: Arc<Config>) -> Result<Self, Error> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(&config.smtp_url)
+            .map_err(|err| Error::Internal(err.to_string()))?
+            .build();
+
+        Ok(Self { config, transport })
+    }
+}
+
+impl Mailer for Smtp {
+    async fn send(&self, mail: &Mail) -> Result<(), Error> {
+        let from: Mailbox = self
+            .config
+            .mail_from
+            .parse()
+            .map_err(|_| Error::Internal("MAIL_FROM is not a valid mailbox".to_string()))?;
+        let to: Mailbox = mail
+            .to
+            .parse()
+            .map_err(|_| Error::BadRequest(format!("{} is not a valid mailbox", mail.to)))?;
+
+        let message = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        Ok(())
+    }
+}
+
+// Writes every mail as a JSON file instead of delivering it, for local development and tests
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+#[derive(Serialize)]
+struct OutboxEntry<'a> {
+    #[serde(flatten)]
+    mail: &'a Mail,
+    sent_at: DateTime<Local>,
+}
+
+impl Outbox {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            dir: PathBuf::from(&config.outbox_dir),
+        }
+    }
+}
+
+impl Mailer for Outbox {
+    async fn send(&self, mail: &Mail) -> Result<(), Error> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        let path = self.dir.join(format!("{}.json", id::new()));
+        let entry = OutboxEntry {
+            mail,
+            sent_at: Local::now(),
+        };
+        let content =
+            serde_json::to_vec_pretty(&entry).map_err(|err| Error::Internal(err.to_string()))?;
+
+        tokio::fs::write(&path, content)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        info!("Mail to {} written to {}", mail.to, path.display());
+
+        Ok(())
+    }
+}
+
+// The transport is picked from `MAILER` at startup
+pub enum Transport {
+    Smtp(Smtp),
+    Outbox(Outbox),
+}
+
+impl Transport {
+    pub fn new(config: Arc<Config>) -> Result<Self, Error> {
+        match config.mailer.as_str() {
+            "smtp" => Ok(Transport::Smtp(Smtp::new(config)?)),
+            "outbox" => Ok(Transport::Outbox(Outbox::new(config))),
+            mailer => Err(Error::Internal(format!("Unknown mailer {}", mailer))),
+        }
+    }
+}
+
+impl Mailer for Transport {
+    async fn send(&self, mail: &Mail) -> Result<(), Error> {
+        match self {
+            Transport::Smtp(smtp) => smtp.send(mail).await,
+            Transport::Outbox(outbox) => outbox.send(mail).await,
+        }
+    }
+}
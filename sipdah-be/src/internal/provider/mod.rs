@@ -1 +1,8 @@
-pub mod cache;
\ No newline at end of file
+pub mod cache;
+pub mod cipher;
+pub mod jwt;
+pub mod mailer;
+pub mod oidc;
+pub mod password;
+pub mod storage;
+pub mod webauthn;
\ No newline at end of file
@@ -0,0 +1,534 @@
+use crate::config::{Config, OidcProvider};
+use crate::internal::model::error::Error;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+
+// Provider keys rotate rarely, an unknown `kid` forces an early refresh anyway
+const DISCOVERY_TTL: Duration = Duration::from_secs(60 * 60);
+// Tokens with made up `kid`s must not turn into a stream of requests to the provider
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+// A provider that hangs would otherwise hold the sign-in request open indefinitely
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Clone)]
+struct Discovery {
+    metadata: Metadata,
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "bool_or_string")]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub nonce: Option<String>,
+}
+
+// Some providers send `email_verified` as the string "true"
+fn bool_or_string<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        String(String),
+    }
+
+    Ok(match Flag::deserialize(deserializer)? {
+        Flag::Bool(value) => value,
+        Flag::String(value) => value.eq_ignore_ascii_case("true"),
+    })
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    providers: HashMap<String, OidcProvider>,
+    discoveries: RwLock<HashMap<String, Discovery>>,
+    // Held while refetching so concurrent refreshes wait for one another instead of piling up
+    refresh_lock: Mutex<()>,
+}
+
+impl Client {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        Self::with_providers(&config.oidc_providers)
+    }
+
+    fn with_providers(providers: &[OidcProvider]) -> Result<Self, Error> {
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|err| Error::Internal(err.to_string()))?;
+        let providers = providers
+            .iter()
+            .map(|provider| (provider.id.clone(), provider.clone()))
+            .collect();
+
+        Ok(Self {
+            http,
+            providers,
+            discoveries: RwLock::new(HashMap::new()),
+            refresh_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn provider(&self, provider_id: &str) -> Result<&OidcProvider, Error> {
+        self.providers
+            .get(provider_id)
+            .ok_or_else(|| Error::NotFound(format!("Provider {} is not found", provider_id)))
+    }
+
+    pub async fn authorization_url(
+        &self,
+        provider_id: &str,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String, Error> {
+        let provider = self.provider(provider_id)?;
+        let discovery = self.discover(provider, false).await?;
+
+        let url = Url::parse_with_params(
+            &discovery.metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &provider.client_id),
+                ("redirect_uri", &provider.redirect_uri),
+                ("scope", &provider.scopes.join(" ")),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|err| Error::Internal(err.to_string()))?;
+
+        Ok(url.to_string())
+    }
+
+    // Redeems an authorization code for validated ID token claims, the caller checks the nonce
+    pub async fn exchange_code(
+        &self,
+        provider_id: &str,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<IdTokenClaims, Error> {
+        let provider = self.provider(provider_id)?;
+        let discovery = self.discover(provider, false).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &provider.redirect_uri),
+            ("client_id", &provider.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &provider.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let res = self
+            .http
+            .post(&discovery.metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+        if !res.status().is_success() {
+            return Err(Error::Unauthorized(
+                "Identity provider rejected the authorization code".to_string(),
+            ));
+        }
+
+        let token = res
+            .json::<TokenResponse>()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        self.validate(provider, discovery, &token.id_token).await
+    }
+
+    async fn validate(
+        &self,
+        provider: &OidcProvider,
+        mut discovery: Discovery,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, Error> {
+        let invalid = || Error::Unauthorized("ID token is not valid".to_string());
+
+        let header = jsonwebtoken::decode_header(id_token).map_err(|_| invalid())?;
+        // Symmetric algorithms would let anyone holding the client secret mint tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid());
+        }
+
+        let kid = header.kid.ok_or_else(invalid)?;
+        if discovery.jwks.find(&kid).is_none()
+            && discovery.fetched_at.elapsed() >= MIN_REFRESH_INTERVAL
+        {
+            discovery = self.discover(provider, true).await?;
+        }
+        let jwk = discovery.jwks.find(&kid).ok_or_else(invalid)?;
+        let decoding_key = DecodingKey::from_jwk(jwk).map_err(|_| invalid())?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&discovery.metadata.issuer]);
+        validation.set_audience(&[&provider.client_id]);
+
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| invalid())
+    }
+
+    async fn discover(&self, provider: &OidcProvider, refresh: bool) -> Result<Discovery, Error> {
+        let cached = match refresh {
+            true => None,
+            false => self.cached(provider, DISCOVERY_TTL).await,
+        };
+        if let Some(discovery) = cached {
+            return Ok(discovery);
+        }
+
+        let _guard = self.refresh_lock.lock().await;
+        // Another request may have refetched while this one was waiting for the lock
+        let max_age = match refresh {
+            true => MIN_REFRESH_INTERVAL,
+            false => DISCOVERY_TTL,
+        };
+        if let Some(discovery) = self.cached(provider, max_age).await {
+            return Ok(discovery);
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        );
+        let metadata = self.fetch::<Metadata>(&url).await?;
+        if metadata.issuer != provider.issuer {
+            return Err(Error::Internal(format!(
+                "Provider {} advertises issuer {}",
+                provider.id, metadata.issuer
+            )));
+        }
+        let jwks = self.fetch::<JwkSet>(&metadata.jwks_uri).await?;
+
+        let discovery = Discovery {
+            metadata,
+            jwks,
+            fetched_at: Instant::now(),
+        };
+        self.discoveries
+            .write()
+            .await
+            .insert(provider.id.clone(), discovery.clone());
+
+        Ok(discovery)
+    }
+
+    async fn cached(&self, provider: &OidcProvider, max_age: Duration) -> Option<Discovery> {
+        self.discoveries
+            .read()
+            .await
+            .get(&provider.id)
+            .filter(|discovery| discovery.fetched_at.elapsed() < max_age)
+            .cloned()
+    }
+
+    async fn fetch<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, Error> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|err| Error::Internal(err.to_string()))?
+            .json::<T>()
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::jwk::{
+        AlgorithmParameters, CommonParameters, Jwk, RSAKeyParameters, RSAKeyType,
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+    use rsa::pkcs1::EncodeRsaPrivateKey;
+    use rsa::pkcs8::LineEnding;
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+    use serde::Serialize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, OnceLock};
+
+    const CLIENT_ID: &str = "sipdah";
+    const CODE: &str = "code";
+    const CODE_VERIFIER: &str = "verifier";
+
+    // Generating RSA keys is slow in debug builds, so every test shares the same one
+    fn private_key() -> &'static RsaPrivateKey {
+        static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+        KEY.get_or_init(|| RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap())
+    }
+
+    fn jwk(kid: &str) -> Jwk {
+        let public_key = private_key().to_public_key();
+
+        Jwk {
+            common: CommonParameters {
+                key_id: Some(kid.to_string()),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+                e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+            }),
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Claims<'a> {
+        iss: &'a str,
+        aud: &'a str,
+        sub: &'a str,
+        email: &'a str,
+        email_verified: bool,
+        nonce: &'a str,
+        exp: i64,
+    }
+
+    fn id_token(issuer: &str, audience: &str, kid: &str) -> String {
+        let pem = private_key().to_pkcs1_pem(LineEnding::LF).unwrap();
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+
+        jsonwebtoken::encode(
+            &header,
+            &Claims {
+                iss: issuer,
+                aud: audience,
+                sub: "subject",
+                email: "user@example.com",
+                email_verified: true,
+                nonce: "nonce",
+                exp: chrono::Utc::now().timestamp() + 300,
+            },
+            &EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap(),
+        )
+        .unwrap()
+    }
+
+    // A minimal identity provider serving discovery, keys and the token endpoint
+    struct MockIdp {
+        issuer: String,
+        kids: std::sync::Mutex<Vec<String>>,
+        id_token: std::sync::Mutex<String>,
+        jwks_requests: AtomicUsize,
+    }
+
+    async fn metadata(State(idp): State<Arc<MockIdp>>) -> Response {
+        Json(serde_json::json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        }))
+        .into_response()
+    }
+
+    async fn jwks(State(idp): State<Arc<MockIdp>>) -> Response {
+        idp.jwks_requests.fetch_add(1, Ordering::SeqCst);
+        let keys = idp
+            .kids
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|kid| jwk(kid))
+            .collect();
+
+        Json(JwkSet { keys }).into_response()
+    }
+
+    async fn token(
+        State(idp): State<Arc<MockIdp>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        let is_valid = form.get("grant_type").map(String::as_str) == Some("authorization_code")
+            && form.get("code").map(String::as_str) == Some(CODE)
+            && form.get("code_verifier").map(String::as_str) == Some(CODE_VERIFIER)
+            && form.get("client_id").map(String::as_str) == Some(CLIENT_ID);
+        if !is_valid {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+
+        let id_token = idp.id_token.lock().unwrap().clone();
+        Json(serde_json::json!({ "id_token": id_token })).into_response()
+    }
+
+    async fn start_idp() -> (Arc<MockIdp>, Client) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let idp = Arc::new(MockIdp {
+            issuer: issuer.clone(),
+            kids: std::sync::Mutex::new(vec!["key-1".to_string()]),
+            id_token: std::sync::Mutex::new(String::new()),
+            jwks_requests: AtomicUsize::new(0),
+        });
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(metadata))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(Arc::clone(&idp));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = Client::with_providers(&[OidcProvider {
+            id: "mock".to_string(),
+            issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost/callback".to_string(),
+            scopes: vec!["openid".to_string()],
+            trust_email: false,
+        }])
+        .unwrap();
+
+        (idp, client)
+    }
+
+    fn set_id_token(idp: &MockIdp, id_token: String) {
+        *idp.id_token.lock().unwrap() = id_token;
+    }
+
+    #[tokio::test]
+    async fn exchange_code_returns_the_claims_of_a_valid_id_token() {
+        let (idp, client) = start_idp().await;
+        set_id_token(&idp, id_token(&idp.issuer, CLIENT_ID, "key-1"));
+
+        let claims = client
+            .exchange_code("mock", CODE, CODE_VERIFIER)
+            .await
+            .unwrap();
+
+        assert_eq!(claims.sub, "subject");
+        assert_eq!(claims.email.as_deref(), Some("user@example.com"));
+        assert!(claims.email_verified);
+        assert_eq!(claims.nonce.as_deref(), Some("nonce"));
+    }
+
+    #[tokio::test]
+    async fn exchange_code_fails_when_the_provider_rejects_the_verifier() {
+        let (idp, client) = start_idp().await;
+        set_id_token(&idp, id_token(&idp.issuer, CLIENT_ID, "key-1"));
+
+        let res = client.exchange_code("mock", CODE, "another-verifier").await;
+
+        assert!(matches!(res, Err(Error::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_a_token_for_another_client() {
+        let (idp, client) = start_idp().await;
+        set_id_token(&idp, id_token(&idp.issuer, "another-client", "key-1"));
+
+        let res = client.exchange_code("mock", CODE, CODE_VERIFIER).await;
+
+        assert!(matches!(res, Err(Error::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_a_token_from_another_issuer() {
+        let (idp, client) = start_idp().await;
+        set_id_token(&idp, id_token("http://evil.example", CLIENT_ID, "key-1"));
+
+        let res = client.exchange_code("mock", CODE, CODE_VERIFIER).await;
+
+        assert!(matches!(res, Err(Error::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn exchange_code_rejects_symmetric_tokens() {
+        let (idp, client) = start_idp().await;
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("key-1".to_string());
+        let token = jsonwebtoken::encode(
+            &header,
+            &serde_json::json!({ "iss": idp.issuer, "aud": CLIENT_ID, "sub": "subject" }),
+            &EncodingKey::from_secret(b"client-secret"),
+        )
+        .unwrap();
+        set_id_token(&idp, token);
+
+        let res = client.exchange_code("mock", CODE, CODE_VERIFIER).await;
+
+        assert!(matches!(res, Err(Error::Unauthorized(_))));
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_refetches_the_keys_at_most_once_per_interval() {
+        let (idp, client) = start_idp().await;
+        set_id_token(&idp, id_token(&idp.issuer, CLIENT_ID, "key-1"));
+        client
+            .exchange_code("mock", CODE, CODE_VERIFIER)
+            .await
+            .unwrap();
+        assert_eq!(idp.jwks_requests.load(Ordering::SeqCst), 1);
+
+        // The provider rotates its key, but the keys were fetched too recently to ask again
+        idp.kids.lock().unwrap().push("key-2".to_string());
+        set_id_token(&idp, id_token(&idp.issuer, CLIENT_ID, "key-2"));
+        for _ in 0..3 {
+            let res = client.exchange_code("mock", CODE, CODE_VERIFIER).await;
+            assert!(matches!(res, Err(Error::Unauthorized(_))));
+        }
+        assert_eq!(idp.jwks_requests.load(Ordering::SeqCst), 1);
+
+        // Once the interval has passed the new key is picked up with a single refetch
+        for discovery in client.discoveries.write().await.values_mut() {
+            discovery.fetched_at = Instant::now() - MIN_REFRESH_INTERVAL;
+        }
+        client
+            .exchange_code("mock", CODE, CODE_VERIFIER)
+            .await
+            .unwrap();
+        client
+            .exchange_code("mock", CODE, CODE_VERIFIER)
+            .await
+            .unwrap();
+        assert_eq!(idp.jwks_requests.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::config::Config;
+use crate::internal::model::error::Error;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+
+pub trait Hasher {
+    async fn hash(&self, password: &str) -> Result<String, Error>;
+
+    async fn verify(&self, password: &str, hash: &str) -> Result<bool, Error>;
+
+    // True when the hash was made with another algorithm or cost than the current one
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+pub struct Argon2id {
+    params: Params,
+}
+
+impl Argon2id {
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let params = Params::new(
+            config.password_hash_memory_kib,
+            config.password_hash_iterations,
+            config.password_hash_parallelism,
+            None,
+        )
+        .map_err(|err| Error::Internal(err.to_string()))?;
+
+        Ok(Self { params })
+    }
+}
+
+fn argon2(params: Params) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+// Hashing is slow on purpose, so it runs off the async worker threads
+async fn run_blocking<T: Send + 'static>(
+    task: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    tokio::task::spawn_blocking(task)
+        .await
+        .map_err(|err| Error::Internal(err.to_string()))?
+}
+
+// Accounts created before Argon2id still carry bcrypt hashes until their next sign-in
+fn is_bcrypt(hash: &str) -> bool {
+    hash.starts_with("$2")
+}
+
+impl Hasher for Argon2id {
+    async fn hash(&self, password: &str) -> Result<String, Error> {
+        let params = self.params.clone();
+        let password = password.to_string();
+
+        run_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+
+            argon2(params)
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|err| Error::Internal(err.to_string()))
+        })
+        .await
+    }
+
+    async fn verify(&self, password: &str, hash: &str) -> Result<bool, Error> {
+        let params = self.params.clone();
+        let password = password.to_string();
+        let hash = hash.to_string();
+
+        run_blocking(move || {
+            if is_bcrypt(&hash) {
+                return bcrypt::verify(&password, &hash)
+                    .map_err(|err| Error::Internal(err.to_string()));
+            }
+
+            let hash = PasswordHash::new(&hash).map_err(|err| Error::Internal(err.to_string()))?;
+            // The parameters are read from the hash itself, so older costs still verify
+            Ok(argon2(params)
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok())
+        })
+        .await
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        if is_bcrypt(hash) {
+            return true;
+        }
+
+        match PasswordHash::new(hash) {
+            Ok(hash) => {
+                hash.algorithm != Algorithm::Argon2id.ident()
+                    || Params::try_from(&hash).map_or(true, |params| {
+                        params.m_cost() != self.params.m_cost()
+                            || params.t_cost() != self.params.t_cost()
+                            || params.p_cost() != self.params.p_cost()
+                    })
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Far below the real costs, tests only need hashes the hasher recognises as its own
+    fn hasher(iterations: u32) -> Argon2id {
+        Argon2id::new(&Config {
+            password_hash_memory_kib: 1024,
+            password_hash_iterations: iterations,
+            ..Config::for_tests()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_fresh_hash_verifies_and_is_kept() {
+        let hasher = hasher(1);
+
+        let hash = hasher.hash("correct horse").await.unwrap();
+
+        assert!(hasher.verify("correct horse", &hash).await.unwrap());
+        assert!(!hasher.verify("wrong horse", &hash).await.unwrap());
+        assert!(!hasher.needs_rehash(&hash));
+    }
+
+    #[tokio::test]
+    async fn a_legacy_bcrypt_hash_verifies_and_is_upgraded() {
+        let hasher = hasher(1);
+        let hash = bcrypt::hash("correct horse", 4).unwrap();
+
+        assert!(hasher.verify("correct horse", &hash).await.unwrap());
+        assert!(!hasher.verify("wrong horse", &hash).await.unwrap());
+        assert!(hasher.needs_rehash(&hash));
+    }
+
+    #[tokio::test]
+    async fn a_hash_with_an_old_cost_verifies_and_is_upgraded() {
+        let hash = hasher(1).hash("correct horse").await.unwrap();
+        let hasher = hasher(2);
+
+        assert!(hasher.verify("correct horse", &hash).await.unwrap());
+        assert!(hasher.needs_rehash(&hash));
+    }
+}
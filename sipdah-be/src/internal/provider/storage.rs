@@ -0,0 +1,57 @@
+use crate::config::Config;
+use crate::internal::model::error::Error;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Keeps file contents by key, keys come from the services and never from clients
+pub trait Storage {
+    async fn put(&self, key: &str, content: &[u8]) -> Result<(), Error>;
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+// Stores every file under `STORAGE_DIR` on the local disk
+pub struct Local {
+    dir: PathBuf,
+}
+
+impl Local {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            dir: PathBuf::from(&config.storage_dir),
+        }
+    }
+}
+
+impl Storage for Local {
+    async fn put(&self, key: &str, content: &[u8]) -> Result<(), Error> {
+        let path = self.dir.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| Error::Internal(err.to_string()))?;
+        }
+
+        tokio::fs::write(&path, content)
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match tokio::fs::read(self.dir.join(key)).await {
+            Ok(content) => Ok(Some(content)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::Internal(err.to_string())),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.dir.join(key)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(Error::Internal(err.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
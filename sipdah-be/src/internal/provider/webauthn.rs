@@ -0,0 +1,239 @@
+use crate::config::Config;
+use crate::internal::model::error::Error;
+use sha2::{Digest, Sha256};
+use webauthn_rs::fake::{FakePasskeyDistribution, WebauthnFakeCredentialGenerator};
+use webauthn_rs::prelude::{RequestChallengeResponse, Url};
+use webauthn_rs::{Webauthn, WebauthnBuilder};
+use webauthn_rs_proto::AllowCredentials;
+
+// Passkeys are bound to the RP ID, so changing it strands every registered passkey
+pub fn new(config: &Config) -> Result<Webauthn, Error> {
+    with_relying_party(
+        config.webauthn_rp_id.as_deref(),
+        &config.webauthn_rp_origin,
+        &config.webauthn_rp_name,
+    )
+}
+
+fn with_relying_party(
+    rp_id: Option<&str>,
+    rp_origin: &str,
+    rp_name: &str,
+) -> Result<Webauthn, Error> {
+    let origin =
+        Url::parse(rp_origin).map_err(|err| Error::Internal(format!("{}: {}", rp_origin, err)))?;
+    let rp_id = match rp_id {
+        Some(rp_id) => rp_id.to_string(),
+        None => origin
+            .host_str()
+            .map(String::from)
+            .ok_or_else(|| Error::Internal(format!("{} has no host", origin)))?,
+    };
+
+    WebauthnBuilder::new(&rp_id, &origin)
+        .and_then(|builder| builder.rp_name(rp_name).build())
+        .map_err(|err| Error::Internal(err.to_string()))
+}
+
+// Makes discoverable options look like those of a user with passkeys. The made-up ids stay the
+// same for an email, so asking twice does not give away that the account has none.
+pub fn disguise_as_user(
+    options: &mut RequestChallengeResponse,
+    config: &Config,
+    email: &str,
+) -> Result<(), Error> {
+    // Derived rather than reused, the encryption key is meant for AES only
+    let key = Sha256::new()
+        .chain_update(b"passkey-decoy:")
+        .chain_update(config.encryption_key.as_bytes())
+        .finalize();
+
+    decoy_credentials(options, &key, email)
+}
+
+fn decoy_credentials(
+    options: &mut RequestChallengeResponse,
+    key: &[u8],
+    email: &str,
+) -> Result<(), Error> {
+    let credential_ids = WebauthnFakeCredentialGenerator::<FakePasskeyDistribution>::new(key)
+        .and_then(|generator| generator.generate(email.to_lowercase().as_bytes()))
+        .map_err(|err| Error::Internal(err.to_string()))?;
+
+    // What `Webauthn::start_passkey_authentication` sends differs in these fields as well
+    options.mediation = None;
+    options.public_key.extensions = None;
+    options.public_key.allow_credentials = credential_ids
+        .iter()
+        .map(|credential_id| AllowCredentials {
+            type_: "public-key".to_string(),
+            id: credential_id.as_ref().into(),
+            transports: None,
+        })
+        .collect();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::model::passkey::PasskeyChallenge;
+    use webauthn_authenticator_rs::softpasskey::SoftPasskey;
+    use webauthn_authenticator_rs::WebauthnAuthenticator;
+    use webauthn_rs::prelude::{Passkey, Uuid};
+
+    const ORIGIN: &str = "https://sipdah.example";
+    const DECOY_KEY: &[u8] = b"decoy-key";
+
+    fn relying_party() -> Webauthn {
+        with_relying_party(None, ORIGIN, "SIPDAH").unwrap()
+    }
+
+    fn authenticator() -> WebauthnAuthenticator<SoftPasskey> {
+        WebauthnAuthenticator::new(SoftPasskey::new(true))
+    }
+
+    fn register(
+        webauthn: &Webauthn,
+        authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+        user_id: Uuid,
+    ) -> Passkey {
+        let (options, registration) = webauthn
+            .start_passkey_registration(user_id, "jane@example.com", "Jane", None)
+            .unwrap();
+        let credential = authenticator
+            .do_registration(Url::parse(ORIGIN).unwrap(), options)
+            .unwrap();
+
+        webauthn
+            .finish_passkey_registration(&credential, &registration)
+            .unwrap()
+    }
+
+    #[test]
+    fn registers_and_authenticates_a_passkey() {
+        let webauthn = relying_party();
+        let mut authenticator = authenticator();
+        let user_id = Uuid::new_v4();
+        let mut passkey = register(&webauthn, &mut authenticator, user_id);
+
+        let (options, authentication) = webauthn
+            .start_passkey_authentication(std::slice::from_ref(&passkey))
+            .unwrap();
+        // The state waits in the cache between the two requests
+        let challenge = serde_json::to_string(&PasskeyChallenge::User {
+            user_id: user_id.to_string(),
+            authentication,
+        })
+        .unwrap();
+        let authentication = match serde_json::from_str(&challenge).unwrap() {
+            PasskeyChallenge::User { authentication, .. } => authentication,
+            PasskeyChallenge::Discoverable(_) => unreachable!(),
+        };
+
+        let credential = authenticator
+            .do_authentication(Url::parse(ORIGIN).unwrap(), options)
+            .unwrap();
+        let result = webauthn
+            .finish_passkey_authentication(&credential, &authentication)
+            .unwrap();
+
+        assert_eq!(result.cred_id(), passkey.cred_id());
+        assert!(result.user_verified());
+        assert_eq!(passkey.update_credential(&result), Some(true));
+    }
+
+    #[test]
+    fn rejects_an_assertion_for_another_origin() {
+        let webauthn = relying_party();
+        let mut authenticator = authenticator();
+        let passkey = register(&webauthn, &mut authenticator, Uuid::new_v4());
+
+        let (options, authentication) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+        let credential = authenticator
+            .do_authentication(Url::parse("https://evil.sipdah.example").unwrap(), options)
+            .unwrap();
+
+        assert!(webauthn
+            .finish_passkey_authentication(&credential, &authentication)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_an_assertion_for_another_challenge() {
+        let webauthn = relying_party();
+        let mut authenticator = authenticator();
+        let passkey = register(&webauthn, &mut authenticator, Uuid::new_v4());
+
+        let (options, _) = webauthn
+            .start_passkey_authentication(std::slice::from_ref(&passkey))
+            .unwrap();
+        let (_, authentication) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+        let credential = authenticator
+            .do_authentication(Url::parse(ORIGIN).unwrap(), options)
+            .unwrap();
+
+        assert!(webauthn
+            .finish_passkey_authentication(&credential, &authentication)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_a_registration_for_another_challenge() {
+        let webauthn = relying_party();
+        let mut authenticator = authenticator();
+        let user_id = Uuid::new_v4();
+
+        let (options, _) = webauthn
+            .start_passkey_registration(user_id, "jane@example.com", "Jane", None)
+            .unwrap();
+        let (_, registration) = webauthn
+            .start_passkey_registration(user_id, "jane@example.com", "Jane", None)
+            .unwrap();
+        let credential = authenticator
+            .do_registration(Url::parse(ORIGIN).unwrap(), options)
+            .unwrap();
+
+        assert!(webauthn
+            .finish_passkey_registration(&credential, &registration)
+            .is_err());
+    }
+
+    #[test]
+    fn decoys_look_like_the_options_of_a_user() {
+        let webauthn = relying_party();
+        let mut authenticator = authenticator();
+        let passkey = register(&webauthn, &mut authenticator, Uuid::new_v4());
+        let (real, _) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+
+        let (mut decoy, _) = webauthn.start_discoverable_authentication().unwrap();
+        decoy_credentials(&mut decoy, DECOY_KEY, "jane@example.com").unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&decoy.mediation).unwrap(),
+            serde_json::to_value(&real.mediation).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&decoy.public_key.extensions).unwrap(),
+            serde_json::to_value(&real.public_key.extensions).unwrap()
+        );
+        assert_eq!(
+            decoy.public_key.user_verification,
+            real.public_key.user_verification
+        );
+    }
+
+    #[test]
+    fn decoys_are_stable_per_email() {
+        let webauthn = relying_party();
+        let decoy = |email: &str| {
+            let (mut options, _) = webauthn.start_discoverable_authentication().unwrap();
+            decoy_credentials(&mut options, DECOY_KEY, email).unwrap();
+            serde_json::to_value(&options.public_key.allow_credentials).unwrap()
+        };
+
+        assert_eq!(decoy("jane@example.com"), decoy("Jane@Example.com"));
+        assert_ne!(decoy("jane@example.com"), decoy("john@example.com"));
+    }
+}
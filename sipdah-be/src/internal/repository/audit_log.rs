@@ -0,0 +1,38 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::audit_log::AuditLog;
+use crate::internal::model::error::Error;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl Repository {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl model::audit_log::Repository for Repository {
+    async fn create(&self, audit_log: &AuditLog) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                audit_log (id, actor_id, user_id, session_id, action, created_at)
+            VALUES
+                (UUID_TO_BIN(?), UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&audit_log.id)
+            .bind(&audit_log.actor_id)
+            .bind(&audit_log.user_id)
+            .bind(&audit_log.session_id)
+            .bind(&audit_log.action)
+            .bind(audit_log.created_at);
+
+        uow::execute(query, &self.pool).await
+    }
+}
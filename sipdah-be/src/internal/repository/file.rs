@@ -0,0 +1,87 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::file::File;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl Repository {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl model::file::Repository for Repository {
+    async fn create(&self, file: &File) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                file (id, owner_id, category, file_name, file_type, file_size, location, created_at)
+            VALUES
+                (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&file.id)
+            .bind(&file.owner_id)
+            .bind(&file.category)
+            .bind(&file.file_name)
+            .bind(&file.file_type)
+            .bind(file.file_size)
+            .bind(&file.location)
+            .bind(file.created_at);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn find_by_id(&self, file_id: &str) -> Result<Option<File>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(owner_id) as owner_id, category, file_name,
+                file_type, file_size, location, created_at
+            FROM
+                file
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query_as::<_, File>(sql).bind(file_id);
+
+        uow::fetch_one_as(query, &self.pool).await
+    }
+
+    async fn find_all_by_owner_id(&self, owner_id: &str) -> Result<Vec<File>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(owner_id) as owner_id, category, file_name,
+                file_type, file_size, location, created_at
+            FROM
+                file
+            WHERE
+                owner_id = UUID_TO_BIN(?)
+            ORDER BY
+                created_at
+        "#;
+
+        let query = sqlx::query_as::<_, File>(sql).bind(owner_id);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn delete(&self, file_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                file
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(file_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
@@ -0,0 +1,106 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::login_event::{LoginEvent, OUTCOME_SUCCESS};
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl Repository {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl model::login_event::Repository for Repository {
+    async fn create(&self, login_event: &LoginEvent) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                login_event (id, user_id, email, kind, outcome, ip_address, user_agent, created_at)
+            VALUES
+                (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&login_event.id)
+            .bind(&login_event.user_id)
+            .bind(&login_event.email)
+            .bind(&login_event.kind)
+            .bind(&login_event.outcome)
+            .bind(&login_event.ip_address)
+            .bind(&login_event.user_agent)
+            .bind(login_event.created_at);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn find_all_by_user_id(
+        &self,
+        user_id: &str,
+        limit: u32,
+    ) -> Result<Vec<LoginEvent>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, email, kind, outcome,
+                ip_address, user_agent, created_at
+            FROM
+                login_event
+            WHERE
+                user_id = UUID_TO_BIN(?)
+            ORDER BY
+                created_at DESC
+            LIMIT ?
+        "#;
+
+        let query = sqlx::query_as::<_, LoginEvent>(sql)
+            .bind(user_id)
+            .bind(limit);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn find_successes_by_user_id(
+        &self,
+        user_id: &str,
+        kind: &str,
+        limit: u32,
+    ) -> Result<Vec<LoginEvent>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, email, kind, outcome,
+                ip_address, user_agent, created_at
+            FROM
+                login_event
+            WHERE
+                user_id = UUID_TO_BIN(?) AND kind = ? AND outcome = ?
+            ORDER BY
+                created_at DESC
+            LIMIT ?
+        "#;
+
+        let query = sqlx::query_as::<_, LoginEvent>(sql)
+            .bind(user_id)
+            .bind(kind)
+            .bind(OUTCOME_SUCCESS)
+            .bind(limit);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                login_event
+            WHERE
+                user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
@@ -1,2 +1,12 @@
 pub mod user;
-pub mod role;
\ No newline at end of file
+pub mod role;
+pub mod session;
+pub mod revoked_token;
+pub mod two_factor;
+pub mod oidc;
+pub mod personal_access_token;
+pub mod permission;
+pub mod audit_log;
+pub mod passkey;
+pub mod login_event;
+pub mod file;
\ No newline at end of file
@@ -0,0 +1,92 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::oidc::UserIdentity;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl Repository {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl model::oidc::Repository for Repository {
+    async fn create(&self, identity: &UserIdentity) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                user_identity (id, user_id, provider, subject, email, created_at)
+            VALUES
+                (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&identity.id)
+            .bind(&identity.user_id)
+            .bind(&identity.provider)
+            .bind(&identity.subject)
+            .bind(&identity.email)
+            .bind(identity.created_at);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn find_by_provider_and_subject(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<UserIdentity>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, provider, subject, email,
+                created_at
+            FROM
+                user_identity
+            WHERE
+                provider = ? AND subject = ?
+        "#;
+
+        let query = sqlx::query_as::<_, UserIdentity>(sql)
+            .bind(provider)
+            .bind(subject);
+        let identity = uow::fetch_one_as(query, &self.pool).await?;
+
+        Ok(identity)
+    }
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<UserIdentity>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, provider, subject, email,
+                created_at
+            FROM
+                user_identity
+            WHERE
+                user_id = UUID_TO_BIN(?)
+            ORDER BY
+                created_at
+        "#;
+
+        let query = sqlx::query_as::<_, UserIdentity>(sql).bind(user_id);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                user_identity
+            WHERE
+                user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
@@ -0,0 +1,127 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::passkey::Passkey;
+use chrono::Local;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl Repository {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl model::passkey::Repository for Repository {
+    async fn create(&self, passkey: &Passkey) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                passkey (id, user_id, name, credential_id, credential, sign_count, transports,
+                         last_used_at, created_at)
+            VALUES
+                (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?, ?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&passkey.id)
+            .bind(&passkey.user_id)
+            .bind(&passkey.name)
+            .bind(&passkey.credential_id)
+            .bind(&passkey.credential)
+            .bind(passkey.sign_count)
+            .bind(&passkey.transports)
+            .bind(passkey.last_used_at)
+            .bind(passkey.created_at);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn find_by_credential_id(&self, credential_id: &str) -> Result<Option<Passkey>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, name, credential_id,
+                credential, sign_count, transports, last_used_at, created_at
+            FROM
+                passkey
+            WHERE
+                credential_id = ?
+        "#;
+
+        let query = sqlx::query_as::<_, Passkey>(sql).bind(credential_id);
+
+        uow::fetch_one_as(query, &self.pool).await
+    }
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<Passkey>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, name, credential_id,
+                credential, sign_count, transports, last_used_at, created_at
+            FROM
+                passkey
+            WHERE
+                user_id = UUID_TO_BIN(?)
+            ORDER BY
+                created_at DESC
+        "#;
+
+        let query = sqlx::query_as::<_, Passkey>(sql).bind(user_id);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn update_credential(
+        &self,
+        passkey_id: &str,
+        credential: &str,
+        sign_count: u32,
+    ) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                passkey
+            SET
+                credential = ?, sign_count = ?, last_used_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(credential)
+            .bind(sign_count)
+            .bind(Local::now())
+            .bind(passkey_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn delete(&self, passkey_id: &str, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                passkey
+            WHERE
+                id = UUID_TO_BIN(?) AND user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(passkey_id).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                passkey
+            WHERE
+                user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
@@ -0,0 +1,114 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::permission::Permission;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl Repository {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl model::permission::Repository for Repository {
+    async fn find_all(&self) -> Result<Vec<Permission>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, name, description, created_at
+            FROM
+                permission
+            ORDER BY
+                name
+        "#;
+
+        let query = sqlx::query_as::<_, Permission>(sql);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn find_by_name(&self, name: &str) -> Result<Option<Permission>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, name, description, created_at
+            FROM
+                permission
+            WHERE
+                name = ?
+        "#;
+
+        let query = sqlx::query_as::<_, Permission>(sql).bind(name);
+        let permission = uow::fetch_one_as(query, &self.pool).await?;
+
+        Ok(permission)
+    }
+
+    async fn find_all_by_role_id(&self, role_id: &str) -> Result<Vec<Permission>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(p.id) as id, p.name, p.description, p.created_at
+            FROM
+                permission p
+            JOIN
+                role_permission rp ON rp.permission_id = p.id
+            WHERE
+                rp.role_id = UUID_TO_BIN(?)
+            ORDER BY
+                p.name
+        "#;
+
+        let query = sqlx::query_as::<_, Permission>(sql).bind(role_id);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn find_all_by_role_name(&self, role: &str) -> Result<Vec<Permission>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(p.id) as id, p.name, p.description, p.created_at
+            FROM
+                permission p
+            JOIN
+                role_permission rp ON rp.permission_id = p.id
+            JOIN
+                role r ON r.id = rp.role_id
+            WHERE
+                r.name = ?
+            ORDER BY
+                p.name
+        "#;
+
+        let query = sqlx::query_as::<_, Permission>(sql).bind(role);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn grant(&self, role_id: &str, permission_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            INSERT IGNORE INTO role_permission (role_id, permission_id)
+            VALUES (UUID_TO_BIN(?), UUID_TO_BIN(?))
+        "#;
+
+        let query = sqlx::query(sql).bind(role_id).bind(permission_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn revoke(&self, role_id: &str, permission_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                role_permission
+            WHERE
+                role_id = UUID_TO_BIN(?) AND permission_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(role_id).bind(permission_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
@@ -0,0 +1,121 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::personal_access_token::PersonalAccessToken;
+use chrono::Local;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl Repository {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl model::personal_access_token::Repository for Repository {
+    async fn create(&self, token: &PersonalAccessToken) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                personal_access_token (id, user_id, name, token_hash, scopes, expires_at,
+                                       last_used_at, created_at)
+            VALUES
+                (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&token.id)
+            .bind(&token.user_id)
+            .bind(&token.name)
+            .bind(&token.token_hash)
+            .bind(&token.scopes)
+            .bind(token.expires_at)
+            .bind(token.last_used_at)
+            .bind(token.created_at);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn find_by_token_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<PersonalAccessToken>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, name, token_hash, scopes,
+                expires_at, last_used_at, created_at
+            FROM
+                personal_access_token
+            WHERE
+                token_hash = ?
+        "#;
+
+        let query = sqlx::query_as::<_, PersonalAccessToken>(sql).bind(token_hash);
+        let token = uow::fetch_one_as(query, &self.pool).await?;
+
+        Ok(token)
+    }
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<PersonalAccessToken>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, name, token_hash, scopes,
+                expires_at, last_used_at, created_at
+            FROM
+                personal_access_token
+            WHERE
+                user_id = UUID_TO_BIN(?)
+            ORDER BY
+                created_at DESC
+        "#;
+
+        let query = sqlx::query_as::<_, PersonalAccessToken>(sql).bind(user_id);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
+    async fn update_last_used_at(&self, token_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                personal_access_token
+            SET
+                last_used_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(Local::now()).bind(token_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn delete(&self, token_id: &str, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                personal_access_token
+            WHERE
+                id = UUID_TO_BIN(?) AND user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(token_id).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn delete_all_by_user_id(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM
+                personal_access_token
+            WHERE
+                user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
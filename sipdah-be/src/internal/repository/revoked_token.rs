@@ -0,0 +1,89 @@
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::provider::cache::Cache as CacheProvider;
+use chrono::{DateTime, Local};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository<T1>
+where
+    T1: CacheProvider,
+{
+    cache_provider: Arc<T1>,
+}
+
+impl<T1> Repository<T1>
+where
+    T1: CacheProvider,
+{
+    pub fn new(cache_provider: Arc<T1>) -> Self {
+        Self { cache_provider }
+    }
+}
+
+fn revoked_token_key(token_id: &str) -> String {
+    format!("auth:revoked-token:{}", token_id)
+}
+
+impl<T1> model::revoked_token::Repository for Repository<T1>
+where
+    T1: CacheProvider,
+{
+    async fn revoke(&self, token_id: &str, expires_at: DateTime<Local>) -> Result<(), Error> {
+        // Once the token expires on its own the entry has nothing left to block
+        let ttl = expires_at - Local::now();
+        if ttl.num_seconds() <= 0 {
+            return Ok(());
+        }
+
+        self.cache_provider
+            .setx(revoked_token_key(token_id), true, ttl)
+            .await
+    }
+
+    async fn is_revoked(&self, token_id: &str) -> Result<bool, Error> {
+        let revoked = self
+            .cache_provider
+            .get::<bool>(revoked_token_key(token_id))
+            .await?;
+
+        Ok(revoked.unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::model::revoked_token::Repository as _;
+    use crate::internal::provider::cache::Memory;
+    use chrono::Duration;
+
+    fn repository() -> Repository<Memory> {
+        Repository::new(Arc::new(Memory::default()))
+    }
+
+    #[tokio::test]
+    async fn a_revoked_token_is_turned_away() {
+        let repository = repository();
+
+        repository
+            .revoke("revoked", Local::now() + Duration::minutes(5))
+            .await
+            .unwrap();
+
+        assert!(repository.is_revoked("revoked").await.unwrap());
+        assert!(!repository.is_revoked("other").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn an_expired_token_is_not_kept() {
+        let repository = repository();
+
+        repository
+            .revoke("expired", Local::now() - Duration::minutes(5))
+            .await
+            .unwrap();
+
+        assert!(!repository.is_revoked("expired").await.unwrap());
+    }
+}
@@ -1,8 +1,8 @@
 use crate::internal::common::uow;
 use crate::internal::model;
 use crate::internal::model::error::Error;
-use crate::internal::model::role::Role;
-use sqlx::{MySql, Pool};
+use crate::internal::model::role::{Role, UserRole};
+use sqlx::{MySql, Pool, QueryBuilder};
 use std::sync::Arc;
 
 #[derive(Clone)]
@@ -31,7 +31,7 @@ impl model::role::Repository for Repository {
             .bind(role.created_at)
             .bind(role.updated_at);
 
-        Ok(uow::execute(query, &*self.pool).await?)
+        uow::execute(query, &self.pool).await
     }
 
     async fn find_by_id(&self, role_id: &str) -> Result<Option<Role>, Error> {
@@ -45,13 +45,25 @@ impl model::role::Repository for Repository {
         "#;
 
         let query = sqlx::query_as::<_, Role>(sql).bind(role_id);
-        let role = uow::fetch_one_as(query, &*self.pool).await?;
+        let role = uow::fetch_one_as(query, &self.pool).await?;
 
         Ok(role)
     }
 
     async fn find_all(&self) -> Result<Vec<Role>, Error> {
-        todo!()
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, name, created_at, updated_at
+            FROM
+                role
+            ORDER BY
+                name
+        "#;
+
+        let query = sqlx::query_as::<_, Role>(sql);
+        let roles = uow::fetch_all(query, &self.pool).await?;
+
+        Ok(roles)
     }
 
     async fn find_by_name(&self, name: &str) -> Result<Option<Role>, Error> {
@@ -65,18 +77,67 @@ impl model::role::Repository for Repository {
         "#;
 
         let query = sqlx::query_as::<_, Role>(sql).bind(name);
-        let role = uow::fetch_one_as(query, &*self.pool).await?;
+        let role = uow::fetch_one_as(query, &self.pool).await?;
 
         Ok(role)
     }
 
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<Role>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(r.id) as id, r.name, r.created_at, r.updated_at
+            FROM
+                role r
+            JOIN
+                user_role ur ON ur.role_id = r.id
+            WHERE
+                ur.user_id = UUID_TO_BIN(?)
+            ORDER BY
+                r.name
+        "#;
+
+        let query = sqlx::query_as::<_, Role>(sql).bind(user_id);
+        let roles = uow::fetch_all(query, &self.pool).await?;
+
+        Ok(roles)
+    }
+
+    async fn find_all_by_user_ids(&self, user_ids: &[String]) -> Result<Vec<UserRole>, Error> {
+        // `IN ()` is not valid SQL
+        if user_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut query = QueryBuilder::<MySql>::new(
+            r#"
+            SELECT
+                BIN_TO_UUID(ur.user_id) as user_id, r.name
+            FROM
+                role r
+            JOIN
+                user_role ur ON ur.role_id = r.id
+            WHERE
+                ur.user_id IN (
+            "#,
+        );
+        let mut ids = query.separated(", ");
+        for user_id in user_ids {
+            ids.push("UUID_TO_BIN(")
+                .push_bind_unseparated(user_id)
+                .push_unseparated(")");
+        }
+        query.push(") ORDER BY r.name");
+
+        uow::fetch_all(query.build_query_as::<UserRole>(), &self.pool).await
+    }
+
     async fn exists_by_name(&self, name: &str) -> Result<bool, Error> {
         let sql = r#"
             SELECT EXISTS(SELECT 1 FROM role WHERE name = ?)
         "#;
 
         let query = sqlx::query_as(sql).bind(name);
-        let exists: (bool,) = uow::fetch_one(query, &*self.pool).await?;
+        let exists: (bool,) = uow::fetch_one(query, &self.pool).await?;
 
         Ok(exists.0)
     }
@@ -89,6 +150,17 @@ impl model::role::Repository for Repository {
 
         let query = sqlx::query(sql).bind(user_id).bind(role_id);
 
-        Ok(uow::execute(query, &*self.pool).await?)
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn remove(&self, user_id: &str, role_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM user_role
+            WHERE user_id = UUID_TO_BIN(?) AND role_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(user_id).bind(role_id);
+
+        uow::execute(query, &self.pool).await
     }
 }
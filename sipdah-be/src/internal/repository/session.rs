@@ -0,0 +1,194 @@
+use crate::config::Config;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::session::Session;
+use crate::internal::provider::cache::Cache as CacheProvider;
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository<T1>
+where
+    T1: CacheProvider,
+{
+    config: Arc<Config>,
+    cache_provider: Arc<T1>,
+}
+
+impl<T1> Repository<T1>
+where
+    T1: CacheProvider,
+{
+    pub fn new(config: Arc<Config>, cache_provider: Arc<T1>) -> Self {
+        Self {
+            config,
+            cache_provider,
+        }
+    }
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("auth:session:{}", session_id)
+}
+
+fn used_refresh_token_key(refresh_token_id: &str) -> String {
+    format!("auth:used-refresh-token:{}", refresh_token_id)
+}
+
+fn user_sessions_key(user_id: &str) -> String {
+    format!("auth:user-sessions:{}", user_id)
+}
+
+impl<T1> model::session::Repository for Repository<T1>
+where
+    T1: CacheProvider,
+{
+    async fn save(&self, session: &Session) -> Result<(), Error> {
+        // A session lives as long as its newest refresh token
+        let ttl = self.config.refresh_token_key_ttl;
+
+        self.cache_provider
+            .setx(session_key(&session.id), session, ttl)
+            .await?;
+        self.cache_provider
+            .sadd(user_sessions_key(&session.user_id), session.id.clone(), ttl)
+            .await
+    }
+
+    async fn find_by_id(&self, session_id: &str) -> Result<Option<Session>, Error> {
+        self.cache_provider.get(session_key(session_id)).await
+    }
+
+    async fn find_all_by_user_id(&self, user_id: &str) -> Result<Vec<Session>, Error> {
+        let session_ids = self
+            .cache_provider
+            .smembers(user_sessions_key(user_id))
+            .await?;
+        let mut sessions = Vec::with_capacity(session_ids.len());
+
+        for session_id in session_ids {
+            match self.find_by_id(&session_id).await? {
+                Some(session) => sessions.push(session),
+                // The session expired on its own, drop it from the index
+                None => {
+                    self.cache_provider
+                        .srem(user_sessions_key(user_id), session_id)
+                        .await?
+                }
+            }
+        }
+
+        sessions.sort_by_key(|session| Reverse(session.last_used_at));
+
+        Ok(sessions)
+    }
+
+    async fn delete(&self, session: &Session) -> Result<(), Error> {
+        self.cache_provider.del(session_key(&session.id)).await?;
+        self.cache_provider
+            .srem(user_sessions_key(&session.user_id), session.id.clone())
+            .await
+    }
+
+    async fn consume_refresh_token(&self, refresh_token_id: &str) -> Result<bool, Error> {
+        // Outlives the token itself, so a replay is always recognised
+        let ttl = self.config.refresh_token_key_ttl;
+
+        self.cache_provider
+            .setnx(used_refresh_token_key(refresh_token_id), true, ttl)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::model::session::Repository as _;
+    use crate::internal::provider::cache::Memory;
+    use chrono::Local;
+
+    fn repository() -> Repository<Memory> {
+        Repository::new(Arc::new(Config::for_tests()), Arc::new(Memory::default()))
+    }
+
+    fn session(id: &str, refresh_token_id: &str) -> Session {
+        Session {
+            id: id.to_string(),
+            user_id: "user".to_string(),
+            refresh_token_id: refresh_token_id.to_string(),
+            access_token_id: format!("{}-access", refresh_token_id),
+            access_token_expires_at: Local::now(),
+            user_agent: None,
+            ip_address: None,
+            created_at: Local::now(),
+            last_used_at: Local::now(),
+            actor_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_refresh_token_can_be_consumed_only_once() {
+        let repository = repository();
+
+        assert!(repository.consume_refresh_token("first").await.unwrap());
+        assert!(!repository.consume_refresh_token("first").await.unwrap());
+        assert!(repository.consume_refresh_token("second").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn of_concurrent_refreshes_with_the_same_token_only_one_wins() {
+        let repository = Arc::new(repository());
+
+        let attempts: Vec<_> = (0..8)
+            .map(|_| {
+                let repository = Arc::clone(&repository);
+                tokio::spawn(async move { repository.consume_refresh_token("token").await })
+            })
+            .collect();
+        let mut winners = 0;
+        for attempt in attempts {
+            if attempt.await.unwrap().unwrap() {
+                winners += 1;
+            }
+        }
+
+        assert_eq!(winners, 1);
+    }
+
+    #[tokio::test]
+    async fn a_replayed_token_no_longer_matches_the_rotated_session() {
+        let repository = repository();
+        let mut current = session("session", "first");
+        repository.save(&current).await.unwrap();
+
+        // What `refresh` does with a token that is still current
+        assert!(repository.consume_refresh_token("first").await.unwrap());
+        current.refresh_token_id = "second".to_string();
+        repository.save(&current).await.unwrap();
+
+        let found = repository.find_by_id("session").await.unwrap().unwrap();
+        assert_ne!(found.refresh_token_id, "first");
+        assert!(!repository.consume_refresh_token("first").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_deleted_session_is_gone_for_the_user_as_well() {
+        let repository = repository();
+        let kept = session("kept", "kept-token");
+        let ended = session("ended", "ended-token");
+        repository.save(&kept).await.unwrap();
+        repository.save(&ended).await.unwrap();
+
+        repository.delete(&ended).await.unwrap();
+
+        assert!(repository.find_by_id("ended").await.unwrap().is_none());
+        let remaining: Vec<String> = repository
+            .find_all_by_user_id("user")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|session| session.id)
+            .collect();
+        assert_eq!(remaining, vec!["kept".to_string()]);
+    }
+}
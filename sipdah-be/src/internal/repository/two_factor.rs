@@ -0,0 +1,148 @@
+use crate::internal::common::uow;
+use crate::internal::model;
+use crate::internal::model::error::Error;
+use crate::internal::model::two_factor::{RecoveryCode, TwoFactor};
+use crate::internal::provider::cipher;
+use crate::internal::provider::cipher::Cipher;
+use sqlx::{MySql, Pool};
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Repository<T1>
+where
+    T1: Cipher,
+{
+    pool: Arc<Pool<MySql>>,
+    cipher: Arc<T1>,
+}
+
+impl<T1> Repository<T1>
+where
+    T1: Cipher,
+{
+    pub fn new(pool: Arc<Pool<MySql>>, cipher: Arc<T1>) -> Self {
+        Self { pool, cipher }
+    }
+}
+
+impl<T1> model::two_factor::Repository for Repository<T1>
+where
+    T1: Cipher,
+{
+    async fn save(&self, two_factor: &TwoFactor) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                user_two_factor (user_id, secret, enabled_at, created_at, updated_at)
+            VALUES
+                (UUID_TO_BIN(?), ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                secret = VALUES(secret), enabled_at = VALUES(enabled_at),
+                updated_at = VALUES(updated_at)
+        "#;
+
+        let secret = self.cipher.encrypt(&two_factor.secret)?;
+        let query = sqlx::query(sql)
+            .bind(&two_factor.user_id)
+            .bind(secret)
+            .bind(two_factor.enabled_at)
+            .bind(two_factor.created_at)
+            .bind(two_factor.updated_at);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn find_by_user_id(&self, user_id: &str) -> Result<Option<TwoFactor>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(user_id) as user_id, secret, enabled_at, created_at, updated_at
+            FROM
+                user_two_factor
+            WHERE
+                user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query_as::<_, TwoFactor>(sql).bind(user_id);
+        let mut two_factor = uow::fetch_one_as(query, &self.pool).await?;
+
+        // Secrets enrolled before encryption was introduced are still plain base32
+        if let Some(two_factor) = two_factor
+            .as_mut()
+            .filter(|two_factor| cipher::is_encrypted(&two_factor.secret))
+        {
+            two_factor.secret = self.cipher.decrypt(&two_factor.secret)?;
+        }
+
+        Ok(two_factor)
+    }
+
+    async fn delete_by_user_id(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM user_two_factor WHERE user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn create_recovery_code(&self, code: &RecoveryCode) -> Result<(), Error> {
+        let sql = r#"
+            INSERT INTO
+                user_recovery_code (id, user_id, code_hash, used_at, created_at)
+            VALUES
+                (UUID_TO_BIN(?), UUID_TO_BIN(?), ?, ?, ?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&code.id)
+            .bind(&code.user_id)
+            .bind(&code.code_hash)
+            .bind(code.used_at)
+            .bind(code.created_at);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn find_unused_recovery_code(
+        &self,
+        user_id: &str,
+        code_hash: &str,
+    ) -> Result<Option<RecoveryCode>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, BIN_TO_UUID(user_id) as user_id, code_hash, used_at,
+                created_at
+            FROM
+                user_recovery_code
+            WHERE
+                user_id = UUID_TO_BIN(?) AND code_hash = ? AND used_at IS NULL
+        "#;
+
+        let query = sqlx::query_as::<_, RecoveryCode>(sql)
+            .bind(user_id)
+            .bind(code_hash);
+        let code = uow::fetch_one_as(query, &self.pool).await?;
+
+        Ok(code)
+    }
+
+    async fn use_recovery_code(&self, code_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE user_recovery_code SET used_at = NOW() WHERE id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(code_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn delete_recovery_codes_by_user_id(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            DELETE FROM user_recovery_code WHERE user_id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
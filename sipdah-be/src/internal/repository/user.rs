@@ -1,10 +1,105 @@
 use crate::internal::common::uow;
 use crate::internal::model;
 use crate::internal::model::error::Error;
-use crate::internal::model::user::User;
-use sqlx::{MySql, Pool};
+use crate::internal::model::user::{
+    User, UserCursor, UserFilter, SORT_EMAIL, SORT_NAME, STATUS_DEACTIVATED, STATUS_DELETED,
+};
+use chrono::{DateTime, Local};
+use sqlx::{MySql, Pool, QueryBuilder};
 use std::sync::Arc;
 
+// Keeps `%` and `_` typed by the user from acting as wildcards
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// Built apart from running it, so the keyset conditions can be checked without a database
+fn find_all_query<'a>(
+    filter: &'a UserFilter,
+    cursor: Option<&'a UserCursor>,
+    limit: u32,
+) -> QueryBuilder<'a, MySql> {
+    let mut query = QueryBuilder::<MySql>::new(
+        r#"
+        SELECT
+            BIN_TO_UUID(id) as id, email, password, name, phone_number,
+            BIN_TO_UUID(photo_id) as photo_id,
+            email_verified_at, created_at, updated_at, deactivated_at, deleted_at
+        FROM
+            user
+        WHERE
+        "#,
+    );
+
+    query.push(match filter.status() {
+        STATUS_DEACTIVATED => "deactivated_at IS NOT NULL AND deleted_at IS NULL",
+        STATUS_DELETED => "deleted_at IS NOT NULL",
+        _ => "deactivated_at IS NULL AND deleted_at IS NULL",
+    });
+    if let Some(search) = &filter.search {
+        let prefix = format!("{}%", escape_like(search));
+        query
+            .push(" AND (name LIKE ")
+            .push_bind(prefix.clone())
+            .push(" OR email LIKE ")
+            .push_bind(prefix)
+            .push(")");
+    }
+    if let Some(role) = &filter.role {
+        query
+            .push(
+                " AND EXISTS(SELECT 1 FROM user_role JOIN role ON role.id = user_role.role_id \
+                WHERE user_role.user_id = user.id AND role.name = ",
+            )
+            .push_bind(role)
+            .push(")");
+    }
+    if let Some(created_from) = filter.created_from {
+        query.push(" AND created_at >= ").push_bind(created_from);
+    }
+    if let Some(created_to) = filter.created_to {
+        query.push(" AND created_at < ").push_bind(created_to);
+    }
+
+    // Sorting by creation is sorting by id, UUIDv7 starts with a timestamp
+    let column = match filter.sort() {
+        SORT_NAME => Some("name"),
+        SORT_EMAIL => Some("email"),
+        _ => None,
+    };
+    let (comparison, direction) = match filter.descending {
+        true => ("<", "DESC"),
+        false => (">", "ASC"),
+    };
+    if let Some(cursor) = cursor {
+        match (column, &cursor.key) {
+            (Some(column), Some(key)) => query
+                .push(format!(" AND ({}, id) {} (", column, comparison))
+                .push_bind(key)
+                .push(", UUID_TO_BIN(")
+                .push_bind(&cursor.id)
+                .push("))"),
+            _ => query
+                .push(format!(" AND id {} UUID_TO_BIN(", comparison))
+                .push_bind(&cursor.id)
+                .push(")"),
+        };
+    }
+
+    query.push(" ORDER BY ");
+    if let Some(column) = column {
+        query.push(format!("{} {}, ", column, direction));
+    }
+    query
+        .push(format!("id {} LIMIT ", direction))
+        .push_bind(limit);
+
+    query
+}
+
 #[derive(Clone)]
 pub struct Repository {
     pool: Arc<Pool<MySql>>,
@@ -20,9 +115,10 @@ impl model::user::Repository for Repository {
     async fn create(&self, user: &User) -> Result<(), Error> {
         let sql = r#"
             INSERT INTO
-                user (id, email, password, name, phone_number, photo_url, created_at, updated_at)
+                user (id, email, password, name, phone_number, photo_id, email_verified_at, created_at,
+                      updated_at)
             VALUES
-                (UUID_TO_BIN(?), ?, ?, ?, ?, ?, ?, ?)
+                (UUID_TO_BIN(?), ?, ?, ?, ?, UUID_TO_BIN(?), ?, ?, ?)
         "#;
 
         let query = sqlx::query(sql)
@@ -31,18 +127,38 @@ impl model::user::Repository for Repository {
             .bind(&user.password)
             .bind(&user.name)
             .bind(&user.phone_number)
-            .bind(&user.photo_url)
+            .bind(&user.photo_id)
+            .bind(user.email_verified_at)
             .bind(user.created_at)
             .bind(user.updated_at);
 
-        Ok(uow::execute(query, &*self.pool).await?)
+        uow::execute(query, &self.pool).await
     }
 
     async fn find_by_id(&self, user_id: &str) -> Result<Option<User>, Error> {
         let sql = r#"
             SELECT
-                BIN_TO_UUID(id) as id, email, password, name, phone_number, photo_url, created_at,
-                updated_at, deleted_at
+                BIN_TO_UUID(id) as id, email, password, name, phone_number,
+                BIN_TO_UUID(photo_id) as photo_id,
+                email_verified_at, created_at, updated_at, deactivated_at, deleted_at
+            FROM
+                user
+            WHERE
+                id = UUID_TO_BIN(?) AND deactivated_at IS NULL AND deleted_at IS NULL
+        "#;
+
+        let query = sqlx::query_as::<_, User>(sql).bind(user_id);
+        let user = uow::fetch_one_as(query, &self.pool).await?;
+
+        Ok(user)
+    }
+
+    async fn find_any_by_id(&self, user_id: &str) -> Result<Option<User>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, email, password, name, phone_number,
+                BIN_TO_UUID(photo_id) as photo_id,
+                email_verified_at, created_at, updated_at, deactivated_at, deleted_at
             FROM
                 user
             WHERE
@@ -50,7 +166,7 @@ impl model::user::Repository for Repository {
         "#;
 
         let query = sqlx::query_as::<_, User>(sql).bind(user_id);
-        let user = uow::fetch_one_as(query, &*self.pool).await?;
+        let user = uow::fetch_one_as(query, &self.pool).await?;
 
         Ok(user)
     }
@@ -58,39 +174,298 @@ impl model::user::Repository for Repository {
     async fn find_by_email(&self, email: &str) -> Result<Option<User>, Error> {
         let sql = r#"
             SELECT
-                BIN_TO_UUID(id) as id, email, password, name, phone_number, photo_url, created_at,
-                updated_at, deleted_at
+                BIN_TO_UUID(id) as id, email, password, name, phone_number,
+                BIN_TO_UUID(photo_id) as photo_id,
+                email_verified_at, created_at, updated_at, deactivated_at, deleted_at
             FROM
                 user
             WHERE
-                email = ?
+                email = ? AND deactivated_at IS NULL AND deleted_at IS NULL
         "#;
 
         let query = sqlx::query_as::<_, User>(sql).bind(email);
-        let user = uow::fetch_one_as(query, &*self.pool).await?;
+        let user = uow::fetch_one_as(query, &self.pool).await?;
 
         Ok(user)
     }
 
+    async fn find_all(
+        &self,
+        filter: &UserFilter,
+        cursor: Option<&UserCursor>,
+        limit: u32,
+    ) -> Result<Vec<User>, Error> {
+        let mut query = find_all_query(filter, cursor, limit);
+
+        uow::fetch_all(query.build_query_as::<User>(), &self.pool).await
+    }
+
+    async fn find_all_deleted_before(
+        &self,
+        deleted_before: DateTime<Local>,
+    ) -> Result<Vec<User>, Error> {
+        let sql = r#"
+            SELECT
+                BIN_TO_UUID(id) as id, email, password, name, phone_number,
+                BIN_TO_UUID(photo_id) as photo_id,
+                email_verified_at, created_at, updated_at, deactivated_at, deleted_at
+            FROM
+                user
+            WHERE
+                deleted_at < ? AND anonymized_at IS NULL
+        "#;
+
+        let query = sqlx::query_as::<_, User>(sql).bind(deleted_before);
+
+        uow::fetch_all(query, &self.pool).await
+    }
+
     async fn exists_by_email(&self, email: &str) -> Result<bool, Error> {
         let sql = r#"
             SELECT EXISTS(SELECT 1 FROM user WHERE email = ?)
         "#;
 
         let query = sqlx::query_as(sql).bind(email);
-        let exists: (bool,) = uow::fetch_one(query, &*self.pool).await?;
+        let exists: (bool,) = uow::fetch_one(query, &self.pool).await?;
 
         Ok(exists.0)
     }
 
-    async fn exists_by_id(&self, id: &str) -> Result<bool, Error> {
+    async fn update_password(&self, user_id: &str, password: &str) -> Result<(), Error> {
         let sql = r#"
-            SELECT EXISTS(SELECT 1 FROM user WHERE id = UUID_TO_BIN(?))
+            UPDATE
+                user
+            SET
+                password = ?, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
         "#;
 
-        let query = sqlx::query_as(sql).bind(id);
-        let exists: (bool,) = uow::fetch_one(query, &*self.pool).await?;
+        let query = sqlx::query(sql)
+            .bind(password)
+            .bind(Local::now())
+            .bind(user_id);
 
-        Ok(exists.0)
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn verify_email(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                email_verified_at = ?, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(Local::now())
+            .bind(Local::now())
+            .bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn update_email(&self, user_id: &str, email: &str) -> Result<(), Error> {
+        // Only called once the link mailed to the new address was opened
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                email = ?, email_verified_at = ?, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(email)
+            .bind(Local::now())
+            .bind(Local::now())
+            .bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn update_profile(&self, user: &User) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                name = ?, phone_number = ?, photo_id = UUID_TO_BIN(?), updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(&user.name)
+            .bind(&user.phone_number)
+            .bind(&user.photo_id)
+            .bind(Local::now())
+            .bind(&user.id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn deactivate(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                deactivated_at = ?, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(Local::now())
+            .bind(Local::now())
+            .bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn reactivate(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                deactivated_at = NULL, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql).bind(Local::now()).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                deleted_at = ?, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(Local::now())
+            .bind(Local::now())
+            .bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn restore(&self, user_id: &str) -> Result<(), Error> {
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                deleted_at = NULL, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?) AND anonymized_at IS NULL
+        "#;
+
+        let query = sqlx::query(sql).bind(Local::now()).bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+
+    async fn anonymize(&self, user_id: &str) -> Result<(), Error> {
+        // The placeholder address stays unique and frees the old one for a new sign-up
+        let sql = r#"
+            UPDATE
+                user
+            SET
+                email = CONCAT('deleted-', BIN_TO_UUID(id), '@invalid'), password = '',
+                name = 'Deleted user', phone_number = NULL, photo_id = NULL,
+                email_verified_at = NULL, anonymized_at = ?, updated_at = ?
+            WHERE
+                id = UUID_TO_BIN(?)
+        "#;
+
+        let query = sqlx::query(sql)
+            .bind(Local::now())
+            .bind(Local::now())
+            .bind(user_id);
+
+        uow::execute(query, &self.pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::model::user::SORT_CREATED_AT;
+
+    fn filter(sort: &str, descending: bool) -> UserFilter {
+        UserFilter {
+            search: None,
+            role: None,
+            status: None,
+            created_from: None,
+            created_to: None,
+            sort: Some(sort.to_string()),
+            descending,
+        }
+    }
+
+    fn cursor(filter: &UserFilter, key: Option<&str>) -> UserCursor {
+        UserCursor {
+            sort: filter.sort().to_string(),
+            descending: filter.descending,
+            key: key.map(String::from),
+            id: "0190f8a4-7c2e-7000-8000-000000000000".to_string(),
+        }
+    }
+
+    fn sql(filter: &UserFilter, cursor: Option<&UserCursor>) -> String {
+        find_all_query(filter, cursor, 21).sql().to_string()
+    }
+
+    #[test]
+    fn the_first_page_has_no_keyset_condition() {
+        let filter = filter(SORT_NAME, false);
+
+        let sql = sql(&filter, None);
+
+        assert!(!sql.contains("(name, id)"));
+        assert!(sql.ends_with(" ORDER BY name ASC, id ASC LIMIT ?"));
+    }
+
+    #[test]
+    fn sorting_by_creation_continues_after_the_id() {
+        for (descending, comparison, direction) in [(false, ">", "ASC"), (true, "<", "DESC")] {
+            let filter = filter(SORT_CREATED_AT, descending);
+            let cursor = cursor(&filter, None);
+
+            let sql = sql(&filter, Some(&cursor));
+
+            assert!(sql.contains(&format!(" AND id {} UUID_TO_BIN(?)", comparison)));
+            assert!(sql.ends_with(&format!(" ORDER BY id {} LIMIT ?", direction)));
+        }
+    }
+
+    #[test]
+    fn sorting_by_a_column_compares_the_column_and_the_id_as_a_row() {
+        for column in [SORT_NAME, SORT_EMAIL] {
+            for (descending, comparison, direction) in [(false, ">", "ASC"), (true, "<", "DESC")] {
+                let filter = filter(column, descending);
+                let cursor = cursor(&filter, Some("jane"));
+
+                let sql = sql(&filter, Some(&cursor));
+
+                assert!(sql.contains(&format!(
+                    " AND ({}, id) {} (?, UUID_TO_BIN(?))",
+                    column, comparison
+                )));
+                assert!(sql.ends_with(&format!(
+                    " ORDER BY {} {}, id {} LIMIT ?",
+                    column, direction, direction
+                )));
+            }
+        }
     }
 }
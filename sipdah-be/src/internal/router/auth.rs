@@ -1,9 +1,26 @@
+use crate::config::{Config, CookieSameSite, TokenDelivery};
+use crate::internal::common::id;
+use crate::internal::common::response::Json as IntoJson;
 use crate::internal::common::response::{json_error, json_success};
+use crate::internal::middleware::csrf::{verify_csrf, CSRF_COOKIE, CSRF_HEADER};
 use crate::internal::model::auth;
-use crate::internal::model::auth::{RefreshTokenRequest, SignInRequest, SignUpRequest};
+use crate::internal::model::auth::{
+    AuthResponse, Device, ForgotPasswordRequest, ImpersonationRequest, MagicLinkRequest,
+    RefreshTokenRequest, ResendEmailVerificationRequest, ResetPasswordRequest,
+    RestoreAccountRequest, SignInRequest, SignInResponse, SignUpRequest, UnlockSignInRequest,
+    VerifyEmailRequest, VerifyMagicLinkRequest, VerifyTwoFactorRequest,
+};
 use crate::internal::model::error::Error;
-use axum::extract::State;
-use axum::response::IntoResponse;
+use crate::internal::model::oidc::OidcCallbackRequest;
+use crate::internal::model::passkey::{
+    FinishPasskeyAuthenticationRequest, FinishPasskeyRegistrationRequest,
+    StartPasskeyAuthenticationRequest,
+};
+use crate::internal::model::two_factor::{SetupTwoFactorRequest, TwoFactorCodeRequest};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, Method};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
 use axum_extra::extract::cookie::SameSite;
 use axum_extra::extract::{cookie, CookieJar};
@@ -11,47 +28,198 @@ use cookie::Cookie;
 use std::sync::Arc;
 use time::Duration;
 
+// Lets a client override `Config::token_delivery` with `cookie`, `body` or `both`
+pub const TOKEN_DELIVERY_HEADER: &str = "x-token-delivery";
+
+// Ties a magic link to the browser that asked for it
+const MAGIC_LINK_COOKIE: &str = "magic_link_binding";
+// Ties a provider login to the browser that started it
+const OIDC_COOKIE: &str = "oidc_binding";
+
 #[derive(Clone)]
 pub struct AuthState<T1>
 where
     T1: auth::Service,
 {
+    pub config: Arc<Config>,
     pub auth_service: Arc<T1>,
 }
 
+fn token_delivery(config: &Config, headers: &HeaderMap) -> TokenDelivery {
+    headers
+        .get(TOKEN_DELIVERY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(config.token_delivery)
+}
+
+fn build_cookie(
+    config: &Config,
+    name: &'static str,
+    value: String,
+    max_age: chrono::Duration,
+    http_only: bool,
+) -> Cookie<'static> {
+    let same_site = match config.cookie_same_site {
+        CookieSameSite::Strict => SameSite::Strict,
+        CookieSameSite::Lax => SameSite::Lax,
+        CookieSameSite::None => SameSite::None,
+    };
+
+    let mut cookie = Cookie::build((name, value))
+        .http_only(http_only)
+        .secure(config.cookie_secure)
+        .same_site(same_site)
+        .max_age(Duration::seconds(max_age.num_seconds()))
+        .path("/");
+    if let Some(domain) = &config.cookie_domain {
+        cookie = cookie.domain(domain.clone());
+    }
+
+    cookie.build()
+}
+
+// Hands out fresh session tokens as cookies, in the body or both, as the client asked
+fn authenticated(
+    config: &Config,
+    jar: CookieJar,
+    headers: &HeaderMap,
+    mut res: AuthResponse,
+    message: &str,
+) -> Response {
+    let delivery = token_delivery(config, headers);
+    if delivery == TokenDelivery::Body {
+        return json_success(200, res, message.to_string()).into_response();
+    }
+
+    let (csrf, csrf_cookie) = issue_csrf_token(config);
+    let jar = jar
+        .add(build_cookie(
+            config,
+            "refresh_token",
+            res.refresh_token.clone(),
+            config.refresh_token_key_ttl,
+            true,
+        ))
+        .add(build_cookie(
+            config,
+            "access_token",
+            res.access_token.clone(),
+            config.access_token_key_ttl,
+            true,
+        ))
+        .add(build_cookie(
+            config,
+            "is_signed_in",
+            "true".to_string(),
+            config.refresh_token_key_ttl,
+            false,
+        ))
+        .add(csrf_cookie);
+
+    // Scripts never get to see tokens that live in cookies
+    if delivery == TokenDelivery::Cookie {
+        res.access_token.clear();
+        res.refresh_token.clear();
+    }
+
+    (
+        jar,
+        [(CSRF_HEADER, csrf)],
+        json_success(200, res, message.to_string()),
+    )
+        .into_response()
+}
+
+// A fresh double-submit token, lasting as long as the session cookies it protects
+fn issue_csrf_token(config: &Config) -> (String, Cookie<'static>) {
+    let csrf = id::random_token();
+    let cookie = build_cookie(
+        config,
+        CSRF_COOKIE,
+        csrf.clone(),
+        config.refresh_token_key_ttl,
+        false,
+    );
+
+    (csrf, cookie)
+}
+
+// Shared by every way of signing in, each can end in a second factor or an unverified address
+fn signed_in(
+    config: &Config,
+    jar: CookieJar,
+    headers: &HeaderMap,
+    res: Result<SignInResponse, Error>,
+) -> Response {
+    match res {
+        Ok(SignInResponse::TwoFactorRequired(challenge)) => (
+            jar,
+            json_success(
+                200,
+                challenge,
+                "Two-factor authentication is required".to_string(),
+            ),
+        )
+            .into_response(),
+        Ok(SignInResponse::EmailVerificationRequired(res)) => (
+            jar,
+            json_success(200, res, "Email address has not been verified".to_string()),
+        )
+            .into_response(),
+        Ok(SignInResponse::Authenticated(res)) => {
+            authenticated(config, jar, headers, res, "Signed in successfully!")
+        }
+        Err(err) => json_error::<String>(err).into_response(),
+    }
+}
+
+fn clear_cookies(config: &Config, jar: CookieJar) -> CookieJar {
+    ["refresh_token", "access_token", "is_signed_in", CSRF_COOKIE]
+        .into_iter()
+        .fold(jar, |jar, name| {
+            jar.remove(build_cookie(
+                config,
+                name,
+                String::new(),
+                chrono::Duration::zero(),
+                true,
+            ))
+        })
+}
+
+fn signed_out(config: &Config, jar: CookieJar) -> Response {
+    (
+        clear_cookies(config, jar),
+        json_success(200, (), "Signed out successfully".to_string()),
+    )
+        .into_response()
+}
+
 pub async fn sign_up<T1: auth::Service>(
     jar: CookieJar,
+    headers: HeaderMap,
+    device: Device,
     State(state): State<Arc<AuthState<T1>>>,
-    Json(req): Json<SignUpRequest>,
+    Json(mut req): Json<SignUpRequest>,
 ) -> impl IntoResponse + Send {
+    req.device = device;
+
     match state.auth_service.sign_up(&req).await {
-        Ok(res) => {
-            let refresh_token = Cookie::build(("refresh_token", res.refresh_token.clone()))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-            let access_token = Cookie::build(("access_token", res.access_token.clone()))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-            let is_signed_in = Cookie::build(("is_signed_in", "true"))
-                .http_only(false)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-
-            let jar = jar.add(refresh_token).add(access_token).add(is_signed_in);
-
-            (
-                jar,
-                json_success(200, res, "Signed up successfully!".to_string()),
-            )
-                .into_response()
+        Ok(SignInResponse::EmailVerificationRequired(res)) => json_success(
+            200,
+            res,
+            "Signed up successfully! Please verify your email before signing in".to_string(),
+        )
+        .into_response(),
+        Ok(SignInResponse::TwoFactorRequired(challenge)) => json_success(
+            200,
+            challenge,
+            "Two-factor authentication is required".to_string(),
+        )
+        .into_response(),
+        Ok(SignInResponse::Authenticated(res)) => {
+            authenticated(&state.config, jar, &headers, res, "Signed up successfully!")
         }
         Err(err) => json_error::<String>(err).into_response(),
     }
@@ -59,118 +227,175 @@ pub async fn sign_up<T1: auth::Service>(
 
 pub async fn sign_in<T1: auth::Service>(
     jar: CookieJar,
+    headers: HeaderMap,
+    device: Device,
     State(state): State<Arc<AuthState<T1>>>,
-    Json(req): Json<SignInRequest>,
-) -> impl IntoResponse + Send {
-    match state.auth_service.sign_in(&req).await {
-        Ok(res) => {
-            let refresh_token = Cookie::build(("refresh_token", res.refresh_token.clone()))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-            let access_token = Cookie::build(("access_token", res.access_token.clone()))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-            let is_signed_in = Cookie::build(("is_signed_in", "true"))
-                .http_only(false)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-
-            let jar = jar.add(refresh_token).add(access_token).add(is_signed_in);
-
-            (
-                jar,
-                json_success(200, res, "Signed up successfully!".to_string()),
-            )
-                .into_response()
-        }
+    Json(mut req): Json<SignInRequest>,
+) -> impl IntoResponse + Send {
+    req.device = device;
+
+    let res = state.auth_service.sign_in(&req).await;
+
+    signed_in(&state.config, jar, &headers, res)
+}
+
+pub async fn oidc_authorize<T1: auth::Service>(
+    jar: CookieJar,
+    headers: HeaderMap,
+    State(state): State<Arc<AuthState<T1>>>,
+    Path(provider): Path<String>,
+) -> impl IntoResponse + Send {
+    let binding = match token_delivery(&state.config, &headers) {
+        TokenDelivery::Body => None,
+        _ => Some(id::random_token()),
+    };
+
+    let res = match state
+        .auth_service
+        .oidc_authorize(&provider, binding.as_deref())
+        .await
+    {
+        Ok(res) => res,
+        Err(err) => return json_error::<String>(err).into_response(),
+    };
+
+    let jar = match binding {
+        Some(binding) => jar.add(build_cookie(
+            &state.config,
+            OIDC_COOKIE,
+            binding,
+            state.config.oidc_state_ttl,
+            true,
+        )),
+        None => jar,
+    };
+
+    (jar, json_success(200, res, "Success!".to_string())).into_response()
+}
+
+pub async fn oidc_callback<T1: auth::Service>(
+    jar: CookieJar,
+    headers: HeaderMap,
+    device: Device,
+    State(state): State<Arc<AuthState<T1>>>,
+    Path(provider): Path<String>,
+    Json(mut req): Json<OidcCallbackRequest>,
+) -> impl IntoResponse + Send {
+    req.provider = provider;
+    req.binding = jar
+        .get(OIDC_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    req.device = device;
+
+    let res = state.auth_service.oidc_callback(&req).await;
+    let jar = jar.remove(build_cookie(
+        &state.config,
+        OIDC_COOKIE,
+        String::new(),
+        chrono::Duration::zero(),
+        true,
+    ));
+
+    signed_in(&state.config, jar, &headers, res)
+}
+
+pub async fn verify_two_factor<T1: auth::Service>(
+    jar: CookieJar,
+    headers: HeaderMap,
+    device: Device,
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(mut req): Json<VerifyTwoFactorRequest>,
+) -> impl IntoResponse + Send {
+    req.device = device;
+
+    match state.auth_service.verify_two_factor(&req).await {
+        Ok(res) => authenticated(&state.config, jar, &headers, res, "Signed in successfully!"),
         Err(err) => json_error::<String>(err).into_response(),
     }
 }
 
+pub async fn setup_two_factor<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<SetupTwoFactorRequest>,
+) -> impl IntoResponse + Send {
+    state.auth_service.setup_two_factor(&req).await.json()
+}
+
+pub async fn enroll_two_factor<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.auth_service.enroll_two_factor().await.json()
+}
+
+pub async fn confirm_two_factor<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<TwoFactorCodeRequest>,
+) -> impl IntoResponse + Send {
+    state.auth_service.confirm_two_factor(&req).await.json()
+}
+
+pub async fn disable_two_factor<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<TwoFactorCodeRequest>,
+) -> impl IntoResponse + Send {
+    state.auth_service.disable_two_factor(&req).await.json()
+}
+
+// Web clients refresh with the `refresh_token` cookie, other clients post the token in the body
 pub async fn refresh<T1: auth::Service>(
     jar: CookieJar,
+    headers: HeaderMap,
+    method: Method,
+    device: Device,
     State(state): State<Arc<AuthState<T1>>>,
+    body: Bytes,
 ) -> impl IntoResponse + Send {
-    let refresh_token = match jar.get("refresh_token").map(|c| c.value().to_string()) {
-        Some(token) => token,
-        None => {
-            let error = Error::BadRequest("Cannot request without sign in".to_string());
-            return json_error::<String>(error).into_response();
+    let (mut req, is_cookie) = if !body.is_empty() {
+        match serde_json::from_slice::<RefreshTokenRequest>(&body) {
+            Ok(req) => (req, false),
+            Err(err) => {
+                return json_error::<String>(Error::BadRequest(err.to_string())).into_response()
+            }
+        }
+    } else {
+        match jar.get("refresh_token") {
+            Some(cookie) => (
+                RefreshTokenRequest {
+                    refresh_token: cookie.value().to_string(),
+                    device: Device::default(),
+                },
+                true,
+            ),
+            None => {
+                let error = Error::BadRequest("Cannot request without sign in".to_string());
+                return json_error::<String>(error).into_response();
+            }
         }
     };
+    req.device = device;
 
-    match state
-        .auth_service
-        .refresh(&RefreshTokenRequest { refresh_token })
-        .await
-    {
-        Ok(res) => {
-            let refresh_token = Cookie::build(("refresh_token", res.refresh_token.clone()))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-            let access_token = Cookie::build(("access_token", res.access_token.clone()))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-            let is_signed_in = Cookie::build(("is_signed_in", "true"))
-                .http_only(false)
-                .same_site(SameSite::None)
-                .max_age(Duration::days(7))
-                .path("/")
-                .build();
-
-            let jar = jar.add(refresh_token).add(access_token).add(is_signed_in);
-
-            (
-                jar,
-                json_success(200, res, "Signed up successfully!".to_string()),
-            )
-                .into_response()
+    let csrf = if is_cookie {
+        verify_csrf(&state.config, &jar, &method, &headers)
+    } else {
+        Ok(())
+    };
+    if let Err(error) = csrf {
+        return json_error::<String>(error).into_response();
+    }
+
+    match state.auth_service.refresh(&req).await {
+        Ok(res) => authenticated(
+            &state.config,
+            jar,
+            &headers,
+            res,
+            "Token refreshed successfully!",
+        ),
+        // A rejected token leaves stale cookies behind, other failures may pass on a retry
+        Err(err @ Error::Unauthorized(_)) if is_cookie => {
+            (clear_cookies(&state.config, jar), json_error::<String>(err)).into_response()
         }
-        Err(error) => {
-            let refresh_token = Cookie::build(("refresh_token", ""))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::seconds(0))
-                .path("/")
-                .build();
-            let access_token = Cookie::build(("access_token", ""))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::seconds(0))
-                .path("/")
-                .build();
-            let is_signed_in = Cookie::build(("is_signed_in", ""))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::seconds(0))
-                .path("/")
-                .build();
-
-            let jar = jar
-                .remove(refresh_token)
-                .add(access_token)
-                .add(is_signed_in);
-
-            (
-                jar,
-                json_success(200, (), "Signed out successfully".to_string()),
-            )
-                .into_response()
-        },
+        Err(err) => json_error::<String>(err).into_response(),
     }
 }
 
@@ -179,37 +404,234 @@ pub async fn sign_out<T1: auth::Service>(
     State(state): State<Arc<AuthState<T1>>>,
 ) -> impl IntoResponse + Send {
     match state.auth_service.sign_out().await {
-        Ok(_) => {
-            let refresh_token = Cookie::build(("refresh_token", ""))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::seconds(0))
-                .path("/")
-                .build();
-            let access_token = Cookie::build(("access_token", ""))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::seconds(0))
-                .path("/")
-                .build();
-            let is_signed_in = Cookie::build(("is_signed_in", ""))
-                .http_only(true)
-                .same_site(SameSite::None)
-                .max_age(Duration::seconds(0))
-                .path("/")
-                .build();
-
-            let jar = jar
-                .remove(refresh_token)
-                .add(access_token)
-                .add(is_signed_in);
-
-            (
-                jar,
-                json_success(200, (), "Signed out successfully".to_string()),
-            )
-                .into_response()
-        }
+        Ok(_) => signed_out(&state.config, jar),
+        Err(err) => json_error::<String>(err).into_response(),
+    }
+}
+
+// Browsers get a binding cookie for the link, body-token clients keep no cookies and stay unbound
+pub async fn send_magic_link<T1: auth::Service>(
+    jar: CookieJar,
+    headers: HeaderMap,
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(mut req): Json<MagicLinkRequest>,
+) -> impl IntoResponse + Send {
+    if token_delivery(&state.config, &headers) != TokenDelivery::Body {
+        req.binding = Some(id::random_token());
+    }
+
+    if let Err(err) = state.auth_service.send_magic_link(&req).await {
+        return json_error::<String>(err).into_response();
+    }
+
+    let jar = match req.binding {
+        Some(binding) => jar.add(build_cookie(
+            &state.config,
+            MAGIC_LINK_COOKIE,
+            binding,
+            state.config.magic_link_ttl,
+            true,
+        )),
+        None => jar,
+    };
+
+    (
+        jar,
+        json_success(
+            200,
+            (),
+            "If the email is registered, a sign-in link has been sent".to_string(),
+        ),
+    )
+        .into_response()
+}
+
+pub async fn verify_magic_link<T1: auth::Service>(
+    jar: CookieJar,
+    headers: HeaderMap,
+    device: Device,
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(mut req): Json<VerifyMagicLinkRequest>,
+) -> impl IntoResponse + Send {
+    req.binding = jar
+        .get(MAGIC_LINK_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    req.device = device;
+
+    let res = state.auth_service.verify_magic_link(&req).await;
+    let jar = jar.remove(build_cookie(
+        &state.config,
+        MAGIC_LINK_COOKIE,
+        String::new(),
+        chrono::Duration::zero(),
+        true,
+    ));
+
+    signed_in(&state.config, jar, &headers, res)
+}
+
+pub async fn forgot_password<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<ForgotPasswordRequest>,
+) -> impl IntoResponse + Send {
+    state.auth_service.forgot_password(&req).await.json_with(
+        200,
+        "If the email is registered, a reset link has been sent".to_string(),
+    )
+}
+
+pub async fn reset_password<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .auth_service
+        .reset_password(&req)
+        .await
+        .json_with(200, "Password has been reset".to_string())
+}
+
+pub async fn restore_account<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<RestoreAccountRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .auth_service
+        .restore_account(&req)
+        .await
+        .json_with(200, "Account has been restored".to_string())
+}
+
+pub async fn verify_email<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<VerifyEmailRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .auth_service
+        .verify_email(&req)
+        .await
+        .json_with(200, "Email address has been verified".to_string())
+}
+
+pub async fn resend_email_verification<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<ResendEmailVerificationRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .auth_service
+        .resend_email_verification(&req)
+        .await
+        .json_with(
+            200,
+            "If the email awaits verification, a new link has been sent".to_string(),
+        )
+}
+
+pub async fn unlock_sign_in<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<UnlockSignInRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .auth_service
+        .unlock_sign_in(&req)
+        .await
+        .json_with(200, "Sign-in has been unlocked".to_string())
+}
+
+pub async fn get_sessions<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.auth_service.get_sessions().await.json()
+}
+
+pub async fn revoke_session<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse + Send {
+    state.auth_service.revoke_session(&session_id).await.json()
+}
+
+pub async fn revoke_other_sessions<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.auth_service.revoke_other_sessions().await.json()
+}
+
+pub async fn jwks<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+) -> impl IntoResponse + Send {
+    // Served as a bare JWK set so that standard JOSE libraries can consume it
+    Json(state.auth_service.jwks())
+}
+
+pub async fn start_impersonation<T1: auth::Service>(
+    device: Device,
+    State(state): State<Arc<AuthState<T1>>>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse + Send {
+    // Tokens stay in the body so the administrator's own cookies are left alone
+    let req = ImpersonationRequest { user_id, device };
+    state.auth_service.start_impersonation(&req).await.json()
+}
+
+pub async fn end_impersonation<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.auth_service.end_impersonation().await.json()
+}
+
+pub async fn start_passkey_registration<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.auth_service.start_passkey_registration().await.json()
+}
+
+pub async fn finish_passkey_registration<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<FinishPasskeyRegistrationRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .auth_service
+        .finish_passkey_registration(&req)
+        .await
+        .json()
+}
+
+pub async fn start_passkey_authentication<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(req): Json<StartPasskeyAuthenticationRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .auth_service
+        .start_passkey_authentication(&req)
+        .await
+        .json()
+}
+
+pub async fn finish_passkey_authentication<T1: auth::Service>(
+    jar: CookieJar,
+    headers: HeaderMap,
+    device: Device,
+    State(state): State<Arc<AuthState<T1>>>,
+    Json(mut req): Json<FinishPasskeyAuthenticationRequest>,
+) -> impl IntoResponse + Send {
+    req.device = device;
+
+    match state.auth_service.finish_passkey_authentication(&req).await {
+        Ok(res) => authenticated(&state.config, jar, &headers, res, "Signed in successfully!"),
         Err(err) => json_error::<String>(err).into_response(),
     }
 }
+
+pub async fn get_passkeys<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.auth_service.get_passkeys().await.json()
+}
+
+pub async fn delete_passkey<T1: auth::Service>(
+    State(state): State<Arc<AuthState<T1>>>,
+    Path(passkey_id): Path<String>,
+) -> impl IntoResponse + Send {
+    state.auth_service.delete_passkey(&passkey_id).await.json()
+}
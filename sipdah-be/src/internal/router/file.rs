@@ -0,0 +1,36 @@
+use crate::internal::common::response::json_error;
+use crate::internal::model::file;
+use axum::extract::{Path, State};
+use axum::http::header;
+use axum::response::IntoResponse;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct FileState<T1>
+where
+    T1: file::Service,
+{
+    pub file_service: Arc<T1>,
+}
+
+// A new upload always gets a new id, so the content behind a URL never changes
+pub async fn get_public<T1: file::Service>(
+    State(state): State<Arc<FileState<T1>>>,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse + Send {
+    match state.file_service.get_public(&file_id).await {
+        Ok((file, content)) => (
+            [
+                (header::CONTENT_TYPE, file.file_type),
+                (
+                    header::CACHE_CONTROL,
+                    "public, max-age=31536000, immutable".to_string(),
+                ),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            ],
+            content,
+        )
+            .into_response(),
+        Err(err) => json_error::<String>(err).into_response(),
+    }
+}
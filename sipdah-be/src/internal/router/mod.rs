@@ -2,3 +2,5 @@ pub mod auth;
 pub mod user;
 pub mod project;
 pub mod role;
+pub mod personal_access_token;
+pub mod file;
@@ -0,0 +1,46 @@
+use crate::internal::common::response::Json as IntoJson;
+use crate::internal::model::personal_access_token;
+use crate::internal::model::personal_access_token::CreatePersonalAccessTokenRequest;
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use axum::Json;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct PersonalAccessTokenState<T1>
+where
+    T1: personal_access_token::Service,
+{
+    pub personal_access_token_service: Arc<T1>,
+}
+
+pub async fn create<T1: personal_access_token::Service>(
+    State(state): State<Arc<PersonalAccessTokenState<T1>>>,
+    Json(req): Json<CreatePersonalAccessTokenRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .personal_access_token_service
+        .create(&req)
+        .await
+        .json_with(
+            201,
+            "Token created. Copy it now, it will not be shown again".to_string(),
+        )
+}
+
+pub async fn get_all<T1: personal_access_token::Service>(
+    State(state): State<Arc<PersonalAccessTokenState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.personal_access_token_service.get_all().await.json()
+}
+
+pub async fn revoke<T1: personal_access_token::Service>(
+    State(state): State<Arc<PersonalAccessTokenState<T1>>>,
+    Path(token_id): Path<String>,
+) -> impl IntoResponse + Send {
+    state
+        .personal_access_token_service
+        .revoke(&token_id)
+        .await
+        .json()
+}
@@ -2,8 +2,7 @@ use crate::internal::common::response::Json as IntoJson;
 use crate::internal::model::role;
 use axum::{
     extract::{Path, State},
-    response::IntoResponse
-    ,
+    response::IntoResponse,
 };
 use std::sync::Arc;
 
@@ -34,3 +33,38 @@ pub async fn get_all<T1: role::Service>(
 ) -> impl IntoResponse + Send {
     state.role_service.find_all().await.json()
 }
+
+pub async fn get_all_permissions<T1: role::Service>(
+    State(state): State<Arc<RoleState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.role_service.find_all_permissions().await.json()
+}
+
+pub async fn get_permissions<T1: role::Service>(
+    State(state): State<Arc<RoleState<T1>>>,
+    Path(role_id): Path<String>,
+) -> impl IntoResponse + Send {
+    state.role_service.find_permissions(&role_id).await.json()
+}
+
+pub async fn grant_permission<T1: role::Service>(
+    State(state): State<Arc<RoleState<T1>>>,
+    Path((role_id, permission)): Path<(String, String)>,
+) -> impl IntoResponse + Send {
+    state
+        .role_service
+        .grant_permission(&role_id, &permission)
+        .await
+        .json()
+}
+
+pub async fn revoke_permission<T1: role::Service>(
+    State(state): State<Arc<RoleState<T1>>>,
+    Path((role_id, permission)): Path<(String, String)>,
+) -> impl IntoResponse + Send {
+    state
+        .role_service
+        .revoke_permission(&role_id, &permission)
+        .await
+        .json()
+}
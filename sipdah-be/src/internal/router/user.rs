@@ -1,7 +1,16 @@
+use crate::internal::common::response::json_error;
 use crate::internal::common::response::Json as IntoJson;
+use crate::internal::model::error::Error;
+use crate::internal::model::file::FileUpload;
 use crate::internal::model::user;
-use crate::internal::model::user::AddRolesRequest;
-use axum::extract::{Path, State};
+use crate::internal::model::user::{
+    ChangeEmailRequest, ChangePasswordRequest, DeleteAccountRequest, ReplaceRolesRequest,
+    UpdateProfileRequest, UpdateRolesRequest, UserFilter,
+};
+use crate::internal::model::web::PageRequest;
+use axum::extract::multipart::MultipartError;
+use axum::extract::{Multipart, Path, Query, State};
+use axum::http::header;
 use axum::response::IntoResponse;
 use axum::Json;
 use std::sync::Arc;
@@ -27,11 +36,161 @@ pub async fn get_current<T1: user::Service>(
     state.user_service.get_current().await.json()
 }
 
-pub async fn add_roles<T1: user::Service>(
+pub async fn get_all<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Query(page): Query<PageRequest>,
+    Query(filter): Query<UserFilter>,
+) -> impl IntoResponse + Send {
+    state.user_service.get_all(page, filter).await.json()
+}
+
+pub async fn update_roles<T1: user::Service>(
     State(state): State<Arc<UserState<T1>>>,
     Path(user_id): Path<String>,
-    Json(mut req): Json<AddRolesRequest>,
+    Json(mut req): Json<UpdateRolesRequest>,
 ) -> impl IntoResponse + Send {
     req.user_id = user_id;
-    state.user_service.add_roles(req).await.json()
+    state.user_service.update_roles(req).await.json()
+}
+
+pub async fn replace_roles<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Path(user_id): Path<String>,
+    Json(mut req): Json<ReplaceRolesRequest>,
+) -> impl IntoResponse + Send {
+    req.user_id = user_id;
+    state.user_service.replace_roles(req).await.json()
+}
+
+pub async fn remove_role<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Path((user_id, role_id)): Path<(String, String)>,
+) -> impl IntoResponse + Send {
+    state
+        .user_service
+        .remove_role(&user_id, &role_id)
+        .await
+        .json()
+}
+
+pub async fn change_password<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Json(req): Json<ChangePasswordRequest>,
+) -> impl IntoResponse + Send {
+    state.user_service.change_password(req).await.json()
+}
+
+pub async fn change_email<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Json(req): Json<ChangeEmailRequest>,
+) -> impl IntoResponse + Send {
+    state.user_service.change_email(req).await.json()
+}
+
+pub async fn get_login_history<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+) -> impl IntoResponse + Send {
+    state.user_service.get_login_history().await.json()
+}
+
+pub async fn get_login_history_by_user_id<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse + Send {
+    state
+        .user_service
+        .get_login_history_by_user_id(&user_id)
+        .await
+        .json()
+}
+
+pub async fn delete_current<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Json(req): Json<DeleteAccountRequest>,
+) -> impl IntoResponse + Send {
+    state
+        .user_service
+        .delete_current(req)
+        .await
+        .json_with(200, "Account has been deleted".to_string())
+}
+
+pub async fn deactivate<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse + Send {
+    state
+        .user_service
+        .deactivate(&user_id)
+        .await
+        .json_with(200, "User has been deactivated".to_string())
+}
+
+pub async fn reactivate<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse + Send {
+    state
+        .user_service
+        .reactivate(&user_id)
+        .await
+        .json_with(200, "User has been reactivated".to_string())
+}
+
+// Sent as a download, outside the usual response envelope
+pub async fn export<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+) -> impl IntoResponse + Send {
+    match state.user_service.export().await {
+        Ok(export) => (
+            [(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"sipdah-export.json\"",
+            )],
+            Json(export),
+        )
+            .into_response(),
+        Err(err) => json_error::<String>(err).into_response(),
+    }
+}
+
+async fn read_profile_request(mut multipart: Multipart) -> Result<UpdateProfileRequest, Error> {
+    let invalid = |err: MultipartError| Error::BadRequest(err.body_text());
+
+    let mut req = UpdateProfileRequest::default();
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        match field.name() {
+            Some("name") => {
+                req.name = Some(field.text().await.map_err(invalid)?.trim().to_string())
+            }
+            Some("phone_number") => {
+                req.phone_number = Some(field.text().await.map_err(invalid)?.trim().to_string())
+            }
+            Some("avatar") => {
+                let file_name = field.file_name().map(String::from);
+                let content = field.bytes().await.map_err(invalid)?.to_vec();
+                req.avatar = Some(FileUpload { file_name, content });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(req)
+}
+
+pub async fn update_profile<T1: user::Service>(
+    State(state): State<Arc<UserState<T1>>>,
+    multipart: Multipart,
+) -> impl IntoResponse + Send {
+    let req = match read_profile_request(multipart).await {
+        Ok(req) => req,
+        Err(err) => return json_error::<String>(err).into_response(),
+    };
+
+    state
+        .user_service
+        .update_profile(req)
+        .await
+        .json()
+        .into_response()
 }
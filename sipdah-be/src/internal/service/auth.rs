@@ -1,175 +1,722 @@
-use crate::config::Config;
+use crate::config::{Config, EmailVerificationPolicy, OidcProvider};
 use crate::internal::common::id;
 use crate::internal::common::uow::Uow;
+use crate::internal::model::audit_log::{
+    AuditLog, Repository as AuditLogRepository, ACTION_IMPERSONATION_END,
+    ACTION_IMPERSONATION_REFRESH, ACTION_IMPERSONATION_START,
+};
 use crate::internal::model::auth::{
-    AuthResponse, Claim, RefreshTokenRequest, Service as AuthService, SignInRequest, SignUpRequest,
+    AuthResponse, Claim, Device, EmailVerificationClaim, EmailVerificationResponse,
+    ForgotPasswordRequest, ImpersonationRequest, MagicLinkClaim, MagicLinkRequest,
+    RefreshTokenRequest, ResendEmailVerificationRequest, ResetPasswordRequest,
+    RestoreAccountRequest, Service as AuthService, SignInRequest, SignInResponse, SignUpRequest,
+    TokenType, TwoFactorChallenge, TwoFactorChallengeResponse, UnlockSignInRequest,
+    VerifyEmailRequest, VerifyMagicLinkRequest, VerifyTwoFactorRequest,
 };
 use crate::internal::model::error::Error;
-use crate::internal::model::identity::get_current_identity;
-use crate::internal::model::role::{Repository as RoleRepository, ROLE_USER};
-use crate::internal::model::user::{Repository as UserRepository, User};
+use crate::internal::model::identity::{
+    get_current_identity, require_account_owner, require_permission, Identity,
+};
+use crate::internal::model::login_event::{
+    LoginEvent, Repository as LoginEventRepository, KIND_IMPERSONATION, KIND_REFRESH, KIND_SIGN_IN,
+    OUTCOME_FAILURE, OUTCOME_LOCKED, OUTCOME_SUCCESS,
+};
+use crate::internal::model::oidc::{
+    OidcAuthorizationResponse, OidcCallbackRequest, OidcState, Repository as OidcRepository,
+    UserIdentity,
+};
+use crate::internal::model::passkey::{
+    passkey_authentication_key, passkey_registration_key, FinishPasskeyAuthenticationRequest,
+    FinishPasskeyRegistrationRequest, Passkey, PasskeyAuthenticationResponse, PasskeyChallenge,
+    PasskeyResponse, Repository as PasskeyRepository, StartPasskeyAuthenticationRequest,
+};
+use crate::internal::model::permission::{
+    role_permissions_key, Repository as PermissionRepository, CACHE_ROLE_PERMISSIONS,
+    PERMISSION_USER_MANAGE,
+};
+use crate::internal::model::personal_access_token::Repository as PersonalAccessTokenRepository;
+use crate::internal::model::revoked_token::Repository as RevokedTokenRepository;
+use crate::internal::model::role::{
+    user_roles_key, Repository as RoleRepository, CACHE_USER_ROLES, ROLE_SUPER_ADMIN, ROLE_USER,
+};
+use crate::internal::model::session::{Repository as SessionRepository, Session, SessionResponse};
+use crate::internal::model::two_factor::{
+    RecoveryCode, RecoveryCodesResponse, Repository as TwoFactorRepository, SetupTwoFactorRequest,
+    TwoFactor, TwoFactorCodeRequest, TwoFactorEnrollmentResponse,
+};
+use crate::internal::model::user::{
+    account_restore_key, pending_email_key, user_detail_key, Repository as UserRepository, User,
+};
 use crate::internal::provider::cache::Cache as CacheProvider;
-use chrono::Local;
+use crate::internal::provider::jwt::Keyring;
+use crate::internal::provider::mailer::{spawn_send, Mail, Mailer};
+use crate::internal::provider::oidc::{Client as OidcClient, IdTokenClaims};
+use crate::internal::provider::password::Hasher as PasswordHasher;
+use crate::internal::provider::webauthn::disguise_as_user;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Local};
 use jsonwebtoken::errors::ErrorKind;
-use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use jsonwebtoken::jwk::JwkSet;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
 use std::ops::Add;
 use std::sync::Arc;
-use tracing::info;
+use totp_rs::{Secret, TOTP};
+use tracing::{info, warn};
 use uow_macro::uow;
+use uuid::Uuid;
 use validator::Validate;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, DiscoverableKey, Passkey as WebauthnPasskey, PasskeyRegistration,
+};
+use webauthn_rs::Webauthn;
+
+const MAX_CHALLENGE_ATTEMPTS: i64 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+// Past sign-ins a new one is compared with to tell whether the device is known
+const KNOWN_DEVICE_LOOKBACK: u32 = 50;
+// Keeps token requests from writing to the database every time
+const TOKEN_LAST_USED_RESOLUTION_MINUTES: i64 = 5;
+
+fn challenge_key(challenge_token: &str) -> String {
+    format!("auth:2fa-challenge:{}", challenge_token)
+}
+
+// Kept apart from the challenge so concurrent guesses cannot overwrite each other's count
+fn challenge_attempts_key(challenge_token: &str) -> String {
+    format!("auth:2fa-challenge-attempts:{}", challenge_token)
+}
+
+fn new_recovery_code() -> String {
+    let code = &id::random_token()[..10];
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn oidc_state_key(state: &str) -> String {
+    format!("auth:oidc-state:{}", state)
+}
+
+fn password_reset_key(token: &str) -> String {
+    format!("auth:password-reset:{}", id::hash_token(token))
+}
+
+fn email_verification_resend_key(email: &str) -> String {
+    format!("auth:email-verification-resend:{}", email.to_lowercase())
+}
+
+fn password_reset_requests_key(email: &str) -> String {
+    format!("auth:password-reset-requests:{}", email.to_lowercase())
+}
+
+fn magic_link_key(jti: &str) -> String {
+    format!("auth:magic-link:{}", jti)
+}
+
+fn magic_link_requests_key(email: &str) -> String {
+    format!("auth:magic-link-requests:{}", email.to_lowercase())
+}
+
+// Failures are counted per subject, either `account:{email}` or `ip:{address}`
+fn sign_in_failures_key(subject: &str) -> String {
+    format!("auth:sign-in-failures:{}", subject)
+}
+
+fn sign_in_delay_key(subject: &str) -> String {
+    format!("auth:sign-in-delay:{}", subject)
+}
+
+fn sign_in_lock_key(subject: &str) -> String {
+    format!("auth:sign-in-lock:{}", subject)
+}
+
+fn account_subject(email: &str) -> String {
+    format!("account:{}", email.to_lowercase())
+}
+
+fn ip_subject(device: &Device) -> Option<String> {
+    device.ip_address.as_deref().map(ip_address_subject)
+}
+
+fn ip_address_subject(ip_address: &str) -> String {
+    format!("ip:{}", ip_address)
+}
+
+// Every lockout a sign-in by `email` from `device` has to pass
+fn sign_in_subjects(email: &str, device: &Device) -> Vec<String> {
+    std::iter::once(account_subject(email))
+        .chain(ip_subject(device))
+        .collect()
+}
+
+// Addresses in the same /24 (IPv4) or /48 (IPv6) are treated as the same network
+fn ip_range(ip_address: &str) -> String {
+    match ip_address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let octets = ip.octets();
+            format!("{}.{}.{}.0/24", octets[0], octets[1], octets[2])
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+        Err(_) => ip_address.to_string(),
+    }
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    id::hash_token(&normalized)
+}
+
+// Mails a signed verification link for the current address of `user`, shared with the user service
+pub(crate) async fn send_email_verification<M: Mailer>(
+    config: &Config,
+    keyring: &Keyring,
+    mailer: &M,
+    user: &User,
+) -> Result<(), Error> {
+    mailer
+        .send(&email_verification_mail(config, keyring, user)?)
+        .await
+}
+
+fn email_verification_mail(config: &Config, keyring: &Keyring, user: &User) -> Result<Mail, Error> {
+    let token = keyring.sign(&EmailVerificationClaim {
+        sub: user.id.clone(),
+        email: user.email.clone(),
+        exp: chrono::Utc::now()
+            .add(config.email_verification_ttl)
+            .timestamp(),
+        iat: chrono::Utc::now().timestamp(),
+        typ: TokenType::EmailVerification,
+    })?;
+
+    let link = format!("{}/verify-email?token={}", config.app_url, token);
+    Ok(Mail {
+        to: user.email.clone(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+            Please confirm your email address by opening the link below. \
+            It expires in {} hours.\n\n{}\n\n\
+            If you did not request this, you can ignore this email.",
+            user.name,
+            config.email_verification_ttl.num_hours(),
+            link
+        ),
+    })
+}
+
+fn parse_passkey(credential: &str) -> Result<WebauthnPasskey, Error> {
+    serde_json::from_str(credential).map_err(|err| Error::Internal(err.to_string()))
+}
+
+// The library keeps the counter to itself, but it is part of the serialized credential
+fn initial_sign_count(credential: &WebauthnPasskey) -> u32 {
+    serde_json::to_value(credential)
+        .ok()
+        .and_then(|value| value["cred"]["counter"].as_u64())
+        .and_then(|counter| u32::try_from(counter).ok())
+        .unwrap_or(0)
+}
 
 #[derive(Clone)]
-pub struct Service<T1, T2, T3, T4>
+pub struct Service<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
 where
     T1: Uow,
     T2: UserRepository,
     T3: RoleRepository,
-    T4: CacheProvider,
+    T4: SessionRepository,
+    T5: RevokedTokenRepository,
+    T6: TwoFactorRepository,
+    T7: CacheProvider,
+    T8: Mailer,
+    T9: OidcRepository,
+    T10: PersonalAccessTokenRepository,
+    T11: PermissionRepository,
+    T12: PasswordHasher,
+    T13: AuditLogRepository,
+    T14: PasskeyRepository,
+    T15: LoginEventRepository,
 {
     config: Arc<Config>,
+    keyring: Arc<Keyring>,
+    oidc_client: Arc<OidcClient>,
+    webauthn: Arc<Webauthn>,
     uow: Arc<T1>,
     user_repo: Arc<T2>,
     role_repo: Arc<T3>,
-    cache_repo: Arc<T4>,
+    session_repo: Arc<T4>,
+    revoked_token_repo: Arc<T5>,
+    two_factor_repo: Arc<T6>,
+    cache_provider: Arc<T7>,
+    mailer: Arc<T8>,
+    user_identity_repo: Arc<T9>,
+    personal_access_token_repo: Arc<T10>,
+    permission_repo: Arc<T11>,
+    password_hasher: Arc<T12>,
+    audit_log_repo: Arc<T13>,
+    passkey_repo: Arc<T14>,
+    login_event_repo: Arc<T15>,
 }
 
-impl<T1, T2, T3, T4> Service<T1, T2, T3, T4>
+impl<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
+    Service<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
 where
     T1: Uow,
     T2: UserRepository,
     T3: RoleRepository,
-    T4: CacheProvider,
+    T4: SessionRepository,
+    T5: RevokedTokenRepository,
+    T6: TwoFactorRepository,
+    T7: CacheProvider,
+    T8: Mailer + Send + Sync + 'static,
+    T9: OidcRepository,
+    T10: PersonalAccessTokenRepository,
+    T11: PermissionRepository,
+    T12: PasswordHasher,
+    T13: AuditLogRepository,
+    T14: PasskeyRepository,
+    T15: LoginEventRepository,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: Arc<Config>,
+        keyring: Arc<Keyring>,
+        oidc_client: Arc<OidcClient>,
+        webauthn: Arc<Webauthn>,
         uow: Arc<T1>,
         user_repo: Arc<T2>,
         role_repo: Arc<T3>,
-        cache_repo: Arc<T4>,
+        session_repo: Arc<T4>,
+        revoked_token_repo: Arc<T5>,
+        two_factor_repo: Arc<T6>,
+        cache_provider: Arc<T7>,
+        mailer: Arc<T8>,
+        user_identity_repo: Arc<T9>,
+        personal_access_token_repo: Arc<T10>,
+        permission_repo: Arc<T11>,
+        password_hasher: Arc<T12>,
+        audit_log_repo: Arc<T13>,
+        passkey_repo: Arc<T14>,
+        login_event_repo: Arc<T15>,
     ) -> Self {
         Self {
             config,
+            keyring,
+            oidc_client,
+            webauthn,
             uow,
             user_repo,
             role_repo,
-            cache_repo,
+            session_repo,
+            revoked_token_repo,
+            two_factor_repo,
+            cache_provider,
+            mailer,
+            user_identity_repo,
+            personal_access_token_repo,
+            permission_repo,
+            password_hasher,
+            audit_log_repo,
+            passkey_repo,
+            login_event_repo,
         }
     }
 
-    async fn issue_tokens(&self, user: &User) -> Result<AuthResponse, Error> {
-        let access_token = self.create_token(
-            self.config.access_token_key_secret.as_ref(),
-            &user.id,
-            &user.email,
-            chrono::Utc::now()
-                .add(self.config.access_token_key_ttl)
-                .timestamp(),
-            chrono::Utc::now().timestamp(),
-        )?;
-        let refresh_token = self.create_token(
-            self.config.refresh_token_key_secret.as_ref(),
-            &user.id,
+    fn new_session(&self, user: &User, device: &Device) -> Session {
+        Session {
+            id: id::new(),
+            user_id: user.id.clone(),
+            refresh_token_id: id::new(),
+            access_token_id: id::new(),
+            access_token_expires_at: Local::now().add(self.config.access_token_key_ttl),
+            user_agent: device.user_agent.clone(),
+            ip_address: device.ip_address.clone(),
+            created_at: Local::now(),
+            last_used_at: Local::now(),
+            actor_id: None,
+        }
+    }
+
+    async fn start_session(&self, user: &User, device: &Device) -> Result<AuthResponse, Error> {
+        let session = self.new_session(user, device);
+
+        info!("Starting session {} for user {}", session.id, user.email);
+
+        let res = self.issue_tokens(user, session).await?;
+
+        // The session exists by now, losing its tokens over the history would be worse
+        let is_new_device = self
+            .is_new_device(&user.id, device)
+            .await
+            .unwrap_or_else(|err| {
+                warn!("Failed to check the device of {}: {}", user.email, err);
+                false
+            });
+        self.record_login_event(
+            Some(&user.id),
             &user.email,
-            chrono::Utc::now()
+            KIND_SIGN_IN,
+            OUTCOME_SUCCESS,
+            device,
+        )
+        .await;
+        if is_new_device {
+            self.send_new_device_alert(user, device);
+        }
+
+        Ok(res)
+    }
+
+    // Only logged on failure, the history never decides the outcome of what it records
+    async fn record_login_event(
+        &self,
+        user_id: Option<&str>,
+        email: &str,
+        kind: &str,
+        outcome: &str,
+        device: &Device,
+    ) {
+        let recorded = self
+            .login_event_repo
+            .create(&LoginEvent {
+                id: id::new(),
+                user_id: user_id.map(String::from),
+                email: email.to_string(),
+                kind: kind.to_string(),
+                outcome: outcome.to_string(),
+                ip_address: device.ip_address.clone(),
+                user_agent: device.user_agent.clone(),
+                created_at: Local::now(),
+            })
+            .await;
+        if let Err(err) = recorded {
+            warn!("Failed to record {} of {}: {}", kind, email, err);
+        }
+    }
+
+    // New when no recent sign-in shares its user agent and network, never on the first sign-in
+    async fn is_new_device(&self, user_id: &str, device: &Device) -> Result<bool, Error> {
+        let sign_ins = self
+            .login_event_repo
+            .find_successes_by_user_id(user_id, KIND_SIGN_IN, KNOWN_DEVICE_LOOKBACK)
+            .await?;
+        let range = device.ip_address.as_deref().map(ip_range);
+        let is_known = sign_ins.iter().any(|sign_in| {
+            sign_in.user_agent == device.user_agent
+                && sign_in.ip_address.as_deref().map(ip_range) == range
+        });
+
+        Ok(!sign_ins.is_empty() && !is_known)
+    }
+
+    // Best effort, an unreachable mail server must not keep the user from signing in
+    fn send_new_device_alert(&self, user: &User, device: &Device) {
+        let unknown = "unknown".to_string();
+        let mail = Mail {
+            to: user.email.clone(),
+            subject: "New sign-in to your account".to_string(),
+            body: format!(
+                "Hi {},\n\n\
+                Your account was just signed in to from a device or network we have not seen \
+                before.\n\n\
+                Time: {}\n\
+                IP address: {}\n\
+                Device: {}\n\n\
+                If this was you, you can ignore this email. Otherwise change your password and \
+                sign out of your other sessions right away.",
+                user.name,
+                Local::now().format("%Y-%m-%d %H:%M:%S %:z"),
+                device.ip_address.as_ref().unwrap_or(&unknown),
+                device.user_agent.as_ref().unwrap_or(&unknown),
+            ),
+        };
+
+        info!("Sending new device alert to user {}", user.email);
+        spawn_send(Arc::clone(&self.mailer), mail);
+    }
+
+    async fn issue_tokens(&self, user: &User, session: Session) -> Result<AuthResponse, Error> {
+        let roles = self.find_roles(&user.id).await?;
+        let access_token = self.keyring.sign(&Claim {
+            jti: session.access_token_id.clone(),
+            sid: session.id.clone(),
+            sub: user.id.clone(),
+            email: user.email.clone(),
+            email_verified: user.email_verified_at.is_some(),
+            roles: roles.clone(),
+            act: session.actor_id.clone(),
+            exp: session.access_token_expires_at.timestamp(),
+            iat: chrono::Utc::now().timestamp(),
+            typ: TokenType::Access,
+        })?;
+        let refresh_token = self.keyring.sign(&Claim {
+            jti: session.refresh_token_id.clone(),
+            sid: session.id.clone(),
+            sub: user.id.clone(),
+            email: user.email.clone(),
+            email_verified: user.email_verified_at.is_some(),
+            roles,
+            act: session.actor_id.clone(),
+            exp: chrono::Utc::now()
                 .add(self.config.refresh_token_key_ttl)
                 .timestamp(),
-            chrono::Utc::now().timestamp(),
-        )?;
-        let key = format!("auth:refresh-token:{}", user.id);
+            iat: chrono::Utc::now().timestamp(),
+            typ: TokenType::Refresh,
+        })?;
 
-        self.cache_repo
-            .setx(key, &refresh_token, self.config.refresh_token_key_ttl)
-            .await?;
+        self.session_repo.save(&session).await?;
 
         Ok(AuthResponse {
             user_id: user.id.clone(),
             email: user.email.clone(),
             refresh_token,
             access_token,
+            recovery_codes: None,
         })
     }
 
-    fn create_token(
+    async fn check_sign_in_allowed(&self, subjects: &[String]) -> Result<(), Error> {
+        for subject in subjects {
+            let is_locked = self
+                .cache_provider
+                .get::<bool>(sign_in_lock_key(subject))
+                .await?
+                .is_some();
+            let is_delayed = self
+                .cache_provider
+                .get::<bool>(sign_in_delay_key(subject))
+                .await?
+                .is_some();
+
+            if is_locked || is_delayed {
+                return Err(Error::TooManyRequests(
+                    "Too many failed sign-in attempts, please try again later".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn record_sign_in_failure(&self, subject: &str, max_attempts: i64) -> Result<(), Error> {
+        let ttl = self.config.sign_in_lockout_ttl;
+        let failures = self
+            .cache_provider
+            .incr(sign_in_failures_key(subject), ttl)
+            .await?;
+
+        if failures >= max_attempts {
+            warn!(
+                "Sign-in locked for {} after {} failed attempts",
+                subject, failures
+            );
+            return self
+                .cache_provider
+                .setx(sign_in_lock_key(subject), true, ttl)
+                .await;
+        }
+
+        if failures >= self.config.sign_in_delay_after {
+            // The wait doubles with every failure past the threshold
+            let exponent = (failures - self.config.sign_in_delay_after).min(16) as u32;
+            let delay = Duration::seconds(2_i64.pow(exponent)).min(ttl);
+            self.cache_provider
+                .setx(sign_in_delay_key(subject), true, delay)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn clear_sign_in_failures(&self, subject: &str) -> Result<(), Error> {
+        self.cache_provider
+            .del(sign_in_failures_key(subject))
+            .await?;
+        self.cache_provider.del(sign_in_delay_key(subject)).await?;
+        self.cache_provider.del(sign_in_lock_key(subject)).await
+    }
+
+    // Links for an address the user has since moved away from find no pending change
+    async fn confirm_email_change(&self, user: &User, email: &str) -> Result<(), Error> {
+        let pending_email = self
+            .cache_provider
+            .get::<String>(pending_email_key(&user.id))
+            .await?;
+        if pending_email.as_deref() != Some(email) {
+            return Err(Error::BadRequest("Token is not valid".to_string()));
+        }
+        if self.user_repo.exists_by_email(email).await? {
+            return Err(Error::Conflict(format!("Email {} already exists", email)));
+        }
+
+        info!("Email changed for user {} to {}", user.id, email);
+
+        self.user_repo.update_email(&user.id, email).await?;
+        self.cache_provider.del(pending_email_key(&user.id)).await?;
+        self.uow.evict(user_detail_key(&user.id)).await
+    }
+
+    // Second factors apply the same way whichever first factor the user signed in with
+    async fn complete_sign_in(
         &self,
-        key: &[u8],
-        user_id: &str,
-        email: &str,
-        exp: i64,
-        iat: i64,
-    ) -> Result<String, Error> {
-        let claims = serde_json::json!(Claim {
-            sub: user_id.to_string(),
-            email: String::from(email),
-            exp,
-            iat,
-        });
+        user: &User,
+        device: &Device,
+    ) -> Result<SignInResponse, Error> {
+        let two_factor = self.two_factor_repo.find_by_user_id(&user.id).await?;
+        let is_enabled = two_factor.is_some_and(|two_factor| two_factor.enabled_at.is_some());
+        if is_enabled || self.requires_two_factor(&user.id).await? {
+            let challenge = self.start_challenge(user, device, !is_enabled).await?;
 
-        jsonwebtoken::encode(
-            &jsonwebtoken::Header::new(Algorithm::HS256),
-            &claims,
-            &jsonwebtoken::EncodingKey::from_secret(key),
-        )
-        .map_err(|e| Error::Internal(e.to_string()))
+            return Ok(SignInResponse::TwoFactorRequired(challenge));
+        }
+
+        // Failures are forgiven once every factor passed, a right password alone is not enough
+        self.clear_sign_in_failures(&account_subject(&user.email))
+            .await?;
+        self.start_session(user, device)
+            .await
+            .map(SignInResponse::Authenticated)
     }
-}
 
-impl<T1, T2, T3, T4> AuthService for Service<T1, T2, T3, T4>
-where
-    T1: Uow + Send + Sync,
-    T2: UserRepository + Send + Sync,
-    T3: RoleRepository + Send + Sync,
-    T4: CacheProvider + Send + Sync,
-{
-    async fn sign_in(&self, req: &SignInRequest) -> Result<AuthResponse, Error> {
+    async fn sign_in_with_password(&self, req: &SignInRequest) -> Result<SignInResponse, Error> {
         req.validate()
             .map_err(|err| Error::BadRequest(err.to_string()))?;
 
-        let user = self
-            .user_repo
-            .find_by_email(&req.email)
-            .await?
-            .ok_or_else(|| {
-                Error::NotFound(format!("User with email {} is not found", req.email))
-            })?;
+        let account = account_subject(&req.email);
+        let ip = ip_subject(&req.device);
+        let subjects: Vec<String> = std::iter::once(account.clone()).chain(ip.clone()).collect();
+        self.check_sign_in_allowed(&subjects).await?;
+
+        let user = match self.user_repo.find_by_email(&req.email).await? {
+            Some(user) => Some(user),
+            None => {
+                // Spend the same time as a password check so response times do not leak accounts
+                let _ = self.password_hasher.hash(&req.password).await;
+                None
+            }
+        };
+        let is_matching = match &user {
+            Some(user) => {
+                self.password_hasher
+                    .verify(&req.password, &user.password)
+                    .await?
+            }
+            None => false,
+        };
+
+        let user = match user {
+            Some(user) if is_matching => user,
+            _ => {
+                self.record_sign_in_failure(&account, self.config.sign_in_max_attempts)
+                    .await?;
+                if let Some(ip) = &ip {
+                    self.record_sign_in_failure(ip, self.config.sign_in_ip_max_attempts)
+                        .await?;
+                }
+
+                return Err(Error::Unauthorized("Invalid email or password".to_string()));
+            }
+        };
+
+        // The plain password is only at hand here, so legacy hashes are upgraded on sign in
+        if self.password_hasher.needs_rehash(&user.password) {
+            info!("Upgrading password hash for user {}", user.id);
+            let password = self.password_hasher.hash(&req.password).await?;
+            self.user_repo.update_password(&user.id, &password).await?;
+            self.uow.evict(user_detail_key(&user.id)).await?;
+        }
 
-        let is_matching = bcrypt::verify(&req.password, &user.password)
-            .map_err(|e| Error::Internal(e.to_string()))?;
-        if !is_matching {
-            return Err(Error::BadRequest("Password doesn't match".to_string()));
+        // The password was right, so the client may learn where the account stands
+        if user.email_verified_at.is_none()
+            && self.config.email_verification_policy == EmailVerificationPolicy::Required
+        {
+            return Ok(SignInResponse::EmailVerificationRequired(
+                EmailVerificationResponse {
+                    user_id: user.id,
+                    email: user.email,
+                },
+            ));
         }
 
-        self.issue_tokens(&user).await
+        self.complete_sign_in(&user, &req.device).await
     }
 
-    #[uow]
-    async fn sign_up(&self, req: &SignUpRequest) -> Result<AuthResponse, Error> {
-        req.validate()
-            .map_err(|err| Error::BadRequest(err.to_string()))?;
+    async fn rotate_session(
+        &self,
+        req: &RefreshTokenRequest,
+        claim: &Claim,
+    ) -> Result<AuthResponse, Error> {
+        let mut session = self
+            .session_repo
+            .find_by_id(&claim.sid)
+            .await?
+            .filter(|session| session.user_id == claim.sub)
+            .ok_or_else(|| Error::Unauthorized("Session is not found".to_string()))?;
 
-        let is_present = self.user_repo.exists_by_email(&req.email).await?;
-        if is_present {
-            return Err(Error::Conflict(format!(
-                "Email {} already exists",
-                req.email
-            )));
+        // Claiming the token is atomic, so of two concurrent refreshes with it only one can win
+        let is_current = session.refresh_token_id == claim.jti
+            && self.session_repo.consume_refresh_token(&claim.jti).await?;
+        if !is_current {
+            // An already rotated token was presented again, so the family may be in the wrong hands
+            warn!(
+                "Refresh token reuse detected for user {}, revoking session {}",
+                claim.sub, session.id
+            );
+            self.end_session(&session).await?;
+
+            return Err(Error::Unauthorized(
+                "Refresh token has already been used".to_string(),
+            ));
         }
 
-        let password =
-            bcrypt::hash(&req.password, 10).map_err(|err| Error::Internal(err.to_string()))?;
+        let user = self
+            .user_repo
+            .find_by_id(&claim.sub)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
 
-        let user = User {
-            id: id::new(),
-            email: req.email.clone(),
-            password,
-            name: req.name.clone(),
-            phone_number: None,
-            photo_url: None,
-            created_at: Local::now(),
-            updated_at: Local::now(),
-            deleted_at: None,
-        };
+        // The access token issued alongside the rotated refresh token is superseded as well
+        self.revoked_token_repo
+            .revoke(&session.access_token_id, session.access_token_expires_at)
+            .await?;
+
+        session.refresh_token_id = id::new();
+        session.access_token_id = id::new();
+        session.access_token_expires_at = Local::now().add(self.config.access_token_key_ttl);
+        session.last_used_at = Local::now();
+        if req.device.user_agent.is_some() {
+            session.user_agent = req.device.user_agent.clone();
+        }
+        if req.device.ip_address.is_some() {
+            session.ip_address = req.device.ip_address.clone();
+        }
+
+        if let Some(actor_id) = &session.actor_id {
+            self.audit_log_repo
+                .create(&AuditLog {
+                    id: id::new(),
+                    actor_id: actor_id.clone(),
+                    user_id: user.id.clone(),
+                    session_id: Some(session.id.clone()),
+                    action: ACTION_IMPERSONATION_REFRESH.to_string(),
+                    created_at: Local::now(),
+                })
+                .await?;
+        }
+
+        self.issue_tokens(&user, session).await
+    }
 
-        self.user_repo.create(&user).await?;
+    async fn create_user(&self, user: &User) -> Result<(), Error> {
+        self.user_repo.create(user).await?;
 
         let role = self
             .role_repo
@@ -179,78 +726,1618 @@ where
 
         self.role_repo.add(&user.id, &role.id).await?;
 
-        self.issue_tokens(&user).await
+        // Lookups made before the account existed may have been cached as missing
+        self.uow.evict(user_detail_key(&user.id)).await?;
+        self.uow.evict(user_roles_key(&user.id)).await
     }
 
-    async fn sign_out(&self) -> Result<(), Error> {
-        let identity = get_current_identity()?;
-        let key = format!("auth:refresh-token:{}", identity.user_id);
+    async fn find_roles(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        let is_cached = self.config.is_cache_enabled(CACHE_USER_ROLES);
+        let key = user_roles_key(user_id);
+        let cached = match is_cached {
+            true => self.cache_provider.get::<Vec<String>>(key.clone()).await?,
+            false => None,
+        };
+        if let Some(roles) = cached {
+            return Ok(roles);
+        }
 
-        info!("Signout for user {}", identity.email);
+        let roles: Vec<String> = self
+            .role_repo
+            .find_all_by_user_id(user_id)
+            .await?
+            .into_iter()
+            .map(|role| role.name)
+            .collect();
+        if is_cached {
+            self.cache_provider.set(key, &roles).await?;
+        }
 
-        self.cache_repo.del(key).await
+        Ok(roles)
     }
 
-    async fn refresh(&self, req: &RefreshTokenRequest) -> Result<AuthResponse, Error> {
-        req.validate()
-            .map_err(|err| Error::BadRequest(err.to_string()))?;
-
-        let claim = self.verify_refresh_token(&req.refresh_token)?;
-        let key = format!("auth:refresh-token:{}", claim.sub);
+    async fn find_permissions(&self, roles: &[String]) -> Result<Vec<String>, Error> {
+        let is_cached = self.config.is_cache_enabled(CACHE_ROLE_PERMISSIONS);
+        let mut permissions = Vec::new();
 
-        match self.cache_repo.get::<String>(key).await? {
-            Some(token) => {
-                if token != req.refresh_token {
-                    return Err(Error::BadRequest("Token doesn't match".to_string()));
+        for role in roles {
+            let key = role_permissions_key(role);
+            let cached = match is_cached {
+                true => self.cache_provider.get::<Vec<String>>(key.clone()).await?,
+                false => None,
+            };
+            let granted = match cached {
+                Some(granted) => granted,
+                None => {
+                    let granted: Vec<String> = self
+                        .permission_repo
+                        .find_all_by_role_name(role)
+                        .await?
+                        .into_iter()
+                        .map(|permission| permission.name)
+                        .collect();
+                    if is_cached {
+                        self.cache_provider.set(key, &granted).await?;
+                    }
+                    granted
                 }
+            };
+            permissions.extend(granted);
+        }
 
-                let user = self
-                    .user_repo
-                    .find_by_id(&claim.sub)
-                    .await?
-                    .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        permissions.sort();
+        permissions.dedup();
+
+        Ok(permissions)
+    }
+
+    async fn requires_two_factor(&self, user_id: &str) -> Result<bool, Error> {
+        let roles = self.find_roles(user_id).await?;
+
+        Ok(roles
+            .iter()
+            .any(|role| self.config.two_factor_required_roles.contains(role)))
+    }
+
+    async fn start_challenge(
+        &self,
+        user: &User,
+        device: &Device,
+        setup_required: bool,
+    ) -> Result<TwoFactorChallengeResponse, Error> {
+        let challenge_token = id::random_token();
+        let challenge = TwoFactorChallenge {
+            user_id: user.id.clone(),
+            device: device.clone(),
+            setup_required,
+        };
+
+        self.cache_provider
+            .setx(
+                challenge_key(&challenge_token),
+                &challenge,
+                self.config.two_factor_challenge_ttl,
+            )
+            .await?;
+
+        Ok(TwoFactorChallengeResponse {
+            challenge_token,
+            setup_required,
+            expires_at: Local::now().add(self.config.two_factor_challenge_ttl),
+        })
+    }
+
+    async fn enroll(&self, user: &User) -> Result<TwoFactorEnrollmentResponse, Error> {
+        let secret = match Secret::generate_secret().to_encoded() {
+            Secret::Encoded(secret) => secret,
+            Secret::Raw(_) => return Err(Error::Internal("Failed to encode secret".to_string())),
+        };
+        let totp = self.totp(&secret, &user.email)?;
+
+        self.two_factor_repo
+            .save(&TwoFactor {
+                user_id: user.id.clone(),
+                secret: secret.clone(),
+                enabled_at: None,
+                created_at: Local::now(),
+                updated_at: Local::now(),
+            })
+            .await?;
+
+        Ok(TwoFactorEnrollmentResponse {
+            secret,
+            otpauth_uri: totp.get_url(),
+        })
+    }
+
+    fn totp(&self, secret: &str, email: &str) -> Result<TOTP, Error> {
+        let secret = Secret::Encoded(secret.to_string())
+            .to_bytes()
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        TOTP::new(
+            totp_rs::Algorithm::SHA1,
+            6,
+            1,
+            30,
+            secret,
+            Some(self.config.totp_issuer.clone()),
+            email.to_string(),
+        )
+        .map_err(|err| Error::Internal(err.to_string()))
+    }
+
+    async fn check_code(
+        &self,
+        user: &User,
+        two_factor: &TwoFactor,
+        code: &str,
+    ) -> Result<bool, Error> {
+        let code = code.trim();
+        let is_totp = code.len() == 6 && code.chars().all(|c| c.is_ascii_digit());
+
+        if is_totp {
+            let is_valid = self
+                .totp(&two_factor.secret, &user.email)?
+                .check_current(code)
+                .map_err(|err| Error::Internal(err.to_string()))?;
+            if !is_valid {
+                return Ok(false);
+            }
+
+            // A code stays valid for the whole skew window, so only its first use counts
+            let used_key = format!("auth:2fa-used-code:{}:{}", user.id, code);
+            return self
+                .cache_provider
+                .setnx(used_key, true, Duration::seconds(90))
+                .await;
+        }
+
+        // Recovery codes only stand in for an authenticator that was already confirmed
+        if two_factor.enabled_at.is_none() {
+            return Ok(false);
+        }
+
+        let recovery_code = self
+            .two_factor_repo
+            .find_unused_recovery_code(&user.id, &hash_recovery_code(code))
+            .await?;
 
-                self.issue_tokens(&user).await
+        match recovery_code {
+            Some(recovery_code) => {
+                info!("Recovery code used by user {}", user.email);
+                self.two_factor_repo
+                    .use_recovery_code(&recovery_code.id)
+                    .await?;
+                Ok(true)
             }
-            None => Err(Error::NotFound("Token is not found".to_string())),
+            None => Ok(false),
         }
     }
 
-    fn verify_access_token(&self, token: &str) -> Result<Claim, Error> {
-        match jsonwebtoken::decode::<Claim>(
-            &token,
-            &DecodingKey::from_secret(self.config.access_token_key_secret.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        ) {
-            Ok(data) => Ok(data.claims),
-            Err(error) => match error.kind() {
-                ErrorKind::ExpiredSignature => {
-                    Err(Error::Unauthorized("Token is expired".to_string()))
-                }
-                ErrorKind::InvalidSignature => {
-                    Err(Error::BadRequest("Token is not valid".to_string()))
-                }
-                _ => Err(Error::Internal(error.to_string())),
-            },
+    async fn enable_two_factor(&self, mut two_factor: TwoFactor) -> Result<Vec<String>, Error> {
+        two_factor.enabled_at = Some(Local::now());
+        two_factor.updated_at = Local::now();
+        self.two_factor_repo.save(&two_factor).await?;
+        self.two_factor_repo
+            .delete_recovery_codes_by_user_id(&two_factor.user_id)
+            .await?;
+
+        let mut recovery_codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+        for _ in 0..RECOVERY_CODE_COUNT {
+            let code = new_recovery_code();
+
+            self.two_factor_repo
+                .create_recovery_code(&RecoveryCode {
+                    id: id::new(),
+                    user_id: two_factor.user_id.clone(),
+                    code_hash: hash_recovery_code(&code),
+                    used_at: None,
+                    created_at: Local::now(),
+                })
+                .await?;
+
+            recovery_codes.push(code);
         }
+
+        Ok(recovery_codes)
     }
 
-    fn verify_refresh_token(&self, token: &str) -> Result<Claim, Error> {
-        match jsonwebtoken::decode::<Claim>(
-            &token,
-            &DecodingKey::from_secret(self.config.refresh_token_key_secret.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        ) {
-            Ok(data) => Ok(data.claims),
-            Err(error) => match error.kind() {
-                ErrorKind::ExpiredSignature => {
-                    Err(Error::BadRequest("Token is expired".to_string()))
-                }
-                ErrorKind::InvalidSignature => {
-                    Err(Error::BadRequest("Token is not valid".to_string()))
-                }
-                _ => Err(Error::Internal(error.to_string())),
-            },
+    async fn end_session(&self, session: &Session) -> Result<(), Error> {
+        self.session_repo.delete(session).await?;
+        self.revoked_token_repo
+            .revoke(&session.access_token_id, session.access_token_expires_at)
+            .await
+    }
+
+    async fn end_all_sessions(&self, user_id: &str) -> Result<(), Error> {
+        for session in self.session_repo.find_all_by_user_id(user_id).await? {
+            self.end_session(&session).await?;
         }
+
+        Ok(())
+    }
+
+    // Returns the user whose password was reset, their sessions end once this has committed
+    #[uow]
+    async fn reset_password_with_token(&self, req: &ResetPasswordRequest) -> Result<String, Error> {
+        let user_id = self
+            .cache_provider
+            .take::<String>(password_reset_key(&req.token))
+            .await?
+            .ok_or_else(|| Error::BadRequest("Reset token is invalid or expired".to_string()))?;
+
+        let password = self.password_hasher.hash(&req.password).await?;
+        self.user_repo.update_password(&user_id, &password).await?;
+        self.uow.evict(user_detail_key(&user_id)).await?;
+
+        Ok(user_id)
+    }
+
+    #[uow]
+    async fn register(&self, req: &SignUpRequest) -> Result<User, Error> {
+        let is_present = self.user_repo.exists_by_email(&req.email).await?;
+        if is_present {
+            return Err(Error::Conflict(format!(
+                "Email {} already exists",
+                req.email
+            )));
+        }
+
+        let password = self.password_hasher.hash(&req.password).await?;
+
+        let user = User {
+            id: id::new(),
+            email: req.email.clone(),
+            password,
+            name: req.name.clone(),
+            phone_number: None,
+            photo_id: None,
+            email_verified_at: None,
+            created_at: Local::now(),
+            updated_at: Local::now(),
+            deactivated_at: None,
+            deleted_at: None,
+        };
+
+        self.create_user(&user).await?;
+
+        Ok(user)
+    }
+
+    #[uow]
+    async fn find_or_link_oidc_user(
+        &self,
+        provider: &OidcProvider,
+        claims: IdTokenClaims,
+    ) -> Result<User, Error> {
+        let identity = self
+            .user_identity_repo
+            .find_by_provider_and_subject(&provider.id, &claims.sub)
+            .await?;
+        let user = match identity {
+            Some(identity) => self
+                .user_repo
+                .find_by_id(&identity.user_id)
+                .await?
+                .ok_or_else(|| Error::NotFound("User not found".to_string()))?,
+            None => {
+                // Only an address the provider vouches for may claim or create a local account
+                let email = claims
+                    .email
+                    .filter(|_| claims.email_verified || provider.trust_email)
+                    .ok_or_else(|| {
+                        Error::Forbidden(
+                            "Identity provider did not share a verified email address".to_string(),
+                        )
+                    })?;
+
+                let user = match self.user_repo.find_by_email(&email).await? {
+                    // Whoever signed up with the address may not own it, so the provider account
+                    // must not inherit a session, password or anything else they set up
+                    Some(user) if user.email_verified_at.is_none() => {
+                        return Err(Error::Forbidden(
+                            "Verify the email address of the existing account before signing in \
+                            with this provider"
+                                .to_string(),
+                        ));
+                    }
+                    Some(user) => user,
+                    // The address still belongs to a deactivated or deleted account
+                    None if self.user_repo.exists_by_email(&email).await? => {
+                        return Err(Error::Forbidden("Account is not active".to_string()));
+                    }
+                    None => {
+                        // Provider accounts have no usable password until one is reset
+                        let password = self.password_hasher.hash(&id::random_token()).await?;
+                        let user = User {
+                            id: id::new(),
+                            email: email.clone(),
+                            password,
+                            name: claims.name.clone().unwrap_or_else(|| email.clone()),
+                            phone_number: None,
+                            photo_id: None,
+                            email_verified_at: Some(Local::now()),
+                            created_at: Local::now(),
+                            updated_at: Local::now(),
+                            deactivated_at: None,
+                            deleted_at: None,
+                        };
+
+                        info!("Creating user {} from provider {}", email, provider.id);
+                        self.create_user(&user).await?;
+
+                        user
+                    }
+                };
+
+                info!("Linking user {} to provider {}", user.email, provider.id);
+                self.user_identity_repo
+                    .create(&UserIdentity {
+                        id: id::new(),
+                        user_id: user.id.clone(),
+                        provider: provider.id.clone(),
+                        subject: claims.sub.clone(),
+                        email: Some(email),
+                        created_at: Local::now(),
+                    })
+                    .await?;
+
+                user
+            }
+        };
+
+        Ok(user)
+    }
+}
+
+impl<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15> AuthService
+    for Service<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
+where
+    T1: Uow + Send + Sync,
+    T2: UserRepository + Send + Sync,
+    T3: RoleRepository + Send + Sync,
+    T4: SessionRepository + Send + Sync,
+    T5: RevokedTokenRepository + Send + Sync,
+    T6: TwoFactorRepository + Send + Sync,
+    T7: CacheProvider + Send + Sync,
+    T8: Mailer + Send + Sync + 'static,
+    T9: OidcRepository + Send + Sync,
+    T10: PersonalAccessTokenRepository + Send + Sync,
+    T11: PermissionRepository + Send + Sync,
+    T12: PasswordHasher + Send + Sync,
+    T13: AuditLogRepository + Send + Sync,
+    T14: PasskeyRepository + Send + Sync,
+    T15: LoginEventRepository + Send + Sync,
+{
+    async fn sign_in(&self, req: &SignInRequest) -> Result<SignInResponse, Error> {
+        let res = self.sign_in_with_password(req).await;
+
+        // Successful sign-ins are recorded once their session starts
+        let outcome = match &res {
+            Err(Error::TooManyRequests(_)) => Some(OUTCOME_LOCKED),
+            Err(Error::Unauthorized(_) | Error::Forbidden(_)) => Some(OUTCOME_FAILURE),
+            _ => None,
+        };
+        if let Some(outcome) = outcome {
+            // Only logged, the caller must still learn why the sign-in did not succeed
+            match self.user_repo.find_by_email(&req.email).await {
+                Ok(user) => {
+                    self.record_login_event(
+                        user.as_ref().map(|user| user.id.as_str()),
+                        &req.email,
+                        KIND_SIGN_IN,
+                        outcome,
+                        &req.device,
+                    )
+                    .await
+                }
+                Err(err) => warn!("Failed to record sign-in of {}: {}", req.email, err),
+            }
+        }
+
+        res
+    }
+
+    async fn sign_up(&self, req: &SignUpRequest) -> Result<SignInResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let user = self.register(req).await?;
+
+        // The account is usable without the email, which can be resent later on
+        if let Err(err) =
+            send_email_verification(&self.config, &self.keyring, self.mailer.as_ref(), &user).await
+        {
+            warn!(
+                "Failed to send verification email to {}: {}",
+                user.email, err
+            );
+        }
+
+        if self.config.email_verification_policy == EmailVerificationPolicy::Required {
+            return Ok(SignInResponse::EmailVerificationRequired(
+                EmailVerificationResponse {
+                    user_id: user.id,
+                    email: user.email,
+                },
+            ));
+        }
+
+        self.start_session(&user, &req.device)
+            .await
+            .map(SignInResponse::Authenticated)
+    }
+
+    async fn oidc_authorize(
+        &self,
+        provider_id: &str,
+        binding: Option<&str>,
+    ) -> Result<OidcAuthorizationResponse, Error> {
+        let state = id::random_token();
+        let oidc_state = OidcState {
+            provider: provider_id.to_string(),
+            nonce: id::random_token(),
+            code_verifier: id::random_token(),
+            binding: binding.map(id::hash_token),
+        };
+        let code_challenge =
+            URL_SAFE_NO_PAD.encode(Sha256::digest(oidc_state.code_verifier.as_bytes()));
+
+        let authorization_url = self
+            .oidc_client
+            .authorization_url(provider_id, &state, &oidc_state.nonce, &code_challenge)
+            .await?;
+
+        self.cache_provider
+            .setx(
+                oidc_state_key(&state),
+                &oidc_state,
+                self.config.oidc_state_ttl,
+            )
+            .await?;
+
+        Ok(OidcAuthorizationResponse {
+            authorization_url,
+            state,
+        })
+    }
+
+    async fn oidc_callback(&self, req: &OidcCallbackRequest) -> Result<SignInResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let provider = self.oidc_client.provider(&req.provider)?;
+        // A state started in another browser must not complete here, it could be an attacker's
+        let oidc_state = self
+            .cache_provider
+            .take::<OidcState>(oidc_state_key(&req.state))
+            .await?
+            .filter(|oidc_state| oidc_state.provider == provider.id)
+            .filter(|oidc_state| match &oidc_state.binding {
+                Some(binding) => {
+                    req.binding.as_deref().map(id::hash_token).as_ref() == Some(binding)
+                }
+                None => true,
+            })
+            .ok_or_else(|| {
+                Error::Unauthorized("Login attempt is expired or invalid".to_string())
+            })?;
+
+        // The provider is called before any transaction is opened, it may be slow to answer
+        let claims = self
+            .oidc_client
+            .exchange_code(&provider.id, &req.code, &oidc_state.code_verifier)
+            .await?;
+        if claims.nonce.as_deref() != Some(oidc_state.nonce.as_str()) {
+            return Err(Error::Unauthorized("ID token is not valid".to_string()));
+        }
+
+        let user = self.find_or_link_oidc_user(provider, claims).await?;
+        self.check_sign_in_allowed(&sign_in_subjects(&user.email, &req.device))
+            .await?;
+
+        self.complete_sign_in(&user, &req.device).await
+    }
+
+    async fn sign_out(&self) -> Result<(), Error> {
+        let identity = get_current_identity()?;
+
+        info!("Signout for user {}", identity.email);
+
+        let session = match &identity.session_id {
+            Some(session_id) => self.session_repo.find_by_id(session_id).await?,
+            None => None,
+        };
+
+        match session {
+            Some(session) => self.end_session(&session).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn refresh(&self, req: &RefreshTokenRequest) -> Result<AuthResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        // Tokens that do not verify cannot be tied to an account, so they are not recorded
+        let claim = self.verify_refresh_token(&req.refresh_token)?;
+        let res = self.rotate_session(req, &claim).await;
+
+        let outcome = match &res {
+            Ok(_) => Some(OUTCOME_SUCCESS),
+            Err(Error::Unauthorized(_) | Error::NotFound(_)) => Some(OUTCOME_FAILURE),
+            Err(_) => None,
+        };
+        if let Some(outcome) = outcome {
+            self.record_login_event(
+                Some(&claim.sub),
+                &claim.email,
+                KIND_REFRESH,
+                outcome,
+                &req.device,
+            )
+            .await;
+        }
+
+        res
+    }
+
+    #[uow]
+    async fn verify_two_factor(&self, req: &VerifyTwoFactorRequest) -> Result<AuthResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let key = challenge_key(&req.challenge_token);
+        let attempts_key = challenge_attempts_key(&req.challenge_token);
+        let challenge = self
+            .cache_provider
+            .get::<TwoFactorChallenge>(key.clone())
+            .await?
+            .ok_or_else(|| Error::Unauthorized("Challenge is expired or invalid".to_string()))?;
+        let user = self
+            .user_repo
+            .find_by_id(&challenge.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        // A fresh challenge only costs a sign-in, so wrong codes count against the account as well
+        let account = account_subject(&user.email);
+        let ip = ip_subject(&challenge.device);
+        let subjects: Vec<String> = std::iter::once(account.clone()).chain(ip.clone()).collect();
+        self.check_sign_in_allowed(&subjects).await?;
+
+        // Counted before the code is checked, so parallel guesses cannot exceed the limit
+        let attempts = self
+            .cache_provider
+            .incr(attempts_key.clone(), self.config.two_factor_challenge_ttl)
+            .await?;
+        if attempts > MAX_CHALLENGE_ATTEMPTS {
+            warn!(
+                "Too many two-factor attempts for user {}",
+                challenge.user_id
+            );
+            self.cache_provider.del(key).await?;
+            self.cache_provider.del(attempts_key).await?;
+
+            return Err(Error::Unauthorized(
+                "Challenge is expired or invalid".to_string(),
+            ));
+        }
+
+        let two_factor = self
+            .two_factor_repo
+            .find_by_user_id(&user.id)
+            .await?
+            .ok_or_else(|| {
+                Error::BadRequest("Two-factor enrollment has not been started".to_string())
+            })?;
+
+        if !self.check_code(&user, &two_factor, &req.code).await? {
+            self.record_sign_in_failure(&account, self.config.sign_in_max_attempts)
+                .await?;
+            if let Some(ip) = &ip {
+                self.record_sign_in_failure(ip, self.config.sign_in_ip_max_attempts)
+                    .await?;
+            }
+
+            return Err(Error::Unauthorized("Code is not valid".to_string()));
+        }
+
+        // Taking the challenge makes sure a second request with another valid code loses
+        if self
+            .cache_provider
+            .take::<TwoFactorChallenge>(key)
+            .await?
+            .is_none()
+        {
+            return Err(Error::Unauthorized(
+                "Challenge is expired or invalid".to_string(),
+            ));
+        }
+        self.cache_provider.del(attempts_key).await?;
+        self.clear_sign_in_failures(&account).await?;
+
+        let recovery_codes = match two_factor.enabled_at {
+            Some(_) => None,
+            None => Some(self.enable_two_factor(two_factor).await?),
+        };
+
+        let mut res = self.start_session(&user, &challenge.device).await?;
+        res.recovery_codes = recovery_codes;
+
+        Ok(res)
+    }
+
+    async fn setup_two_factor(
+        &self,
+        req: &SetupTwoFactorRequest,
+    ) -> Result<TwoFactorEnrollmentResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let challenge = self
+            .cache_provider
+            .get::<TwoFactorChallenge>(challenge_key(&req.challenge_token))
+            .await?
+            .filter(|challenge| challenge.setup_required)
+            .ok_or_else(|| Error::Unauthorized("Challenge is expired or invalid".to_string()))?;
+
+        let user = self
+            .user_repo
+            .find_by_id(&challenge.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        self.enroll(&user).await
+    }
+
+    async fn enroll_two_factor(&self) -> Result<TwoFactorEnrollmentResponse, Error> {
+        let identity = require_account_owner()?;
+        let user = self
+            .user_repo
+            .find_by_id(&identity.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        let two_factor = self.two_factor_repo.find_by_user_id(&user.id).await?;
+        if two_factor.is_some_and(|two_factor| two_factor.enabled_at.is_some()) {
+            return Err(Error::Conflict(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        self.enroll(&user).await
+    }
+
+    #[uow]
+    async fn confirm_two_factor(
+        &self,
+        req: &TwoFactorCodeRequest,
+    ) -> Result<RecoveryCodesResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let identity = require_account_owner()?;
+        let user = self
+            .user_repo
+            .find_by_id(&identity.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        let two_factor = self
+            .two_factor_repo
+            .find_by_user_id(&user.id)
+            .await?
+            .filter(|two_factor| two_factor.enabled_at.is_none())
+            .ok_or_else(|| {
+                Error::BadRequest("Two-factor enrollment has not been started".to_string())
+            })?;
+
+        if !self.check_code(&user, &two_factor, &req.code).await? {
+            return Err(Error::BadRequest("Code is not valid".to_string()));
+        }
+
+        info!("Two-factor authentication enabled for user {}", user.email);
+
+        let recovery_codes = self.enable_two_factor(two_factor).await?;
+
+        Ok(RecoveryCodesResponse { recovery_codes })
+    }
+
+    #[uow]
+    async fn disable_two_factor(&self, req: &TwoFactorCodeRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let identity = require_account_owner()?;
+        if self.requires_two_factor(&identity.user_id).await? {
+            return Err(Error::Forbidden(
+                "Two-factor authentication is mandatory for your role".to_string(),
+            ));
+        }
+
+        let user = self
+            .user_repo
+            .find_by_id(&identity.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        let two_factor = self
+            .two_factor_repo
+            .find_by_user_id(&user.id)
+            .await?
+            .filter(|two_factor| two_factor.enabled_at.is_some())
+            .ok_or_else(|| {
+                Error::BadRequest("Two-factor authentication is not enabled".to_string())
+            })?;
+
+        if !self.check_code(&user, &two_factor, &req.code).await? {
+            return Err(Error::BadRequest("Code is not valid".to_string()));
+        }
+
+        info!("Two-factor authentication disabled for user {}", user.email);
+
+        self.two_factor_repo
+            .delete_recovery_codes_by_user_id(&user.id)
+            .await?;
+        self.two_factor_repo.delete_by_user_id(&user.id).await
+    }
+
+    async fn forgot_password(&self, req: &ForgotPasswordRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        // Counted by address before the lookup, and answered the same way once the limit is hit,
+        // so neither the count nor the answer tells whether the account exists
+        let requests = self
+            .cache_provider
+            .incr(
+                password_reset_requests_key(&req.email),
+                self.config.password_reset_request_window,
+            )
+            .await?;
+        if requests > self.config.password_reset_max_requests {
+            warn!("Too many password reset requests for {}", req.email);
+            return Ok(());
+        }
+
+        let user = match self.user_repo.find_by_email(&req.email).await? {
+            Some(user) => user,
+            // Answer the same way for unknown emails so the endpoint cannot probe for accounts
+            None => return Ok(()),
+        };
+
+        let token = id::random_token();
+        self.cache_provider
+            .setx(
+                password_reset_key(&token),
+                &user.id,
+                self.config.password_reset_ttl,
+            )
+            .await?;
+
+        info!("Password reset requested for user {}", user.email);
+
+        // Sent in the background so the response time does not reveal that the account exists
+        let link = format!("{}/reset-password?token={}", self.config.app_url, token);
+        spawn_send(
+            Arc::clone(&self.mailer),
+            Mail {
+                to: user.email,
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Hi {},\n\n\
+                    Use the link below to choose a new password. It expires in {} minutes \
+                    and can only be used once.\n\n{}\n\n\
+                    If you did not ask for this, you can ignore this email.",
+                    user.name,
+                    self.config.password_reset_ttl.num_minutes(),
+                    link
+                ),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn reset_password(&self, req: &ResetPasswordRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let user_id = self.reset_password_with_token(req).await?;
+
+        info!("Password reset for user {}, revoking all sessions", user_id);
+
+        self.end_all_sessions(&user_id).await
+    }
+
+    #[uow]
+    async fn restore_account(&self, req: &RestoreAccountRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let invalid = || Error::BadRequest("Restore token is invalid or expired".to_string());
+        let user_id = self
+            .cache_provider
+            .take::<String>(account_restore_key(&req.token))
+            .await?
+            .ok_or_else(invalid)?;
+        let user = self
+            .user_repo
+            .find_any_by_id(&user_id)
+            .await?
+            .filter(|user| user.deleted_at.is_some())
+            .ok_or_else(invalid)?;
+
+        info!("Account restored for user {}", user.email);
+
+        self.user_repo.restore(&user.id).await?;
+        self.uow.evict(user_detail_key(&user.id)).await
+    }
+
+    async fn verify_email(&self, req: &VerifyEmailRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let claim = match self.keyring.verify::<EmailVerificationClaim>(&req.token) {
+            Ok(claim) if claim.typ == TokenType::EmailVerification => claim,
+            Ok(_) => return Err(Error::BadRequest("Token is not valid".to_string())),
+            Err(error) => {
+                return match error.kind() {
+                    ErrorKind::ExpiredSignature => Err(Error::BadRequest(
+                        "Verification link has expired".to_string(),
+                    )),
+                    _ => Err(Error::BadRequest("Token is not valid".to_string())),
+                };
+            }
+        };
+
+        let user = self
+            .user_repo
+            .find_by_id(&claim.sub)
+            .await?
+            .ok_or_else(|| Error::BadRequest("Token is not valid".to_string()))?;
+
+        if user.email != claim.email {
+            return self.confirm_email_change(&user, &claim.email).await;
+        }
+        if user.email_verified_at.is_some() {
+            return Ok(());
+        }
+
+        info!("Email verified for user {}", user.email);
+
+        self.user_repo.verify_email(&user.id).await?;
+        self.uow.evict(user_detail_key(&user.id)).await
+    }
+
+    async fn resend_email_verification(
+        &self,
+        req: &ResendEmailVerificationRequest,
+    ) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        // Throttle by address before the lookup so unknown emails behave the same way
+        let key = email_verification_resend_key(&req.email);
+        if self
+            .cache_provider
+            .get::<bool>(key.clone())
+            .await?
+            .is_some()
+        {
+            return Err(Error::TooManyRequests(
+                "Please wait before requesting another verification email".to_string(),
+            ));
+        }
+        self.cache_provider
+            .setx(key, true, self.config.email_verification_resend_interval)
+            .await?;
+
+        // Sent in the background, the time it takes would tell which accounts exist
+        if let Some(user) = self
+            .user_repo
+            .find_by_email(&req.email)
+            .await?
+            .filter(|user| user.email_verified_at.is_none())
+        {
+            let mail = email_verification_mail(&self.config, &self.keyring, &user)?;
+            spawn_send(Arc::clone(&self.mailer), mail);
+        }
+
+        Ok(())
+    }
+
+    async fn unlock_sign_in(&self, req: &UnlockSignInRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let identity = require_permission(PERMISSION_USER_MANAGE)?;
+
+        info!("Sign-in for {} unlocked by {}", req.email, identity.email);
+
+        self.clear_sign_in_failures(&account_subject(&req.email))
+            .await?;
+        match &req.ip_address {
+            Some(ip_address) => {
+                self.clear_sign_in_failures(&ip_address_subject(ip_address))
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn send_magic_link(&self, req: &MagicLinkRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        // Counted by address before the lookup so unknown emails behave the same way
+        let requests = self
+            .cache_provider
+            .incr(
+                magic_link_requests_key(&req.email),
+                self.config.magic_link_request_window,
+            )
+            .await?;
+        if requests > self.config.magic_link_max_requests {
+            return Err(Error::TooManyRequests(
+                "Too many sign-in links requested, please try again later".to_string(),
+            ));
+        }
+
+        let user = match self.user_repo.find_by_email(&req.email).await? {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+
+        let jti = id::new();
+        let token = self.keyring.sign(&MagicLinkClaim {
+            jti: jti.clone(),
+            sub: user.id.clone(),
+            email: user.email.clone(),
+            bnd: req.binding.as_deref().map(id::hash_token),
+            exp: chrono::Utc::now()
+                .add(self.config.magic_link_ttl)
+                .timestamp(),
+            iat: chrono::Utc::now().timestamp(),
+            typ: TokenType::MagicLink,
+        })?;
+        self.cache_provider
+            .setx(magic_link_key(&jti), true, self.config.magic_link_ttl)
+            .await?;
+
+        info!("Magic link requested for user {}", user.email);
+
+        // Sent in the background so the response time does not reveal that the account exists
+        let link = format!("{}/magic-link?token={}", self.config.app_url, token);
+        spawn_send(
+            Arc::clone(&self.mailer),
+            Mail {
+                to: user.email,
+                subject: "Your sign-in link".to_string(),
+                body: format!(
+                    "Hi {},\n\n\
+                    Use the link below to sign in. It expires in {} minutes \
+                    and can only be used once.\n\n{}\n\n\
+                    If you did not ask for this, you can ignore this email.",
+                    user.name,
+                    self.config.magic_link_ttl.num_minutes(),
+                    link
+                ),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn verify_magic_link(
+        &self,
+        req: &VerifyMagicLinkRequest,
+    ) -> Result<SignInResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let invalid = || Error::Unauthorized("Sign-in link is not valid".to_string());
+
+        let claim = match self.keyring.verify::<MagicLinkClaim>(&req.token) {
+            Ok(claim) if claim.typ == TokenType::MagicLink => claim,
+            Ok(_) => return Err(invalid()),
+            Err(error) => {
+                return match error.kind() {
+                    ErrorKind::ExpiredSignature => {
+                        Err(Error::Unauthorized("Sign-in link has expired".to_string()))
+                    }
+                    _ => Err(invalid()),
+                };
+            }
+        };
+
+        // Checked before the link is spent, so opening it elsewhere does not burn it
+        let is_bound = match &claim.bnd {
+            Some(binding) => req.binding.as_deref().map(id::hash_token).as_ref() == Some(binding),
+            None => true,
+        };
+        if !is_bound {
+            return Err(Error::Unauthorized(
+                "Sign-in link must be opened in the browser that requested it".to_string(),
+            ));
+        }
+
+        // A locked account stays locked whichever way the user signs in
+        self.check_sign_in_allowed(&sign_in_subjects(&claim.email, &req.device))
+            .await?;
+
+        self.cache_provider
+            .take::<bool>(magic_link_key(&claim.jti))
+            .await?
+            .ok_or_else(|| Error::Unauthorized("Sign-in link has already been used".to_string()))?;
+
+        let mut user = self
+            .user_repo
+            .find_by_id(&claim.sub)
+            .await?
+            .filter(|user| user.email == claim.email)
+            .ok_or_else(invalid)?;
+
+        // Opening the link proves the address belongs to the user
+        if user.email_verified_at.is_none() {
+            info!("Email verified by magic link for user {}", user.email);
+            self.user_repo.verify_email(&user.id).await?;
+            self.uow.evict(user_detail_key(&user.id)).await?;
+            user.email_verified_at = Some(Local::now());
+        }
+
+        self.complete_sign_in(&user, &req.device).await
+    }
+
+    async fn get_sessions(&self) -> Result<Vec<SessionResponse>, Error> {
+        let identity = get_current_identity()?;
+        let sessions = self
+            .session_repo
+            .find_all_by_user_id(&identity.user_id)
+            .await?
+            .into_iter()
+            .map(|session| SessionResponse {
+                current: identity.session_id.as_ref() == Some(&session.id),
+                id: session.id,
+                user_agent: session.user_agent,
+                ip_address: session.ip_address,
+                created_at: session.created_at,
+                last_used_at: session.last_used_at,
+            })
+            .collect();
+
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, session_id: &str) -> Result<(), Error> {
+        let identity = get_current_identity()?;
+        let session = self
+            .session_repo
+            .find_by_id(session_id)
+            .await?
+            .filter(|session| session.user_id == identity.user_id)
+            .ok_or_else(|| Error::NotFound(format!("Session {} is not found", session_id)))?;
+
+        info!("Revoking session {} of user {}", session.id, identity.email);
+
+        self.end_session(&session).await
+    }
+
+    async fn revoke_other_sessions(&self) -> Result<(), Error> {
+        let identity = get_current_identity()?;
+        let sessions = self
+            .session_repo
+            .find_all_by_user_id(&identity.user_id)
+            .await?;
+
+        info!("Revoking other sessions of user {}", identity.email);
+
+        for session in sessions {
+            if identity.session_id.as_ref() != Some(&session.id) {
+                self.end_session(&session).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn start_impersonation(&self, req: &ImpersonationRequest) -> Result<AuthResponse, Error> {
+        let identity = get_current_identity()?;
+        if !identity.roles.iter().any(|role| role == ROLE_SUPER_ADMIN) {
+            return Err(Error::Forbidden(format!(
+                "Role {} is required",
+                ROLE_SUPER_ADMIN
+            )));
+        }
+        // Chained impersonation would hide who is really acting
+        if identity.actor_id.is_some() {
+            return Err(Error::Forbidden(
+                "Impersonation cannot be started from an impersonation session".to_string(),
+            ));
+        }
+        if identity.user_id == req.user_id {
+            return Err(Error::BadRequest("Cannot impersonate yourself".to_string()));
+        }
+
+        let user = self
+            .user_repo
+            .find_by_id(&req.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        if self
+            .find_roles(&user.id)
+            .await?
+            .iter()
+            .any(|role| role == ROLE_SUPER_ADMIN)
+        {
+            return Err(Error::Forbidden(format!(
+                "Users with role {} cannot be impersonated",
+                ROLE_SUPER_ADMIN
+            )));
+        }
+
+        let mut session = self.new_session(&user, &req.device);
+        session.actor_id = Some(identity.user_id.clone());
+
+        warn!(
+            "User {} is impersonating user {} in session {}",
+            identity.email, user.email, session.id
+        );
+
+        self.audit_log_repo
+            .create(&AuditLog {
+                id: id::new(),
+                actor_id: identity.user_id,
+                user_id: user.id.clone(),
+                session_id: Some(session.id.clone()),
+                action: ACTION_IMPERSONATION_START.to_string(),
+                created_at: Local::now(),
+            })
+            .await?;
+        // Shows up in the login history of the user, like any other way into the account
+        self.record_login_event(
+            Some(&user.id),
+            &user.email,
+            KIND_IMPERSONATION,
+            OUTCOME_SUCCESS,
+            &req.device,
+        )
+        .await;
+
+        self.issue_tokens(&user, session).await
+    }
+
+    async fn end_impersonation(&self) -> Result<(), Error> {
+        let identity = get_current_identity()?;
+        let actor_id = identity
+            .actor_id
+            .clone()
+            .ok_or_else(|| Error::BadRequest("Session is not an impersonation".to_string()))?;
+
+        info!(
+            "User {} stopped impersonating user {}",
+            actor_id, identity.email
+        );
+
+        let session = match &identity.session_id {
+            Some(session_id) => self.session_repo.find_by_id(session_id).await?,
+            None => None,
+        };
+        if let Some(session) = session {
+            self.end_session(&session).await?;
+        }
+
+        self.audit_log_repo
+            .create(&AuditLog {
+                id: id::new(),
+                actor_id,
+                user_id: identity.user_id,
+                session_id: identity.session_id,
+                action: ACTION_IMPERSONATION_END.to_string(),
+                created_at: Local::now(),
+            })
+            .await
+    }
+
+    async fn record_audit_log(&self, identity: &Identity, action: &str) -> Result<(), Error> {
+        self.audit_log_repo
+            .create(&AuditLog {
+                id: id::new(),
+                actor_id: identity
+                    .actor_id
+                    .clone()
+                    .unwrap_or_else(|| identity.user_id.clone()),
+                user_id: identity.user_id.clone(),
+                session_id: identity.session_id.clone(),
+                action: action.to_string(),
+                created_at: Local::now(),
+            })
+            .await
+    }
+
+    async fn start_passkey_registration(&self) -> Result<CreationChallengeResponse, Error> {
+        let identity = require_account_owner()?;
+        let user = self
+            .user_repo
+            .find_by_id(&identity.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        let user_uuid =
+            Uuid::parse_str(&user.id).map_err(|err| Error::Internal(err.to_string()))?;
+
+        // Keeps an authenticator from registering the same passkey twice
+        let exclude_credentials = self
+            .passkey_repo
+            .find_all_by_user_id(&user.id)
+            .await?
+            .iter()
+            .map(|passkey| parse_passkey(&passkey.credential))
+            .map(|credential| credential.map(|credential| credential.cred_id().clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (options, registration) = self
+            .webauthn
+            .start_passkey_registration(
+                user_uuid,
+                &user.email,
+                &user.name,
+                Some(exclude_credentials),
+            )
+            .map_err(|err| Error::Internal(err.to_string()))?;
+
+        self.cache_provider
+            .setx(
+                passkey_registration_key(&user.id),
+                &registration,
+                self.config.webauthn_challenge_ttl,
+            )
+            .await?;
+
+        Ok(options)
+    }
+
+    async fn finish_passkey_registration(
+        &self,
+        req: &FinishPasskeyRegistrationRequest,
+    ) -> Result<PasskeyResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let identity = require_account_owner()?;
+        let registration = self
+            .cache_provider
+            .take::<PasskeyRegistration>(passkey_registration_key(&identity.user_id))
+            .await?
+            .ok_or_else(|| Error::BadRequest("Registration is expired or invalid".to_string()))?;
+
+        let credential = self
+            .webauthn
+            .finish_passkey_registration(&req.credential, &registration)
+            .map_err(|err| {
+                warn!(
+                    "Passkey registration failed for user {}: {}",
+                    identity.email, err
+                );
+                Error::BadRequest("Passkey could not be verified".to_string())
+            })?;
+
+        let credential_id = URL_SAFE_NO_PAD.encode(credential.cred_id());
+        if self
+            .passkey_repo
+            .find_by_credential_id(&credential_id)
+            .await?
+            .is_some()
+        {
+            return Err(Error::Conflict("Passkey is already registered".to_string()));
+        }
+
+        let transports = req
+            .credential
+            .response
+            .transports
+            .as_ref()
+            .and_then(|transports| serde_json::to_value(transports).ok())
+            .and_then(|transports| serde_json::from_value::<Vec<String>>(transports).ok())
+            .filter(|transports| !transports.is_empty())
+            .map(|transports| transports.join(" "));
+        let passkey = Passkey {
+            id: id::new(),
+            user_id: identity.user_id.clone(),
+            name: req.name.clone(),
+            credential_id,
+            credential: serde_json::to_string(&credential)
+                .map_err(|err| Error::Internal(err.to_string()))?,
+            sign_count: initial_sign_count(&credential),
+            transports,
+            last_used_at: None,
+            created_at: Local::now(),
+        };
+        self.passkey_repo.create(&passkey).await?;
+
+        info!(
+            "Passkey {} registered for user {}",
+            passkey.id, identity.email
+        );
+
+        Ok(PasskeyResponse::from(passkey))
+    }
+
+    async fn start_passkey_authentication(
+        &self,
+        req: &StartPasskeyAuthenticationRequest,
+    ) -> Result<PasskeyAuthenticationResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let user = match &req.email {
+            Some(email) => self.user_repo.find_by_email(email).await?,
+            None => None,
+        };
+        let passkeys = match &user {
+            Some(user) => self
+                .passkey_repo
+                .find_all_by_user_id(&user.id)
+                .await?
+                .iter()
+                .map(|passkey| parse_passkey(&passkey.credential))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![],
+        };
+
+        // Unknown emails and users without passkeys fall back to discoverable passkeys
+        let (mut options, challenge) = match user.filter(|_| !passkeys.is_empty()) {
+            Some(user) => self.webauthn.start_passkey_authentication(&passkeys).map(
+                |(options, authentication)| {
+                    let challenge = PasskeyChallenge::User {
+                        user_id: user.id,
+                        authentication,
+                    };
+                    (options, challenge)
+                },
+            ),
+            None => self.webauthn.start_discoverable_authentication().map(
+                |(options, authentication)| {
+                    (options, PasskeyChallenge::Discoverable(authentication))
+                },
+            ),
+        }
+        .map_err(|err| Error::Internal(err.to_string()))?;
+        // Otherwise the options would tell which emails belong to an account with passkeys
+        if let (Some(email), PasskeyChallenge::Discoverable(_)) = (&req.email, &challenge) {
+            disguise_as_user(&mut options, &self.config, email)?;
+        }
+
+        let challenge_id = id::random_token();
+        self.cache_provider
+            .setx(
+                passkey_authentication_key(&challenge_id),
+                &challenge,
+                self.config.webauthn_challenge_ttl,
+            )
+            .await?;
+
+        Ok(PasskeyAuthenticationResponse {
+            challenge_id,
+            options,
+        })
+    }
+
+    async fn finish_passkey_authentication(
+        &self,
+        req: &FinishPasskeyAuthenticationRequest,
+    ) -> Result<AuthResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let invalid = || Error::Unauthorized("Passkey is not valid".to_string());
+
+        let challenge = self
+            .cache_provider
+            .take::<PasskeyChallenge>(passkey_authentication_key(&req.challenge_id))
+            .await?
+            .ok_or_else(|| Error::Unauthorized("Challenge is expired or invalid".to_string()))?;
+
+        let credential_id = URL_SAFE_NO_PAD.encode(req.credential.get_credential_id());
+        let mut passkey = self
+            .passkey_repo
+            .find_by_credential_id(&credential_id)
+            .await?
+            .ok_or_else(invalid)?;
+        let mut credential = parse_passkey(&passkey.credential)?;
+
+        let result = match challenge {
+            PasskeyChallenge::Discoverable(authentication) => {
+                // The user handle must point at the owner of the credential that signed
+                let (user_uuid, _) = self
+                    .webauthn
+                    .identify_discoverable_authentication(&req.credential)
+                    .map_err(|_| invalid())?;
+                if user_uuid.to_string() != passkey.user_id {
+                    return Err(invalid());
+                }
+
+                self.webauthn.finish_discoverable_authentication(
+                    &req.credential,
+                    authentication,
+                    &[DiscoverableKey::from(&credential)],
+                )
+            }
+            PasskeyChallenge::User {
+                user_id,
+                authentication,
+            } => {
+                if user_id != passkey.user_id {
+                    return Err(invalid());
+                }
+
+                self.webauthn
+                    .finish_passkey_authentication(&req.credential, &authentication)
+            }
+        }
+        .map_err(|err| {
+            warn!("Passkey {} failed to authenticate: {}", passkey.id, err);
+            invalid()
+        })?;
+
+        // A counter that moves backwards hints at a cloned authenticator, the library rejects it
+        if credential.update_credential(&result) == Some(true) {
+            passkey.credential = serde_json::to_string(&credential)
+                .map_err(|err| Error::Internal(err.to_string()))?;
+        }
+        passkey.sign_count = result.counter();
+        self.passkey_repo
+            .update_credential(&passkey.id, &passkey.credential, passkey.sign_count)
+            .await?;
+
+        let user = self
+            .user_repo
+            .find_by_id(&passkey.user_id)
+            .await?
+            .ok_or_else(invalid)?;
+        if user.email_verified_at.is_none()
+            && self.config.email_verification_policy == EmailVerificationPolicy::Required
+        {
+            return Err(Error::Forbidden(
+                "Email address has not been verified".to_string(),
+            ));
+        }
+        self.check_sign_in_allowed(&sign_in_subjects(&user.email, &req.device))
+            .await?;
+
+        // Passkeys verify the user on the device, which already counts as a second factor
+        self.start_session(&user, &req.device).await
+    }
+
+    async fn get_passkeys(&self) -> Result<Vec<PasskeyResponse>, Error> {
+        let identity = get_current_identity()?;
+        let passkeys = self
+            .passkey_repo
+            .find_all_by_user_id(&identity.user_id)
+            .await?;
+
+        Ok(passkeys.into_iter().map(PasskeyResponse::from).collect())
+    }
+
+    async fn delete_passkey(&self, passkey_id: &str) -> Result<(), Error> {
+        let identity = require_account_owner()?;
+        let is_owned = self
+            .passkey_repo
+            .find_all_by_user_id(&identity.user_id)
+            .await?
+            .iter()
+            .any(|passkey| passkey.id == passkey_id);
+        if !is_owned {
+            return Err(Error::NotFound(format!(
+                "Passkey {} is not found",
+                passkey_id
+            )));
+        }
+
+        info!("Passkey {} removed by user {}", passkey_id, identity.email);
+
+        self.passkey_repo
+            .delete(passkey_id, &identity.user_id)
+            .await
+    }
+
+    async fn verify_access_token(&self, token: &str) -> Result<Claim, Error> {
+        let claim = match self.keyring.verify::<Claim>(token) {
+            Ok(claim) if claim.typ == TokenType::Access => claim,
+            Ok(_) => return Err(Error::BadRequest("Token is not valid".to_string())),
+            Err(error) => {
+                return match error.kind() {
+                    ErrorKind::ExpiredSignature => {
+                        Err(Error::Unauthorized("Token is expired".to_string()))
+                    }
+                    ErrorKind::InvalidSignature | ErrorKind::InvalidToken | ErrorKind::Json(_) => {
+                        Err(Error::BadRequest("Token is not valid".to_string()))
+                    }
+                    _ => Err(Error::Internal(error.to_string())),
+                };
+            }
+        };
+
+        if self.revoked_token_repo.is_revoked(&claim.jti).await? {
+            return Err(Error::Unauthorized("Token has been revoked".to_string()));
+        }
+
+        Ok(claim)
+    }
+
+    async fn get_roles(&self, user_id: &str) -> Result<Vec<String>, Error> {
+        self.find_roles(user_id).await
+    }
+
+    async fn get_permissions(&self, roles: &[String]) -> Result<Vec<String>, Error> {
+        self.find_permissions(roles).await
+    }
+
+    async fn verify_personal_access_token(&self, token: &str) -> Result<Identity, Error> {
+        let personal_access_token = self
+            .personal_access_token_repo
+            .find_by_token_hash(&id::hash_token(token))
+            .await?
+            .ok_or_else(|| Error::Unauthorized("Token is not valid".to_string()))?;
+        if personal_access_token.expires_at < Local::now() {
+            return Err(Error::Unauthorized("Token is expired".to_string()));
+        }
+
+        let user = self
+            .user_repo
+            .find_by_id(&personal_access_token.user_id)
+            .await?
+            .ok_or_else(|| Error::Unauthorized("Token is not valid".to_string()))?;
+        // Tokens run unattended, so an unverified owner gets no partial access through them
+        if user.email_verified_at.is_none()
+            && self.config.email_verification_policy != EmailVerificationPolicy::Optional
+        {
+            return Err(Error::Forbidden(
+                "Email address has not been verified".to_string(),
+            ));
+        }
+
+        let is_stale = personal_access_token
+            .last_used_at
+            .is_none_or(|last_used_at| {
+                last_used_at < Local::now() - Duration::minutes(TOKEN_LAST_USED_RESOLUTION_MINUTES)
+            });
+        if is_stale {
+            self.personal_access_token_repo
+                .update_last_used_at(&personal_access_token.id)
+                .await?;
+        }
+
+        let roles = self.find_roles(&user.id).await?;
+        let permissions = self.find_permissions(&roles).await?;
+
+        Ok(Identity {
+            user_id: user.id,
+            email: user.email,
+            session_id: None,
+            actor_id: None,
+            scopes: Some(personal_access_token.scope_list()),
+            roles,
+            permissions,
+        })
+    }
+
+    fn verify_refresh_token(&self, token: &str) -> Result<Claim, Error> {
+        match self.keyring.verify::<Claim>(token) {
+            Ok(claim) if claim.typ == TokenType::Refresh => Ok(claim),
+            Ok(_) => Err(Error::BadRequest("Token is not valid".to_string())),
+            Err(error) => match error.kind() {
+                ErrorKind::ExpiredSignature => {
+                    Err(Error::BadRequest("Token is expired".to_string()))
+                }
+                ErrorKind::InvalidSignature | ErrorKind::InvalidToken | ErrorKind::Json(_) => {
+                    Err(Error::BadRequest("Token is not valid".to_string()))
+                }
+                _ => Err(Error::Internal(error.to_string())),
+            },
+        }
+    }
+
+    fn jwks(&self) -> JwkSet {
+        self.keyring.jwks()
     }
 }
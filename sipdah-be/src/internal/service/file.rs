@@ -0,0 +1,51 @@
+use crate::internal::model::error::Error;
+use crate::internal::model::file::{
+    File, Repository as FileRepository, Service as FileService, CATEGORY_AVATAR,
+};
+use crate::internal::provider::storage::Storage;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Service<T1, T2>
+where
+    T1: FileRepository + Send + Sync,
+    T2: Storage + Send + Sync,
+{
+    file_repo: Arc<T1>,
+    storage: Arc<T2>,
+}
+
+impl<T1, T2> Service<T1, T2>
+where
+    T1: FileRepository + Send + Sync,
+    T2: Storage + Send + Sync,
+{
+    pub fn new(file_repo: Arc<T1>, storage: Arc<T2>) -> Self {
+        Self { file_repo, storage }
+    }
+}
+
+impl<T1, T2> FileService for Service<T1, T2>
+where
+    T1: FileRepository + Send + Sync,
+    T2: Storage + Send + Sync,
+{
+    // Avatars are shown to anyone, other categories need their own access checks once they exist
+    async fn get_public(&self, file_id: &str) -> Result<(File, Vec<u8>), Error> {
+        let not_found = || Error::NotFound(format!("File {} is not found", file_id));
+
+        let file = self
+            .file_repo
+            .find_by_id(file_id)
+            .await?
+            .filter(|file| file.category == CATEGORY_AVATAR)
+            .ok_or_else(not_found)?;
+        let content = self
+            .storage
+            .get(&file.location)
+            .await?
+            .ok_or_else(not_found)?;
+
+        Ok((file, content))
+    }
+}
@@ -6,3 +6,5 @@ pub mod project_kanban;
 pub mod project_message;
 pub mod user;
 pub(crate) mod role;
+pub mod personal_access_token;
+pub mod file;
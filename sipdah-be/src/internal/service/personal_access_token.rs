@@ -0,0 +1,115 @@
+use crate::internal::common::id;
+use crate::internal::model::error::Error;
+use crate::internal::model::identity::{get_current_identity, require_account_owner};
+use crate::internal::model::personal_access_token::{
+    CreatePersonalAccessTokenRequest, CreatedPersonalAccessTokenResponse, PersonalAccessToken,
+    PersonalAccessTokenResponse, Repository as PersonalAccessTokenRepository,
+    Service as PersonalAccessTokenService, TOKEN_PREFIX,
+};
+use chrono::{Duration, Local};
+use std::ops::Add;
+use std::sync::Arc;
+use tracing::info;
+use validator::Validate;
+
+#[derive(Clone)]
+pub struct Service<T1>
+where
+    T1: PersonalAccessTokenRepository,
+{
+    personal_access_token_repo: Arc<T1>,
+}
+
+impl<T1> Service<T1>
+where
+    T1: PersonalAccessTokenRepository,
+{
+    pub fn new(personal_access_token_repo: Arc<T1>) -> Self {
+        Self {
+            personal_access_token_repo,
+        }
+    }
+}
+
+impl<T1> PersonalAccessTokenService for Service<T1>
+where
+    T1: PersonalAccessTokenRepository,
+{
+    async fn create(
+        &self,
+        req: &CreatePersonalAccessTokenRequest,
+    ) -> Result<CreatedPersonalAccessTokenResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let identity = require_account_owner()?;
+
+        let mut scopes = req.scopes.clone();
+        scopes.sort();
+        scopes.dedup();
+
+        let token = format!("{}{}", TOKEN_PREFIX, id::random_token());
+        let personal_access_token = PersonalAccessToken {
+            id: id::new(),
+            user_id: identity.user_id,
+            name: req.name.clone(),
+            token_hash: id::hash_token(&token),
+            scopes: scopes.join(" "),
+            expires_at: Local::now().add(Duration::days(req.expires_in_days)),
+            last_used_at: None,
+            created_at: Local::now(),
+        };
+
+        self.personal_access_token_repo
+            .create(&personal_access_token)
+            .await?;
+
+        info!(
+            "Personal access token {} created for user {}",
+            personal_access_token.id, identity.email
+        );
+
+        Ok(CreatedPersonalAccessTokenResponse {
+            token,
+            details: PersonalAccessTokenResponse::from(personal_access_token),
+        })
+    }
+
+    async fn get_all(&self) -> Result<Vec<PersonalAccessTokenResponse>, Error> {
+        let identity = get_current_identity()?;
+        let tokens = self
+            .personal_access_token_repo
+            .find_all_by_user_id(&identity.user_id)
+            .await?
+            .into_iter()
+            .map(PersonalAccessTokenResponse::from)
+            .collect();
+
+        Ok(tokens)
+    }
+
+    async fn revoke(&self, token_id: &str) -> Result<(), Error> {
+        let identity = get_current_identity()?;
+        let is_owned = self
+            .personal_access_token_repo
+            .find_all_by_user_id(&identity.user_id)
+            .await?
+            .iter()
+            .any(|token| token.id == token_id);
+        if !is_owned {
+            return Err(Error::NotFound(format!(
+                "Personal access token {} is not found",
+                token_id
+            )));
+        }
+
+        info!(
+            "Personal access token {} revoked by user {}",
+            token_id, identity.email
+        );
+
+        self.personal_access_token_repo
+            .delete(token_id, &identity.user_id)
+            .await
+    }
+}
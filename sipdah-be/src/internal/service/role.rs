@@ -1,34 +1,74 @@
 use crate::internal::common::id;
 use crate::internal::model::error::Error;
+use crate::internal::model::identity::require_permission;
+use crate::internal::model::permission::{
+    role_permissions_key, Permission, PermissionResponse, Repository as PermissionRepository,
+    PERMISSION_ROLE_MANAGE,
+};
 use crate::internal::model::role::{
-    CreateRoleRequest, Repository as RoleRepository, RoleResponse, Service as RoleService,
+    CreateRoleRequest, Repository as RoleRepository, Role, RoleResponse, Service as RoleService,
 };
+use crate::internal::provider::cache::Cache as CacheProvider;
 use chrono::Local;
 use std::sync::Arc;
+use tracing::info;
 use validator::Validate;
 
 #[derive(Clone)]
-pub struct Service<T1>
+pub struct Service<T1, T2, T3>
 where
     T1: RoleRepository,
+    T2: PermissionRepository,
+    T3: CacheProvider,
 {
     role_repo: Arc<T1>,
+    permission_repo: Arc<T2>,
+    cache_provider: Arc<T3>,
 }
 
-impl<T1> Service<T1>
+impl<T1, T2, T3> Service<T1, T2, T3>
 where
     T1: RoleRepository,
+    T2: PermissionRepository,
+    T3: CacheProvider,
 {
-    pub fn new(role_repo: Arc<T1>) -> Self {
-        Self { role_repo }
+    pub fn new(role_repo: Arc<T1>, permission_repo: Arc<T2>, cache_provider: Arc<T3>) -> Self {
+        Self {
+            role_repo,
+            permission_repo,
+            cache_provider,
+        }
+    }
+
+    async fn find_role_and_permission(
+        &self,
+        role_id: &str,
+        permission: &str,
+    ) -> Result<(Role, Permission), Error> {
+        let role = self
+            .role_repo
+            .find_by_id(role_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Role with ID '{}' not found", role_id)))?;
+        let permission = self
+            .permission_repo
+            .find_by_name(permission)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Permission '{}' not found", permission)))?;
+
+        Ok((role, permission))
     }
 }
 
-impl<T1> RoleService for Service<T1>
+impl<T1, T2, T3> RoleService for Service<T1, T2, T3>
 where
     T1: RoleRepository,
+    T2: PermissionRepository,
+    T3: CacheProvider,
 {
     async fn create(&self, role: &CreateRoleRequest) -> Result<(), Error> {
+        require_permission(PERMISSION_ROLE_MANAGE)?;
+
         role.validate()
             .map_err(|err| Error::BadRequest(err.to_string()))?;
 
@@ -40,7 +80,7 @@ where
             )));
         }
 
-        let new_role = crate::internal::model::role::Role {
+        let new_role = Role {
             id: id::new(),
             name: role.name.clone(),
             created_at: Local::now(),
@@ -79,4 +119,68 @@ where
 
         Ok(responses)
     }
+
+    async fn find_all_permissions(&self) -> Result<Vec<PermissionResponse>, Error> {
+        let permissions = self.permission_repo.find_all().await?;
+
+        Ok(permissions
+            .into_iter()
+            .map(PermissionResponse::from)
+            .collect())
+    }
+
+    async fn find_permissions(&self, role_id: &str) -> Result<Vec<PermissionResponse>, Error> {
+        if self.role_repo.find_by_id(role_id).await?.is_none() {
+            return Err(Error::NotFound(format!(
+                "Role with ID '{}' not found",
+                role_id
+            )));
+        }
+
+        let permissions = self.permission_repo.find_all_by_role_id(role_id).await?;
+
+        Ok(permissions
+            .into_iter()
+            .map(PermissionResponse::from)
+            .collect())
+    }
+
+    async fn grant_permission(&self, role_id: &str, permission: &str) -> Result<(), Error> {
+        let identity = require_permission(PERMISSION_ROLE_MANAGE)?;
+        let (role, permission) = self.find_role_and_permission(role_id, permission).await?;
+        // Otherwise a role manager could hand out more than they hold themselves
+        if !identity.permissions.contains(&permission.name) {
+            return Err(Error::Forbidden(format!(
+                "Only a holder of permission {} can grant it",
+                permission.name
+            )));
+        }
+
+        info!(
+            "Permission {} granted to role {} by {}",
+            permission.name, role.name, identity.email
+        );
+
+        self.permission_repo.grant(&role.id, &permission.id).await?;
+        self.cache_provider
+            .del(role_permissions_key(&role.name))
+            .await
+    }
+
+    async fn revoke_permission(&self, role_id: &str, permission: &str) -> Result<(), Error> {
+        let identity = require_permission(PERMISSION_ROLE_MANAGE)?;
+        let (role, permission) = self.find_role_and_permission(role_id, permission).await?;
+
+        info!(
+            "Permission {} revoked from role {} by {}",
+            permission.name, role.name, identity.email
+        );
+
+        self.permission_repo
+            .revoke(&role.id, &permission.id)
+            .await?;
+        self.cache_provider
+            .del(role_permissions_key(&role.name))
+            .await
+    }
 }
@@ -1,95 +1,1062 @@
+use crate::config::Config;
+use crate::internal::common::id;
 use crate::internal::common::uow::Uow;
 use crate::internal::model::error::Error;
-use crate::internal::model::identity::get_current_identity;
-use crate::internal::model::role::Repository as RoleRepository;
+use crate::internal::model::file::{
+    File, FileResponse, FileUpload, Repository as FileRepository, CATEGORY_AVATAR,
+};
+use crate::internal::model::identity::{
+    get_current_identity, require_account_owner, require_permission, Identity,
+};
+use crate::internal::model::login_event::{
+    LoginEventResponse, Repository as LoginEventRepository, LOGIN_HISTORY_LIMIT,
+};
+use crate::internal::model::oidc::{Repository as OidcRepository, UserIdentityResponse};
+use crate::internal::model::passkey::{PasskeyResponse, Repository as PasskeyRepository};
+use crate::internal::model::permission::{PERMISSION_ROLE_ASSIGN, PERMISSION_USER_MANAGE};
+use crate::internal::model::personal_access_token::{
+    PersonalAccessTokenResponse, Repository as PersonalAccessTokenRepository,
+};
+use crate::internal::model::revoked_token::Repository as RevokedTokenRepository;
+use crate::internal::model::role::{
+    user_roles_key, Repository as RoleRepository, ROLE_SUPER_ADMIN,
+};
+use crate::internal::model::session::{Repository as SessionRepository, SessionResponse};
+use crate::internal::model::two_factor::Repository as TwoFactorRepository;
 use crate::internal::model::user::{
-    AddRolesRequest, Repository as UserRepository, Service as UserService, UserResponse,
+    account_restore_key, pending_email_key, reauthentication_failures_key, user_detail_key,
+    ChangeEmailRequest, ChangePasswordRequest, DeleteAccountRequest, ReplaceRolesRequest,
+    Repository as UserRepository, Service as UserService, UpdateProfileRequest, UpdateRolesRequest,
+    User, UserCursor, UserExport, UserFilter, UserResponse, CACHE_USER, SORT_CREATED_AT,
+    SORT_EMAIL, SORT_NAME, STATUS_ACTIVE, STATUS_DEACTIVATED, STATUS_DELETED,
 };
+use crate::internal::model::web::{encode_cursor, PageRequest, PageResponse};
 use crate::internal::provider::cache::Cache as CacheProvider;
+use crate::internal::provider::jwt::Keyring;
+use crate::internal::provider::mailer::{Mail, Mailer};
+use crate::internal::provider::password::Hasher as PasswordHasher;
+use crate::internal::provider::storage::Storage;
+use crate::internal::service::auth::send_email_verification;
+use chrono::Local;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tracing::{info, warn};
 use uow_macro::uow;
+use validator::Validate;
+
+// Judged by the content, the type a client claims for an upload is not trusted
+fn image_type(content: &[u8]) -> Option<(&'static str, &'static str)> {
+    match content {
+        [0x89, b'P', b'N', b'G', ..] => Some(("image/png", "png")),
+        [0xFF, 0xD8, 0xFF, ..] => Some(("image/jpeg", "jpg")),
+        [b'G', b'I', b'F', b'8', ..] => Some(("image/gif", "gif")),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            Some(("image/webp", "webp"))
+        }
+        _ => None,
+    }
+}
+
+fn check_cursor(cursor: Option<&UserCursor>, filter: &UserFilter) -> Result<(), Error> {
+    if cursor.is_some_and(|cursor| {
+        cursor.sort != filter.sort() || cursor.descending != filter.descending
+    }) {
+        return Err(Error::BadRequest(
+            "Cursor belongs to a different sort order".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Cuts the look-ahead user off `users`, a cursor is only handed out when it was there
+fn next_page(
+    mut users: Vec<User>,
+    size: usize,
+    filter: &UserFilter,
+) -> Result<(Vec<User>, Option<String>), Error> {
+    let has_more = users.len() > size;
+    users.truncate(size);
+
+    let next_cursor = match users.last().filter(|_| has_more) {
+        Some(last) => Some(encode_cursor(&UserCursor {
+            sort: filter.sort().to_string(),
+            descending: filter.descending,
+            key: match filter.sort() {
+                SORT_NAME => Some(last.name.clone()),
+                SORT_EMAIL => Some(last.email.clone()),
+                _ => None,
+            },
+            id: last.id.clone(),
+        })?),
+        None => None,
+    };
+
+    Ok((users, next_cursor))
+}
 
 #[derive(Clone)]
-pub struct Service<T1, T2, T3, T4>
+pub struct Service<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
 where
     T1: Uow + Send + Sync,
     T2: UserRepository + Send + Sync,
     T3: RoleRepository + Send + Sync,
     T4: CacheProvider + Send + Sync,
+    T5: SessionRepository + Send + Sync,
+    T6: RevokedTokenRepository + Send + Sync,
+    T7: Mailer + Send + Sync,
+    T8: PasswordHasher + Send + Sync,
+    T9: LoginEventRepository + Send + Sync,
+    T10: FileRepository + Send + Sync,
+    T11: Storage + Send + Sync,
+    T12: PersonalAccessTokenRepository + Send + Sync,
+    T13: PasskeyRepository + Send + Sync,
+    T14: OidcRepository + Send + Sync,
+    T15: TwoFactorRepository + Send + Sync,
 {
+    config: Arc<Config>,
+    keyring: Arc<Keyring>,
     uow: Arc<T1>,
     user_repo: Arc<T2>,
     role_repo: Arc<T3>,
     cache_provider: Arc<T4>,
+    session_repo: Arc<T5>,
+    revoked_token_repo: Arc<T6>,
+    mailer: Arc<T7>,
+    password_hasher: Arc<T8>,
+    login_event_repo: Arc<T9>,
+    file_repo: Arc<T10>,
+    storage: Arc<T11>,
+    personal_access_token_repo: Arc<T12>,
+    passkey_repo: Arc<T13>,
+    user_identity_repo: Arc<T14>,
+    two_factor_repo: Arc<T15>,
 }
 
-impl<T1, T2, T3, T4> Service<T1, T2, T3, T4>
+impl<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
+    Service<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
 where
     T1: Uow + Send + Sync,
     T2: UserRepository + Send + Sync,
     T3: RoleRepository + Send + Sync,
     T4: CacheProvider + Send + Sync,
+    T5: SessionRepository + Send + Sync,
+    T6: RevokedTokenRepository + Send + Sync,
+    T7: Mailer + Send + Sync,
+    T8: PasswordHasher + Send + Sync,
+    T9: LoginEventRepository + Send + Sync,
+    T10: FileRepository + Send + Sync,
+    T11: Storage + Send + Sync,
+    T12: PersonalAccessTokenRepository + Send + Sync,
+    T13: PasskeyRepository + Send + Sync,
+    T14: OidcRepository + Send + Sync,
+    T15: TwoFactorRepository + Send + Sync,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        config: Arc<Config>,
+        keyring: Arc<Keyring>,
         uow: Arc<T1>,
         user_repo: Arc<T2>,
         role_repo: Arc<T3>,
         cache_provider: Arc<T4>,
+        session_repo: Arc<T5>,
+        revoked_token_repo: Arc<T6>,
+        mailer: Arc<T7>,
+        password_hasher: Arc<T8>,
+        login_event_repo: Arc<T9>,
+        file_repo: Arc<T10>,
+        storage: Arc<T11>,
+        personal_access_token_repo: Arc<T12>,
+        passkey_repo: Arc<T13>,
+        user_identity_repo: Arc<T14>,
+        two_factor_repo: Arc<T15>,
     ) -> Self {
         Self {
+            config,
+            keyring,
             uow,
             user_repo,
             role_repo,
             cache_provider,
+            session_repo,
+            revoked_token_repo,
+            mailer,
+            password_hasher,
+            login_event_repo,
+            file_repo,
+            storage,
+            personal_access_token_repo,
+            passkey_repo,
+            user_identity_repo,
+            two_factor_repo,
+        }
+    }
+
+    // Loads the signed-in user after checking their password again, interactive sessions only
+    async fn reauthenticate(&self, password: &str) -> Result<(Identity, User), Error> {
+        let identity = require_account_owner()?;
+        if identity.session_id.is_none() {
+            return Err(Error::Forbidden(
+                "Account security settings require an interactive session".to_string(),
+            ));
+        }
+
+        let failures_key = reauthentication_failures_key(&identity.user_id);
+        let failures = self
+            .cache_provider
+            .get::<i64>(failures_key.clone())
+            .await?
+            .unwrap_or_default();
+        if failures >= self.config.sign_in_max_attempts {
+            return Err(Error::TooManyRequests(
+                "Too many incorrect passwords, please try again later".to_string(),
+            ));
+        }
+
+        let user = self
+            .user_repo
+            .find_by_id(&identity.user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        if !self
+            .password_hasher
+            .verify(password, &user.password)
+            .await?
+        {
+            self.cache_provider
+                .incr(failures_key, self.config.sign_in_lockout_ttl)
+                .await?;
+            return Err(Error::BadRequest(
+                "Current password is incorrect".to_string(),
+            ));
+        }
+        self.cache_provider.del(failures_key).await?;
+
+        Ok((identity, user))
+    }
+
+    // Ends every session but the current one and revokes their access tokens
+    async fn end_other_sessions(&self, identity: &Identity) -> Result<(), Error> {
+        for session in self
+            .session_repo
+            .find_all_by_user_id(&identity.user_id)
+            .await?
+        {
+            if identity.session_id.as_ref() == Some(&session.id) {
+                continue;
+            }
+
+            self.session_repo.delete(&session).await?;
+            self.revoked_token_repo
+                .revoke(&session.access_token_id, session.access_token_expires_at)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // The cached profile with roles, for callers that already checked who may see it
+    async fn find_response(&self, user_id: &str) -> Result<UserResponse, Error> {
+        let is_cached = self.config.is_cache_enabled(CACHE_USER);
+        let key = user_detail_key(user_id);
+
+        // A cached `None` remembers a user that does not exist
+        let cached = match is_cached {
+            true => {
+                self.cache_provider
+                    .get::<Option<UserResponse>>(key.clone())
+                    .await?
+            }
+            false => None,
+        };
+        let res = match cached {
+            Some(res) => res,
+            None => {
+                let res = match self.user_repo.find_by_id(user_id).await? {
+                    Some(user) => Some(self.to_response(user).await?),
+                    None => None,
+                };
+
+                let ttl = match res {
+                    Some(_) => self.config.redis_default_ttl,
+                    None => self.config.cache_negative_ttl,
+                };
+                if is_cached {
+                    self.cache_provider.setx(key, &res, ttl).await?;
+                }
+
+                res
+            }
+        };
+
+        res.ok_or_else(|| Error::NotFound("User not found".to_string()))
+    }
+
+    async fn to_response(&self, user: User) -> Result<UserResponse, Error> {
+        let roles = self
+            .role_repo
+            .find_all_by_user_id(&user.id)
+            .await?
+            .into_iter()
+            .map(|role| role.name)
+            .collect();
+
+        Ok(UserResponse {
+            roles,
+            ..UserResponse::from(user)
+        })
+    }
+
+    // Adds and removes roles by id, checking every id before anything changes
+    async fn change_roles(
+        &self,
+        user_id: &str,
+        add: &[String],
+        remove: &[String],
+    ) -> Result<UserResponse, Error> {
+        require_permission(PERMISSION_ROLE_ASSIGN)?;
+
+        let identity = get_current_identity()?;
+        let user = self
+            .user_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        for role_id in add.iter().chain(remove) {
+            let role = self
+                .role_repo
+                .find_by_id(role_id)
+                .await?
+                .ok_or_else(|| Error::NotFound(format!("Role {} not found", role_id)))?;
+            // Otherwise an admin could hand out more than they hold themselves
+            if !identity.roles.contains(&role.name) {
+                return Err(Error::Forbidden(format!(
+                    "Only a holder of role {} can assign or remove it",
+                    role.name
+                )));
+            }
+        }
+
+        let mut assigned: Vec<String> = self
+            .role_repo
+            .find_all_by_user_id(&user.id)
+            .await?
+            .into_iter()
+            .map(|role| role.id)
+            .collect();
+        for role_id in add {
+            if !assigned.contains(role_id) {
+                self.role_repo.add(&user.id, role_id).await?;
+                assigned.push(role_id.clone());
+            }
+        }
+        for role_id in remove {
+            if assigned.contains(role_id) {
+                self.role_repo.remove(&user.id, role_id).await?;
+                assigned.retain(|assigned| assigned != role_id);
+            }
+        }
+
+        info!("Roles of user {} changed by {}", user.email, identity.email);
+
+        self.uow.evict(user_roles_key(&user.id)).await?;
+        self.uow.evict(user_detail_key(&user.id)).await?;
+
+        // Built from the transaction, the cached profile still holds the old roles until commit
+        self.to_response(user).await
+    }
+
+    async fn end_all_sessions(&self, user_id: &str) -> Result<(), Error> {
+        for session in self.session_repo.find_all_by_user_id(user_id).await? {
+            self.session_repo.delete(&session).await?;
+            self.revoked_token_repo
+                .revoke(&session.access_token_id, session.access_token_expires_at)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn store_avatar(&self, user_id: &str, avatar: FileUpload) -> Result<File, Error> {
+        if avatar.content.len() > self.config.avatar_max_size_kib * 1024 {
+            return Err(Error::BadRequest(format!(
+                "Avatar must not be larger than {} KiB",
+                self.config.avatar_max_size_kib
+            )));
+        }
+        let (file_type, extension) = image_type(&avatar.content).ok_or_else(|| {
+            Error::BadRequest("Avatar must be a PNG, JPEG, GIF or WebP image".to_string())
+        })?;
+
+        let file_id = id::new();
+        let file = File {
+            location: format!("{}/{}.{}", CATEGORY_AVATAR, file_id, extension),
+            id: file_id,
+            owner_id: Some(user_id.to_string()),
+            category: CATEGORY_AVATAR.to_string(),
+            file_name: avatar
+                .file_name
+                .map(|file_name| file_name.chars().take(255).collect())
+                .unwrap_or_else(|| format!("avatar.{}", extension)),
+            file_type: file_type.to_string(),
+            file_size: avatar.content.len() as u64,
+            created_at: Local::now(),
+        };
+
+        self.storage.put(&file.location, &avatar.content).await?;
+
+        Ok(file)
+    }
+
+    // Returns where the content was, to be deleted from storage once the transaction commits
+    async fn remove_file(&self, file_id: &str) -> Result<Option<String>, Error> {
+        let file = match self.file_repo.find_by_id(file_id).await? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        self.file_repo.delete(&file.id).await?;
+
+        Ok(Some(file.location))
+    }
+
+    // A content left behind in storage only costs space, so failing to delete it is not fatal
+    async fn delete_from_storage(&self, location: &str) {
+        if let Err(err) = self.storage.delete(location).await {
+            warn!("Failed to delete {} from storage: {}", location, err);
+        }
+    }
+
+    // Returns the location of the avatar that was replaced, if any
+    #[uow]
+    async fn save_profile(
+        &self,
+        user_id: &str,
+        req: &UpdateProfileRequest,
+        avatar: Option<File>,
+    ) -> Result<Option<String>, Error> {
+        let mut user = self
+            .user_repo
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+
+        if let Some(name) = &req.name {
+            user.name = name.clone();
+        }
+        if let Some(phone_number) = &req.phone_number {
+            user.phone_number =
+                Some(phone_number.clone()).filter(|phone_number| !phone_number.is_empty());
+        }
+        let replaced_photo_id = match avatar {
+            Some(avatar) => {
+                self.file_repo.create(&avatar).await?;
+                user.photo_id.replace(avatar.id)
+            }
+            None => None,
+        };
+
+        self.user_repo.update_profile(&user).await?;
+        let replaced_location = match replaced_photo_id {
+            Some(photo_id) => self.remove_file(&photo_id).await?,
+            None => None,
+        };
+        self.uow.evict(user_detail_key(&user.id)).await?;
+
+        info!("Profile updated for user {}", user.email);
+
+        Ok(replaced_location)
+    }
+
+    // Strips a deleted account of personal data, the rows pointing at it stay
+    #[uow]
+    async fn anonymize(&self, user: &User) -> Result<Vec<String>, Error> {
+        let mut locations = vec![];
+        for file in self.file_repo.find_all_by_owner_id(&user.id).await? {
+            locations.extend(self.remove_file(&file.id).await?);
+        }
+        self.user_identity_repo
+            .delete_all_by_user_id(&user.id)
+            .await?;
+        self.passkey_repo.delete_all_by_user_id(&user.id).await?;
+        self.personal_access_token_repo
+            .delete_all_by_user_id(&user.id)
+            .await?;
+        self.two_factor_repo
+            .delete_recovery_codes_by_user_id(&user.id)
+            .await?;
+        self.two_factor_repo.delete_by_user_id(&user.id).await?;
+        self.login_event_repo
+            .delete_all_by_user_id(&user.id)
+            .await?;
+        self.user_repo.anonymize(&user.id).await?;
+        self.uow.evict(user_detail_key(&user.id)).await?;
+
+        info!("Anonymized deleted user {}", user.id);
+
+        Ok(locations)
+    }
+
+    // Mailing is left to the caller, so no link goes out for a change that was rolled back
+    #[uow]
+    async fn request_email_change(&self, req: &ChangeEmailRequest) -> Result<User, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let (identity, mut user) = self.reauthenticate(&req.current_password).await?;
+
+        if user.email == req.email {
+            return Err(Error::BadRequest(
+                "New email must differ from the current one".to_string(),
+            ));
+        }
+        if self.user_repo.exists_by_email(&req.email).await? {
+            return Err(Error::Conflict(format!(
+                "Email {} already exists",
+                req.email
+            )));
+        }
+
+        info!(
+            "Email change requested for user {}, ending other sessions",
+            user.id
+        );
+
+        self.end_other_sessions(&identity).await?;
+
+        // The account keeps its current address until the new one is confirmed
+        self.cache_provider
+            .setx(
+                pending_email_key(&user.id),
+                req.email.clone(),
+                self.config.email_verification_ttl,
+            )
+            .await?;
+
+        user.email = req.email.clone();
+        Ok(user)
+    }
+
+    #[uow]
+    async fn delete_account(&self, user: &User) -> Result<(), Error> {
+        self.user_repo.delete(&user.id).await?;
+        self.uow.evict(user_detail_key(&user.id)).await
+    }
+
+    // An admin must not be able to switch off, or back on, the account of a super admin
+    async fn ensure_outranks(&self, identity: &Identity, user: &User) -> Result<(), Error> {
+        let is_super_admin = self
+            .role_repo
+            .find_all_by_user_id(&user.id)
+            .await?
+            .iter()
+            .any(|role| role.name == ROLE_SUPER_ADMIN);
+        if is_super_admin && !identity.roles.iter().any(|role| role == ROLE_SUPER_ADMIN) {
+            return Err(Error::Forbidden(format!(
+                "Only role {} can manage users with role {}",
+                ROLE_SUPER_ADMIN, ROLE_SUPER_ADMIN
+            )));
         }
+
+        Ok(())
     }
 }
 
-impl<T1, T2, T3, T4> UserService for Service<T1, T2, T3, T4>
+impl<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15> UserService
+    for Service<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15>
 where
     T1: Uow + Send + Sync,
     T2: UserRepository + Send + Sync,
     T3: RoleRepository + Send + Sync,
     T4: CacheProvider + Send + Sync,
+    T5: SessionRepository + Send + Sync,
+    T6: RevokedTokenRepository + Send + Sync,
+    T7: Mailer + Send + Sync,
+    T8: PasswordHasher + Send + Sync,
+    T9: LoginEventRepository + Send + Sync,
+    T10: FileRepository + Send + Sync,
+    T11: Storage + Send + Sync,
+    T12: PersonalAccessTokenRepository + Send + Sync,
+    T13: PasskeyRepository + Send + Sync,
+    T14: OidcRepository + Send + Sync,
+    T15: TwoFactorRepository + Send + Sync,
 {
     async fn get_by_id(&self, user_id: &str) -> Result<UserResponse, Error> {
-        let res = self
+        let identity = get_current_identity()?;
+        let res = self.find_response(user_id).await?;
+
+        // Roles show who is worth targeting, so only the user and user managers get to see them
+        match identity.user_id == user_id || require_permission(PERMISSION_USER_MANAGE).is_ok() {
+            true => Ok(res),
+            false => Ok(UserResponse {
+                roles: Vec::new(),
+                ..res
+            }),
+        }
+    }
+
+    async fn get_current(&self) -> Result<UserResponse, Error> {
+        let identity = get_current_identity()?;
+        self.find_response(&identity.user_id).await
+    }
+
+    async fn get_all(
+        &self,
+        page: PageRequest,
+        filter: UserFilter,
+    ) -> Result<PageResponse<UserResponse>, Error> {
+        require_permission(PERMISSION_USER_MANAGE)?;
+
+        filter
+            .validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+        if ![STATUS_ACTIVE, STATUS_DEACTIVATED, STATUS_DELETED].contains(&filter.status()) {
+            return Err(Error::BadRequest(format!(
+                "Status must be one of {}, {} or {}",
+                STATUS_ACTIVE, STATUS_DEACTIVATED, STATUS_DELETED
+            )));
+        }
+        if ![SORT_CREATED_AT, SORT_NAME, SORT_EMAIL].contains(&filter.sort()) {
+            return Err(Error::BadRequest(format!(
+                "Sort must be one of {}, {} or {}",
+                SORT_CREATED_AT, SORT_NAME, SORT_EMAIL
+            )));
+        }
+
+        let cursor = page.cursor::<UserCursor>()?;
+        check_cursor(cursor.as_ref(), &filter)?;
+
+        // One more than asked for tells whether another page follows
+        let users = self
             .user_repo
-            .find_by_id(user_id)
-            .await?
+            .find_all(&filter, cursor.as_ref(), page.size() + 1)
+            .await?;
+        let (users, next_cursor) = next_page(users, page.size() as usize, &filter)?;
+
+        let user_ids: Vec<String> = users.iter().map(|user| user.id.clone()).collect();
+        let mut roles: HashMap<String, Vec<String>> = HashMap::new();
+        for role in self.role_repo.find_all_by_user_ids(&user_ids).await? {
+            roles.entry(role.user_id).or_default().push(role.name);
+        }
+        let data: Vec<UserResponse> = users
+            .into_iter()
             .map(|user| UserResponse {
-                id: user.id,
-                email: user.email,
-                name: user.name,
-                phone_number: user.phone_number,
-                photo_url: user.photo_url,
-                created_at: user.created_at,
-                updated_at: user.updated_at,
+                roles: roles.remove(&user.id).unwrap_or_default(),
+                ..UserResponse::from(user)
             })
-            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+            .collect();
+
+        Ok(PageResponse {
+            size: data.len() as u32,
+            data,
+            next_cursor,
+        })
+    }
+
+    #[uow]
+    async fn update_roles(&self, req: UpdateRolesRequest) -> Result<UserResponse, Error> {
+        if let Some(role_id) = req.add.iter().find(|role_id| req.remove.contains(role_id)) {
+            return Err(Error::BadRequest(format!(
+                "Role {} cannot be added and removed at once",
+                role_id
+            )));
+        }
+
+        self.change_roles(&req.user_id, &req.add, &req.remove).await
+    }
+
+    #[uow]
+    async fn replace_roles(&self, req: ReplaceRolesRequest) -> Result<UserResponse, Error> {
+        let remove: Vec<String> = self
+            .role_repo
+            .find_all_by_user_id(&req.user_id)
+            .await?
+            .into_iter()
+            .map(|role| role.id)
+            .filter(|role_id| !req.roles.contains(role_id))
+            .collect();
+
+        self.change_roles(&req.user_id, &req.roles, &remove).await
+    }
+
+    #[uow]
+    async fn remove_role(&self, user_id: &str, role_id: &str) -> Result<UserResponse, Error> {
+        self.change_roles(user_id, &[], &[role_id.to_string()])
+            .await
+    }
+
+    #[uow]
+    async fn change_password(&self, req: ChangePasswordRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let (identity, user) = self.reauthenticate(&req.current_password).await?;
+
+        let password = self.password_hasher.hash(&req.new_password).await?;
+        self.user_repo.update_password(&user.id, &password).await?;
+        self.uow.evict(user_detail_key(&user.id)).await?;
+
+        info!(
+            "Password changed for user {}, ending other sessions",
+            user.id
+        );
+
+        self.end_other_sessions(&identity).await
+    }
+
+    async fn change_email(&self, req: ChangeEmailRequest) -> Result<UserResponse, Error> {
+        let user = self.request_email_change(&req).await?;
+        send_email_verification(&self.config, &self.keyring, self.mailer.as_ref(), &user).await?;
+
+        self.find_response(&user.id).await
+    }
+
+    async fn update_profile(&self, mut req: UpdateProfileRequest) -> Result<UserResponse, Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let identity = get_current_identity()?;
+        let avatar = match req.avatar.take() {
+            Some(avatar) => Some(self.store_avatar(&identity.user_id, avatar).await?),
+            None => None,
+        };
+        let stored_location = avatar.as_ref().map(|avatar| avatar.location.clone());
+
+        // Storage is not part of the transaction, so it is cleaned up once the outcome is known
+        match self.save_profile(&identity.user_id, &req, avatar).await {
+            Ok(replaced_location) => {
+                if let Some(location) = replaced_location {
+                    self.delete_from_storage(&location).await;
+                }
+            }
+            Err(err) => {
+                if let Some(location) = stored_location {
+                    self.delete_from_storage(&location).await;
+                }
+                return Err(err);
+            }
+        }
+
+        self.find_response(&identity.user_id).await
+    }
+
+    async fn get_login_history(&self) -> Result<Vec<LoginEventResponse>, Error> {
+        let identity = get_current_identity()?;
+        let login_events = self
+            .login_event_repo
+            .find_all_by_user_id(&identity.user_id, LOGIN_HISTORY_LIMIT)
+            .await?;
+
+        Ok(login_events
+            .into_iter()
+            .map(LoginEventResponse::from)
+            .collect())
+    }
+
+    async fn get_login_history_by_user_id(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<LoginEventResponse>, Error> {
+        require_permission(PERMISSION_USER_MANAGE)?;
+
+        let login_events = self
+            .login_event_repo
+            .find_all_by_user_id(user_id, LOGIN_HISTORY_LIMIT)
+            .await?;
+
+        Ok(login_events
+            .into_iter()
+            .map(LoginEventResponse::from)
+            .collect())
+    }
+
+    async fn delete_current(&self, req: DeleteAccountRequest) -> Result<(), Error> {
+        req.validate()
+            .map_err(|err| Error::BadRequest(err.to_string()))?;
+
+        let (_, user) = self.reauthenticate(&req.current_password).await?;
+
+        self.delete_account(&user).await?;
+        self.end_all_sessions(&user.id).await?;
 
+        let token = id::random_token();
         self.cache_provider
-            .set(format!("user:detail:{}", res.id), &res)
+            .setx(
+                account_restore_key(&token),
+                &user.id,
+                self.config.account_deletion_grace_period,
+            )
             .await?;
 
-        Ok(res)
+        info!("Deletion requested for user {}", user.email);
+
+        // The account is already gone, so a failed mail is only logged
+        let link = format!("{}/restore-account?token={}", self.config.app_url, token);
+        let mail = Mail {
+            to: user.email.clone(),
+            subject: "Your account is scheduled for deletion".to_string(),
+            body: format!(
+                "Hi {},\n\n\
+                    Your account has been deleted and you have been signed out everywhere. \
+                    Your personal data is erased for good in {} days. Until then, the link \
+                    below brings the account back.\n\n{}\n\n\
+                    If you did not ask for this, restore the account and change your password.",
+                user.name,
+                self.config.account_deletion_grace_period.num_days(),
+                link
+            ),
+        };
+        if let Err(err) = self.mailer.send(&mail).await {
+            warn!("Failed to send deletion email to {}: {}", user.email, err);
+        }
+
+        Ok(())
     }
 
-    async fn get_current(&self) -> Result<UserResponse, Error> {
+    #[uow]
+    async fn deactivate(&self, user_id: &str) -> Result<(), Error> {
+        require_permission(PERMISSION_USER_MANAGE)?;
+
         let identity = get_current_identity()?;
-        self.get_by_id(&identity.user_id).await
+        if identity.user_id == user_id {
+            return Err(Error::BadRequest(
+                "Cannot deactivate your own account".to_string(),
+            ));
+        }
+
+        let user = self
+            .user_repo
+            .find_any_by_id(user_id)
+            .await?
+            .filter(|user| user.deleted_at.is_none())
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        self.ensure_outranks(&identity, &user).await?;
+        if user.deactivated_at.is_some() {
+            return Ok(());
+        }
+
+        warn!("User {} deactivated by {}", user.email, identity.email);
+
+        self.user_repo.deactivate(&user.id).await?;
+        self.end_all_sessions(&user.id).await?;
+        self.uow.evict(user_detail_key(&user.id)).await
     }
 
     #[uow]
-    async fn add_roles(&self, req: AddRolesRequest) -> Result<UserResponse, Error> {
-        if self.user_repo.exists_by_id(&req.user_id).await? {
-            return Err(Error::NotFound("User is not found".to_string()));
+    async fn reactivate(&self, user_id: &str) -> Result<(), Error> {
+        require_permission(PERMISSION_USER_MANAGE)?;
+
+        let identity = get_current_identity()?;
+        let user = self
+            .user_repo
+            .find_any_by_id(user_id)
+            .await?
+            .filter(|user| user.deleted_at.is_none())
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        self.ensure_outranks(&identity, &user).await?;
+        if user.deactivated_at.is_none() {
+            return Ok(());
+        }
+
+        info!("User {} reactivated by {}", user.email, identity.email);
+
+        self.user_repo.reactivate(&user.id).await?;
+        self.uow.evict(user_detail_key(&user.id)).await
+    }
+
+    async fn export(&self) -> Result<UserExport, Error> {
+        let identity = get_current_identity()?;
+        let user_id = identity.user_id.as_str();
+
+        let sessions = self
+            .session_repo
+            .find_all_by_user_id(user_id)
+            .await?
+            .into_iter()
+            .map(|session| SessionResponse {
+                current: identity.session_id.as_ref() == Some(&session.id),
+                id: session.id,
+                user_agent: session.user_agent,
+                ip_address: session.ip_address,
+                created_at: session.created_at,
+                last_used_at: session.last_used_at,
+            })
+            .collect();
+
+        Ok(UserExport {
+            exported_at: Local::now(),
+            profile: self.find_response(user_id).await?,
+            two_factor_enabled: self
+                .two_factor_repo
+                .find_by_user_id(user_id)
+                .await?
+                .is_some_and(|two_factor| two_factor.enabled_at.is_some()),
+            identities: self
+                .user_identity_repo
+                .find_all_by_user_id(user_id)
+                .await?
+                .into_iter()
+                .map(UserIdentityResponse::from)
+                .collect(),
+            passkeys: self
+                .passkey_repo
+                .find_all_by_user_id(user_id)
+                .await?
+                .into_iter()
+                .map(PasskeyResponse::from)
+                .collect(),
+            personal_access_tokens: self
+                .personal_access_token_repo
+                .find_all_by_user_id(user_id)
+                .await?
+                .into_iter()
+                .map(PersonalAccessTokenResponse::from)
+                .collect(),
+            sessions,
+            // The whole history rather than the page shown in the account settings
+            login_history: self
+                .login_event_repo
+                .find_all_by_user_id(user_id, u32::MAX)
+                .await?
+                .into_iter()
+                .map(LoginEventResponse::from)
+                .collect(),
+            files: self
+                .file_repo
+                .find_all_by_owner_id(user_id)
+                .await?
+                .into_iter()
+                .map(FileResponse::from)
+                .collect(),
+        })
+    }
+
+    async fn anonymize_deleted(&self) -> Result<usize, Error> {
+        let deleted_before = Local::now() - self.config.account_deletion_grace_period;
+        let users = self
+            .user_repo
+            .find_all_deleted_before(deleted_before)
+            .await?;
+
+        for user in &users {
+            // Contents are only deleted once the rows pointing at them are gone for good
+            for location in self.anonymize(user).await? {
+                self.delete_from_storage(&location).await;
+            }
+        }
+
+        Ok(users.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(sort: &str, descending: bool) -> UserFilter {
+        UserFilter {
+            search: None,
+            role: None,
+            status: None,
+            created_from: None,
+            created_to: None,
+            sort: Some(sort.to_string()),
+            descending,
         }
+    }
 
-        for role in req.roles {
-            self.role_repo.add(&req.user_id, &role).await?;
+    fn user(n: u32) -> User {
+        User {
+            id: format!("0190f8a4-7c2e-7000-8000-00000000000{}", n),
+            email: format!("user{}@example.com", n),
+            password: String::new(),
+            name: format!("User {}", n),
+            phone_number: None,
+            photo_id: None,
+            email_verified_at: None,
+            created_at: Local::now(),
+            updated_at: Local::now(),
+            deactivated_at: None,
+            deleted_at: None,
         }
+    }
+
+    fn read_cursor(cursor: String) -> UserCursor {
+        PageRequest {
+            cursor: Some(cursor),
+            size: None,
+        }
+        .cursor::<UserCursor>()
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn cursor_round_trips_for_every_sort_order() {
+        for sort in [SORT_CREATED_AT, SORT_NAME, SORT_EMAIL] {
+            for descending in [false, true] {
+                let filter = filter(sort, descending);
+
+                let (users, next_cursor) =
+                    next_page(vec![user(1), user(2), user(3)], 2, &filter).unwrap();
+                let cursor = read_cursor(next_cursor.unwrap());
+
+                assert_eq!(users.len(), 2);
+                assert_eq!(cursor.id, users[1].id);
+                assert_eq!(cursor.sort, sort);
+                assert_eq!(cursor.descending, descending);
+                assert_eq!(
+                    cursor.key,
+                    match sort {
+                        SORT_NAME => Some(users[1].name.clone()),
+                        SORT_EMAIL => Some(users[1].email.clone()),
+                        _ => None,
+                    }
+                );
+                assert!(check_cursor(Some(&cursor), &filter).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn the_last_page_has_no_cursor() {
+        let filter = filter(SORT_NAME, false);
+
+        let (users, next_cursor) = next_page(vec![user(1), user(2)], 2, &filter).unwrap();
+
+        assert_eq!(users.len(), 2);
+        assert!(next_cursor.is_none());
+    }
+
+    #[test]
+    fn a_cursor_of_another_sort_order_is_rejected() {
+        let (_, next_cursor) =
+            next_page(vec![user(1), user(2)], 1, &filter(SORT_NAME, false)).unwrap();
+        let cursor = read_cursor(next_cursor.unwrap());
+
+        assert!(matches!(
+            check_cursor(Some(&cursor), &filter(SORT_EMAIL, false)),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!(
+            check_cursor(Some(&cursor), &filter(SORT_NAME, true)),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn a_cursor_that_was_not_handed_out_is_rejected() {
+        let page = PageRequest {
+            cursor: Some("not-a-cursor".to_string()),
+            size: None,
+        };
 
-        self.get_by_id(&req.user_id).await
+        assert!(matches!(
+            page.cursor::<UserCursor>(),
+            Err(Error::BadRequest(_))
+        ));
     }
 }
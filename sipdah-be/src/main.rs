@@ -2,17 +2,26 @@ use crate::config::Config;
 use crate::db::mysql;
 use crate::db::redis;
 use crate::internal::common::uow;
+use crate::internal::middleware::csrf::CSRF_HEADER;
+use crate::internal::model::role::SUPER_ADMIN_ROLES;
+use crate::internal::model::user::Service as _;
 use crate::internal::router::auth;
+use crate::internal::router::auth::TOKEN_DELIVERY_HEADER;
+use crate::internal::router::file;
+use crate::internal::router::personal_access_token;
 use crate::internal::router::role;
 use crate::internal::router::user;
 use crate::internal::{middleware, provider, repository, service};
-use axum::http::{header, HeaderValue, Method};
+use axum::extract::DefaultBodyLimit;
+use axum::http::{header, HeaderName, HeaderValue, Method};
 use axum::middleware::from_fn_with_state;
-use axum::routing::{delete, get, patch, post};
+use axum::routing::{delete, get, patch, post, put};
+use axum::Extension;
 use axum::Router;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
-use tracing::{error, Level};
+use tracing::{error, info, Level};
 
 mod config;
 mod db;
@@ -40,32 +49,159 @@ async fn main() {
         }
     };
 
+    let keyring = match provider::jwt::Keyring::new(&config) {
+        Ok(keyring) => Arc::new(keyring),
+        Err(err) => {
+            error!(error = %err, "Failed to load jwt keys");
+            return;
+        }
+    };
+
+    let oidc_client = match provider::oidc::Client::new(&config) {
+        Ok(oidc_client) => Arc::new(oidc_client),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize oidc client");
+            return;
+        }
+    };
+    let webauthn = match provider::webauthn::new(&config) {
+        Ok(webauthn) => Arc::new(webauthn),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize webauthn");
+            return;
+        }
+    };
+    let password_hasher = match provider::password::Argon2id::new(&config) {
+        Ok(password_hasher) => Arc::new(password_hasher),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize password hasher");
+            return;
+        }
+    };
+
+    let cipher = match provider::cipher::Aes::new(&config) {
+        Ok(cipher) => Arc::new(cipher),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize cipher");
+            return;
+        }
+    };
+
     let user_repo = Arc::new(repository::user::Repository::new(Arc::clone(&mysql)));
     let role_repo = Arc::new(repository::role::Repository::new(Arc::clone(&mysql)));
+    let two_factor_repo = Arc::new(repository::two_factor::Repository::new(
+        Arc::clone(&mysql),
+        Arc::clone(&cipher),
+    ));
+    let user_identity_repo = Arc::new(repository::oidc::Repository::new(Arc::clone(&mysql)));
+    let permission_repo = Arc::new(repository::permission::Repository::new(Arc::clone(&mysql)));
+    let audit_log_repo = Arc::new(repository::audit_log::Repository::new(Arc::clone(&mysql)));
+    let passkey_repo = Arc::new(repository::passkey::Repository::new(Arc::clone(&mysql)));
+    let login_event_repo = Arc::new(repository::login_event::Repository::new(Arc::clone(&mysql)));
+    let file_repo = Arc::new(repository::file::Repository::new(Arc::clone(&mysql)));
+    let personal_access_token_repo = Arc::new(repository::personal_access_token::Repository::new(
+        Arc::clone(&mysql),
+    ));
 
     let cache_provider = Arc::new(provider::cache::Redis::new(
         Arc::clone(&config),
         Arc::clone(&redis),
     ));
 
-    let uow = Arc::new(uow::TransactionManager::new(Arc::clone(&mysql)));
+    let storage = Arc::new(provider::storage::Local::new(Arc::clone(&config)));
+
+    let mailer = match provider::mailer::Transport::new(Arc::clone(&config)) {
+        Ok(mailer) => Arc::new(mailer),
+        Err(err) => {
+            error!(error = %err, "Failed to initialize mailer");
+            return;
+        }
+    };
+
+    let session_repo = Arc::new(repository::session::Repository::new(
+        Arc::clone(&config),
+        Arc::clone(&cache_provider),
+    ));
+
+    let revoked_token_repo = Arc::new(repository::revoked_token::Repository::new(Arc::clone(
+        &cache_provider,
+    )));
+
+    let uow = Arc::new(uow::TransactionManager::new(
+        Arc::clone(&mysql),
+        Arc::clone(&cache_provider),
+    ));
 
     let auth_service = Arc::new(service::auth::Service::new(
         Arc::clone(&config),
+        Arc::clone(&keyring),
+        Arc::clone(&oidc_client),
+        Arc::clone(&webauthn),
         Arc::clone(&uow),
         Arc::clone(&user_repo),
         Arc::clone(&role_repo),
+        Arc::clone(&session_repo),
+        Arc::clone(&revoked_token_repo),
+        Arc::clone(&two_factor_repo),
         Arc::clone(&cache_provider),
+        Arc::clone(&mailer),
+        Arc::clone(&user_identity_repo),
+        Arc::clone(&personal_access_token_repo),
+        Arc::clone(&permission_repo),
+        Arc::clone(&password_hasher),
+        Arc::clone(&audit_log_repo),
+        Arc::clone(&passkey_repo),
+        Arc::clone(&login_event_repo),
     ));
     let user_service = Arc::new(service::user::Service::new(
+        Arc::clone(&config),
+        Arc::clone(&keyring),
         Arc::clone(&uow),
         Arc::clone(&user_repo),
         Arc::clone(&role_repo),
         Arc::clone(&cache_provider),
+        Arc::clone(&session_repo),
+        Arc::clone(&revoked_token_repo),
+        Arc::clone(&mailer),
+        Arc::clone(&password_hasher),
+        Arc::clone(&login_event_repo),
+        Arc::clone(&file_repo),
+        Arc::clone(&storage),
+        Arc::clone(&personal_access_token_repo),
+        Arc::clone(&passkey_repo),
+        Arc::clone(&user_identity_repo),
+        Arc::clone(&two_factor_repo),
+    ));
+    let role_service = Arc::new(service::role::Service::new(
+        Arc::clone(&role_repo),
+        Arc::clone(&permission_repo),
+        Arc::clone(&cache_provider),
+    ));
+    let personal_access_token_service = Arc::new(service::personal_access_token::Service::new(
+        Arc::clone(&personal_access_token_repo),
+    ));
+    let file_service = Arc::new(service::file::Service::new(
+        Arc::clone(&file_repo),
+        Arc::clone(&storage),
     ));
-    let role_service = Arc::new(service::role::Service::new(Arc::clone(&role_repo)));
+
+    // Deleted accounts are anonymized once their grace period has ended
+    let purge_service = Arc::clone(&user_service);
+    let purge_interval = config.account_purge_interval.to_std().unwrap();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(purge_interval);
+        loop {
+            interval.tick().await;
+            match purge_service.anonymize_deleted().await {
+                Ok(0) => {}
+                Ok(count) => info!("Anonymized {} deleted users", count),
+                Err(err) => error!(error = %err, "Failed to anonymize deleted users"),
+            }
+        }
+    });
 
     let auth_state = Arc::new(auth::AuthState {
+        config: Arc::clone(&config),
         auth_service: Arc::clone(&auth_service),
     });
     let user_state = Arc::new(user::UserState {
@@ -74,14 +210,83 @@ async fn main() {
     let role_state = Arc::new(role::RoleState {
         role_service: Arc::clone(&role_service),
     });
+    let personal_access_token_state = Arc::new(personal_access_token::PersonalAccessTokenState {
+        personal_access_token_service: Arc::clone(&personal_access_token_service),
+    });
+    let file_state = Arc::new(file::FileState {
+        file_service: Arc::clone(&file_service),
+    });
 
     let auth_route = Router::new()
         .route("/api/v1/auth/signup", post(auth::sign_up))
         .route("/api/v1/auth/signin", post(auth::sign_in))
-        .route("/api/v1/auth/refresh", get(auth::refresh))
+        .route("/api/v1/auth/refresh", post(auth::refresh))
+        .route("/api/v1/auth/magic-link", post(auth::send_magic_link))
+        .route(
+            "/api/v1/auth/magic-link/verify",
+            post(auth::verify_magic_link),
+        )
+        .route("/api/v1/auth/forgot-password", post(auth::forgot_password))
+        .route("/api/v1/auth/reset-password", post(auth::reset_password))
+        .route("/api/v1/auth/restore-account", post(auth::restore_account))
+        .route("/api/v1/auth/verify-email", post(auth::verify_email))
+        .route(
+            "/api/v1/auth/verify-email/resend",
+            post(auth::resend_email_verification),
+        )
+        .route(
+            "/api/v1/auth/oidc/{provider}/authorize",
+            get(auth::oidc_authorize),
+        )
+        .route(
+            "/api/v1/auth/oidc/{provider}/callback",
+            post(auth::oidc_callback),
+        )
+        .route("/api/v1/auth/2fa/verify", post(auth::verify_two_factor))
+        .route(
+            "/api/v1/auth/passkeys/authenticate/start",
+            post(auth::start_passkey_authentication),
+        )
+        .route(
+            "/api/v1/auth/passkeys/authenticate/finish",
+            post(auth::finish_passkey_authentication),
+        )
+        .route("/api/v1/auth/2fa/setup", post(auth::setup_two_factor))
+        .route("/.well-known/jwks.json", get(auth::jwks))
         .merge(
             Router::new()
                 .route("/api/v1/auth/signout", delete(auth::sign_out))
+                .route("/api/v1/auth/2fa", delete(auth::disable_two_factor))
+                .route("/api/v1/auth/2fa/enroll", post(auth::enroll_two_factor))
+                .route("/api/v1/auth/2fa/confirm", post(auth::confirm_two_factor))
+                .route("/api/v1/auth/unlock", post(auth::unlock_sign_in))
+                .route("/api/v1/auth/sessions", get(auth::get_sessions))
+                .route("/api/v1/auth/sessions", delete(auth::revoke_other_sessions))
+                .route(
+                    "/api/v1/auth/sessions/{session_id}",
+                    delete(auth::revoke_session),
+                )
+                .route(
+                    "/api/v1/auth/impersonate/{user_id}",
+                    post(auth::start_impersonation).route_layer(from_fn_with_state(
+                        SUPER_ADMIN_ROLES,
+                        middleware::require_roles,
+                    )),
+                )
+                .route("/api/v1/auth/impersonate", delete(auth::end_impersonation))
+                .route("/api/v1/auth/passkeys", get(auth::get_passkeys))
+                .route(
+                    "/api/v1/auth/passkeys/register/start",
+                    post(auth::start_passkey_registration),
+                )
+                .route(
+                    "/api/v1/auth/passkeys/register/finish",
+                    post(auth::finish_passkey_registration),
+                )
+                .route(
+                    "/api/v1/auth/passkeys/{passkey_id}",
+                    delete(auth::delete_passkey),
+                )
                 .route_layer(from_fn_with_state(
                     Arc::clone(&auth_state),
                     middleware::auth,
@@ -90,9 +295,36 @@ async fn main() {
         .with_state(Arc::clone(&auth_state));
 
     let user_route = Router::new()
-        .route("/api/v1/user", get(user::get_current))
+        .route(
+            "/api/v1/user",
+            get(user::get_current)
+                .patch(user::update_profile)
+                .delete(user::delete_current)
+                // Room for the avatar plus the other multipart fields
+                .layer(DefaultBodyLimit::max(
+                    config.avatar_max_size_kib * 1024 + 64 * 1024,
+                )),
+        )
         .route("/api/v1/user/{user_id}", get(user::get_by_id))
-        .route("/api/v1/user/role", patch(user::add_roles))
+        .route("/api/v1/users", get(user::get_all))
+        .route("/api/v1/user/password", put(user::change_password))
+        .route("/api/v1/user/email", put(user::change_email))
+        .route("/api/v1/user/login-history", get(user::get_login_history))
+        .route("/api/v1/user/export", get(user::export))
+        .route("/api/v1/user/{user_id}/deactivate", post(user::deactivate))
+        .route("/api/v1/user/{user_id}/reactivate", post(user::reactivate))
+        .route(
+            "/api/v1/user/{user_id}/login-history",
+            get(user::get_login_history_by_user_id),
+        )
+        .route(
+            "/api/v1/user/{user_id}/role",
+            patch(user::update_roles).put(user::replace_roles),
+        )
+        .route(
+            "/api/v1/user/{user_id}/role/{role_id}",
+            delete(user::remove_role),
+        )
         .route_layer(from_fn_with_state(
             Arc::clone(&auth_state),
             middleware::auth,
@@ -103,12 +335,39 @@ async fn main() {
         .route("/api/v1/roles", post(role::create))
         .route("/api/v1/roles", get(role::get_all))
         .route("/api/v1/roles/{role_id}", get(role::get_by_id))
+        .route("/api/v1/permissions", get(role::get_all_permissions))
+        .route(
+            "/api/v1/roles/{role_id}/permissions",
+            get(role::get_permissions),
+        )
+        .route(
+            "/api/v1/roles/{role_id}/permissions/{permission}",
+            put(role::grant_permission).delete(role::revoke_permission),
+        )
         .route_layer(from_fn_with_state(
             Arc::clone(&auth_state),
             middleware::auth,
         ))
         .with_state(Arc::clone(&role_state));
 
+    let personal_access_token_route = Router::new()
+        .route("/api/v1/tokens", post(personal_access_token::create))
+        .route("/api/v1/tokens", get(personal_access_token::get_all))
+        .route(
+            "/api/v1/tokens/{token_id}",
+            delete(personal_access_token::revoke),
+        )
+        .route_layer(from_fn_with_state(
+            Arc::clone(&auth_state),
+            middleware::auth,
+        ))
+        .with_state(Arc::clone(&personal_access_token_state));
+
+    // Public so avatars can be shown with a plain image tag
+    let file_route = Router::new()
+        .route("/api/v1/files/{file_id}", get(file::get_public))
+        .with_state(Arc::clone(&file_state));
+
     let allowed_origins: Vec<HeaderValue> = config
         .cors_allowed_origins
         .iter()
@@ -130,18 +389,30 @@ async fn main() {
             header::CONTENT_TYPE,
             header::ACCEPT,
             header::COOKIE,
+            HeaderName::from_static(CSRF_HEADER),
+            HeaderName::from_static(TOKEN_DELIVERY_HEADER),
         ])
+        .expose_headers([HeaderName::from_static(CSRF_HEADER)])
         .allow_credentials(true);
 
     let app = Router::new()
         .merge(auth_route)
         .merge(user_route)
         .merge(role_route)
+        .merge(personal_access_token_route)
+        .merge(file_route)
+        // Lets extractors such as `Device` read the settings whatever the router state is
+        .layer(Extension(Arc::clone(&config)))
         .layer(cors);
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.port))
         .await
         .unwrap();
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
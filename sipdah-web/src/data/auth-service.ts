@@ -27,6 +27,6 @@ export const signOut = async (): Promise<void> => {
 }
 
 export const refresh = async (): Promise<AuthResponse> => {
-  const res = await axios.get<Api<AuthResponse>>('/api/v1/auth/refresh')
+  const res = await axios.post<Api<AuthResponse>>('/api/v1/auth/refresh')
   return res.data.data
 }
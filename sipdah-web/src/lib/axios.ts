@@ -1,5 +1,11 @@
-import defaultAxios, { AxiosError, AxiosRequestConfig, AxiosResponse } from 'axios'
+import defaultAxios, {
+  AxiosError,
+  AxiosRequestConfig,
+  AxiosResponse,
+  InternalAxiosRequestConfig,
+} from 'axios'
 import { BACKEND_BASE_URL } from '@/lib/constants'
+import { getCookie } from '@/lib/cookie'
 import { toast } from 'sonner'
 import { Api } from '@/data/common'
 import { AuthResponse } from '@/data/auth-service'
@@ -11,6 +17,30 @@ const axios = defaultAxios.create({
 
 export default axios
 
+const CSRF_COOKIE = 'csrf_token'
+const CSRF_HEADER = 'x-csrf-token'
+const SAFE_METHODS = ['get', 'head', 'options']
+
+// Scripts cannot read the cookie when the backend runs on another domain, the header is kept too
+let csrfToken: string | null = null
+
+const rememberCsrfToken = (response: AxiosResponse) => {
+  const token = response.headers[CSRF_HEADER]
+  if (typeof token === 'string' && token) {
+    csrfToken = token
+  }
+}
+
+const getCsrfToken = () => getCookie(CSRF_COOKIE) ?? csrfToken
+
+axios.interceptors.request.use((config: InternalAxiosRequestConfig) => {
+  const token = getCsrfToken()
+  if (token && !SAFE_METHODS.includes((config.method ?? 'get').toLowerCase())) {
+    config.headers.set(CSRF_HEADER, token)
+  }
+  return config
+})
+
 export function isAxiosError<ResponseType = unknown>(
   error: unknown
 ): error is AxiosError<ResponseType> {
@@ -37,7 +67,10 @@ const processQueue = (error: unknown | null, token: string | null) => {
 }
 
 axios.interceptors.response.use(
-  (response: AxiosResponse) => response,
+  (response: AxiosResponse) => {
+    rememberCsrfToken(response)
+    return response
+  },
   async (err: unknown) => {
     if (!isAxiosError(err) || !err.response || err.response.status !== 401) {
       return Promise.reject(err)
@@ -68,10 +101,13 @@ axios.interceptors.response.use(
 
     return new Promise((resolve, reject) => {
       defaultAxios
-        .get<Api<AuthResponse>>(`${BACKEND_BASE_URL}/api/v1/auth/refresh`, {
+        .post<Api<AuthResponse>>(`${BACKEND_BASE_URL}/api/v1/auth/refresh`, undefined, {
           withCredentials: true,
+          headers: { [CSRF_HEADER]: getCsrfToken() },
         })
-        .then(({ data }) => {
+        .then((response) => {
+          rememberCsrfToken(response)
+          const { data } = response
           axios.defaults.headers.common['Authorization'] = `Bearer ${data.data.accessToken}`
           originalRequest.headers = {
             ...originalRequest.headers,